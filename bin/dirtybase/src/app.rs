@@ -1,2 +1,4 @@
 pub mod app_setup;
+pub mod migrations;
+pub mod seeders;
 pub mod setup_database;
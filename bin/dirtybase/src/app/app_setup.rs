@@ -1,21 +1,98 @@
-use dirtybase_db::base::schema::SchemaManagerTrait;
-use dirtybase_db::{base, driver::mysql::mysql_schema_manager::MySqlSchemaManager};
-use sqlx::{any::AnyKind, mysql::MySqlPoolOptions, MySql, Pool};
-use std::{str::FromStr, sync::Arc};
+use dirtybase_db::base::{
+    collection::{Collection, SchemaRegistry},
+    manager::SCHEMA_LOCK,
+    migration::{MigrationRegistry, MigrationRunner, MigrationStatus, Rollback},
+    migration_codegen::{generate_migration, timestamped_name},
+    pool_set::{PoolSet, ReplicaStrategy},
+    query_cache::QueryCache,
+    record_defaults::UserContext,
+    schema::SchemaManagerTrait,
+    schema_codegen::generate_rust,
+    schema_diff::SchemaChange,
+    schema_loader::load_schema_dir,
+    seeder::{SeederRegistry, SeederRunner},
+};
+use dirtybase_db::{
+    base,
+    driver::{
+        mysql::mysql_schema_manager::MySqlSchemaManager,
+        sqlite::sqlite_schema_manager::SqliteSchemaManager,
+    },
+};
+use sqlx::{
+    any::AnyKind,
+    mysql::MySqlPoolOptions,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    Executor, MySql, Pool, Sqlite,
+};
+use std::{
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
-use super::setup_database::create_data_tables;
+use super::setup_database::{core_schema, create_data_tables, create_schema_file_tables};
+
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connection: u32,
+    pub query_cache_capacity: usize,
+    pub read_replicas: Vec<String>,
+    pub replica_strategy: ReplicaStrategy,
+    pub read_your_writes: bool,
+    // how long start up and migrations wait for another instance's
+    pub lock_timeout: Duration,
+}
+
+// The pools of the database the url points to
+enum DatabasePools {
+    MySql(Arc<PoolSet<MySql>>),
+    Sqlite(Arc<PoolSet<Sqlite>>),
+}
 
 pub struct Dirtybase {
-    db_pool: Arc<Pool<MySql>>,
+    db_pools: DatabasePools,
     kind: AnyKind,
+    query_cache: Arc<QueryCache>,
+    read_your_writes: bool,
+    registry: Arc<RwLock<SchemaRegistry>>,
+    lock_timeout: Duration,
 }
 
 impl Dirtybase {
-    pub async fn new(db_connection: &str, db_max_connection: u32) -> anyhow::Result<Self> {
-        let kind = AnyKind::from_str(db_connection).unwrap_or(AnyKind::MySql);
+    pub async fn new(config: DatabaseConfig) -> anyhow::Result<Self> {
+        let kind = AnyKind::from_str(&config.url).unwrap_or(AnyKind::MySql);
+
+        let db_pools = match kind {
+            AnyKind::Sqlite => {
+                let mut db_pools =
+                    PoolSet::new(sqlite_connect(&config.url, config.max_connection).await)
+                        .set_strategy(config.replica_strategy);
+                for replica in &config.read_replicas {
+                    db_pools =
+                        db_pools.add_replica(sqlite_connect(replica, config.max_connection).await);
+                }
+                DatabasePools::Sqlite(Arc::new(db_pools))
+            }
+            _ => {
+                let mut db_pools =
+                    PoolSet::new(db_connect(&config.url, config.max_connection).await)
+                        .set_strategy(config.replica_strategy);
+                for replica in &config.read_replicas {
+                    db_pools =
+                        db_pools.add_replica(db_connect(replica, config.max_connection).await);
+                }
+                DatabasePools::MySql(Arc::new(db_pools))
+            }
+        };
+
         let instance = Self {
             kind,
-            db_pool: Arc::new(db_connect(db_connection, db_max_connection).await),
+            db_pools,
+            query_cache: Arc::new(QueryCache::new(config.query_cache_capacity)),
+            read_your_writes: config.read_your_writes,
+            registry: Arc::new(RwLock::new(SchemaRegistry::new())),
+            lock_timeout: config.lock_timeout,
         };
 
         // match instance.kind {
@@ -31,24 +108,198 @@ impl Dirtybase {
     }
 
     pub fn schema_manger(&self) -> base::manager::Manager {
-        // TODO Check the database `kind`
-        // match self.kind {
-        //     _ => base::manager::Manager::new(Box::new(MySqlSchemaManager::instance(
-        //         self.db_pool.clone(),
-        //     ))),
-        // }
+        let schema_manager: Box<dyn SchemaManagerTrait> = match &self.db_pools {
+            DatabasePools::MySql(db_pools) => Box::new(MySqlSchemaManager::new(db_pools.clone())),
+            DatabasePools::Sqlite(db_pools) => Box::new(SqliteSchemaManager::new(db_pools.clone())),
+        };
+
+        base::manager::Manager::new(schema_manager)
+            .with_query_cache(self.query_cache.clone())
+            .with_read_your_writes(self.read_your_writes)
+            .with_lock_timeout(self.lock_timeout)
+            .with_registry(self.registry.clone())
+    }
+
+    /// A manager that blames the records it writes on the user
+    pub fn schema_manager_for(&self, user: UserContext) -> base::manager::Manager {
+        self.schema_manger().with_user(user)
+    }
+
+    /// Creates the core tables and loads the collections. Instances
+    /// starting together take turns through the schema lock. On SQLite that
+    /// lock is process-local, run a single process against the file
+    pub async fn db_setup(&self) -> anyhow::Result<()> {
+        self.schema_manger()
+            .with_lock(
+                SCHEMA_LOCK,
+                self.lock_timeout,
+                create_data_tables(self.schema_manger()),
+            )
+            .await?;
 
-        base::manager::Manager::new(Box::new(MySqlSchemaManager::instance(self.db_pool.clone())))
+        match SchemaRegistry::load(&mut self.schema_manger()).await {
+            Ok(mut registry) => {
+                for table in core_schema().tables() {
+                    registry.register_table(table.clone());
+                }
+                *self.registry.write().unwrap() = registry;
+            }
+            Err(e) => log::error!("could not load the collections: {}", e),
+        }
+
+        Ok(())
+    }
+
+    /// The collections defined at runtime, and the other tables
+    pub fn registry(&self) -> Arc<RwLock<SchemaRegistry>> {
+        self.registry.clone()
     }
 
-    pub async fn db_setup(&self) {
-        create_data_tables(self.schema_manger()).await;
+    /// Creates or updates the collection's table and definition.
+    /// Removed fields are only dropped when `allow_destructive` is set
+    pub async fn save_collection(
+        &self,
+        collection: Collection,
+        allow_destructive: bool,
+    ) -> anyhow::Result<Vec<SchemaChange>> {
+        if self.is_application_table(&collection.name) {
+            anyhow::bail!("the table `{}` is not a collection", collection.name);
+        }
+
+        let skipped = self
+            .schema_manger()
+            .save_collection(&collection, allow_destructive)
+            .await?;
+        self.registry.write().unwrap().register(collection);
+        Ok(skipped)
+    }
+
+    // The core tables and those of the schema files, even before they exist
+    fn is_application_table(&self, name: &str) -> bool {
+        core_schema().find(name).is_some() || self.registry.read().unwrap().is_schema_table(name)
+    }
+
+    /// Applies the pending migrations. Returns their names
+    pub async fn migrate(&self, registry: &MigrationRegistry) -> anyhow::Result<Vec<String>> {
+        let mut manager = self.schema_manger();
+        MigrationRunner::new(&mut manager, registry)
+            .with_lock_timeout(self.lock_timeout)
+            .migrate()
+            .await
+    }
+
+    pub async fn migration_status(
+        &self,
+        registry: &MigrationRegistry,
+    ) -> anyhow::Result<Vec<MigrationStatus>> {
+        let mut manager = self.schema_manger();
+        MigrationRunner::new(&mut manager, registry).status().await
+    }
+
+    /// Rolls back the latest migrations. Returns their names
+    pub async fn rollback(
+        &self,
+        registry: &MigrationRegistry,
+        rollback: Rollback,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut manager = self.schema_manger();
+        MigrationRunner::new(&mut manager, registry)
+            .with_lock_timeout(self.lock_timeout)
+            .rollback(rollback)
+            .await
+    }
+
+    /// Rust code that recreates every table in the database
+    pub async fn generate_schema(&self) -> anyhow::Result<String> {
+        let tables = self.schema_manger().fetch_all_tables().await?;
+        Ok(generate_rust(&tables))
+    }
+
+    /// Runs the seeders that did not run yet, or every seeder with `force`.
+    /// Returns their names
+    pub async fn seed(
+        &self,
+        registry: &SeederRegistry,
+        force: bool,
+    ) -> anyhow::Result<Vec<String>> {
+        let manager = self.schema_manger();
+        SeederRunner::new(&manager, registry).run(force).await
+    }
+
+    /// A migration from the database to the core tables and the tables in
+    /// the schema directory. Returns its name and source, `None` when
+    /// the database is up to date
+    pub async fn generate_migration(
+        &self,
+        description: &str,
+        schema_dir: Option<&str>,
+    ) -> anyhow::Result<Option<(String, String)>> {
+        let mut desired = core_schema();
+        if let Some(dir) = schema_dir {
+            for table in load_schema_dir(dir)? {
+                desired.add(table);
+            }
+        }
+
+        let live = self.schema_manger().fetch_all_tables().await?;
+        let name = timestamped_name(description);
+        Ok(generate_migration(&name, &desired, &live).map(|source| (name, source)))
+    }
+
+    /// Creates or updates the tables defined in a directory of schema files
+    pub async fn load_schema_dir(&self, dir: &str) -> anyhow::Result<()> {
+        for table in load_schema_dir(dir)? {
+            self.registry.write().unwrap().register_table(table);
+        }
+
+        self.schema_manger()
+            .with_lock(
+                SCHEMA_LOCK,
+                self.lock_timeout,
+                create_schema_file_tables(self.schema_manger(), dir),
+            )
+            .await
+    }
+}
+
+/// Connects to a SQLite database, creating its file when missing.
+/// An in-memory database lives as long as its single connection
+pub async fn sqlite_connect(conn: &str, max_connection: u32) -> Pool<Sqlite> {
+    let options = match SqliteConnectOptions::from_str(conn) {
+        Ok(options) => options.create_if_missing(true),
+        Err(e) => {
+            panic!("could not connect to the database: {:#?}", e);
+        }
+    };
+
+    let pool_options = if conn.contains(":memory:") || conn.contains("mode=memory") {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+    } else {
+        SqlitePoolOptions::new().max_connections(max_connection)
+    };
+
+    match pool_options.connect_with(options).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            panic!("could not connect to the database: {:#?}", e);
+        }
     }
 }
 
+/// Connects to a MySQL database. Sessions run in UTC, so `CURRENT_TIMESTAMP`
+/// defaults agree with the timestamps the application writes
 pub async fn db_connect(conn: &str, max_connection: u32) -> Pool<MySql> {
     match MySqlPoolOptions::new()
         .max_connections(max_connection)
+        .after_connect(|conn, _| {
+            Box::pin(async move {
+                conn.execute("SET time_zone = '+00:00'").await?;
+                Ok(())
+            })
+        })
         .connect(conn)
         .await
     {
@@ -0,0 +1,7 @@
+use dirtybase_db::base::migration::MigrationRegistry;
+
+/// The application's migrations, in the order they are applied.
+/// Register new migrations at the end
+pub fn migrations() -> MigrationRegistry {
+    MigrationRegistry::new()
+}
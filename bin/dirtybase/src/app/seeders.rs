@@ -0,0 +1,36 @@
+use super::setup_database::core_schema;
+use async_trait::async_trait;
+use dirtybase_db::base::{
+    factory::Factory,
+    manager::Manager,
+    seeder::{Seeder, SeederRegistry},
+    user_table::user_table_name,
+};
+
+/// The application's seeders, in the order they run
+pub fn seeders() -> SeederRegistry {
+    let mut registry = SeederRegistry::new();
+    registry
+        .register(Box::new(FirstUserSeeder))
+        .expect("seeder names are unique");
+    registry
+}
+
+// The user to sign in with on a new installation
+struct FirstUserSeeder;
+
+#[async_trait(?Send)]
+impl Seeder for FirstUserSeeder {
+    fn name(&self) -> &str {
+        "first_user"
+    }
+
+    async fn run(&self, manager: &Manager) -> anyhow::Result<()> {
+        let schema = core_schema();
+        Factory::new(&user_table_name(), &schema)?
+            .set("username", "first_user")
+            .create(manager)
+            .await?;
+        Ok(())
+    }
+}
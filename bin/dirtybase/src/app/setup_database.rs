@@ -1,98 +1,117 @@
-use dirtybase_db::base::{manager::Manager, user_table::setup_users_table};
+use dirtybase_db::base::{
+    collection::collection_tables,
+    desired_schema::DesiredSchema,
+    manager::Manager,
+    migration::migration_table,
+    pivot::PivotTable,
+    schema_diff::SchemaChange,
+    schema_loader::apply_schema_dir,
+    seeder::seeder_table,
+    user_table::{user_table_name, users_table},
+};
 
-// The table that will hold migration information
-async fn setup_migration_table(manager: &Manager) {
-    let name = "_core_migration";
-    manager
-        .create(name, |table| {
-            // id
-            table.id(None);
-            // migration name
-            table.string("name");
-            // created at
-            table.created_at();
-            // deleted at
-            table.updated_at();
-        })
-        .await;
+// Skipped destructive changes are warned about, a failure is returned
+fn report(name: &str, result: anyhow::Result<Vec<SchemaChange>>) -> anyhow::Result<()> {
+    let skipped = result.map_err(|e| anyhow::anyhow!("could not converge `{}`: {}", name, e))?;
+    for change in skipped {
+        log::warn!(
+            "skipped destructive change on `{}`: {:?}",
+            name,
+            change.operation
+        );
+    }
+    Ok(())
 }
 
 // The table that will hold file metadata
-async fn setup_file_metadata_table(manager: &Manager) {
-    let name = "_core_file_meta";
-    manager
-        .create(name, |table| {
-            // internal_id
-            // id
-            table.id_set();
-            // external_id
-            table.ulid("external_id").set_is_nullable(false);
-            // meta
-            table.json("meta");
-            // timestamp
-            table.timestamps();
-        })
-        .await;
+fn define_file_metadata_table(schema: &mut DesiredSchema) {
+    schema.table("_core_file_meta", |table| {
+        // internal_id
+        // id
+        table.id_set();
+        // external_id
+        table.ulid("external_id").set_is_nullable(false);
+        // meta
+        table.json("meta");
+        // timestamp
+        table.timestamps();
+    });
 }
 
 // The table that will hold company's tenets
-async fn setup_company_table(manager: &Manager) {
-    let name = "_core_company";
-    manager
-        .create(name, |table| {
-            // internal_id
-            // id
-            table.id_set();
-            // name
-            table.string("name");
-            // description
-            table.sized_string("description", 512);
-            // timestamp
-            table.timestamps();
-        })
-        .await;
+fn define_company_table(schema: &mut DesiredSchema) {
+    schema.table("_core_company", |table| {
+        // internal_id
+        // id
+        table.id_set();
+        // name
+        table.string("name");
+        // description
+        table.sized_string("description", 512);
+        // timestamp
+        table.timestamps();
+    });
 }
 
 // The global roles table
-async fn setup_roles_table(manager: &Manager) {
-    let name = "_app_core_role";
-    manager
-        .create(name, |table| {
-            // internal_id
-            // id
-            table.id_set();
-            // company_id
-            table
-                .ulid("company_id")
-                .set_is_nullable(false)
-                .references_without_cascade_delete("_core_company", "id");
-            // name
-            table.string("name");
-            // blame
-            table.blame();
-            // timestamps
-            table.timestamps();
-        })
-        .await;
+fn define_roles_table(schema: &mut DesiredSchema) {
+    schema.table("_app_core_role", |table| {
+        // internal_id
+        // id
+        table.id_set();
+        // company_id
+        table
+            .ulid("company_id")
+            .set_is_nullable(false)
+            .references_without_cascade_delete("_core_company", "id");
+        // name
+        table.string("name");
+        // blame
+        table.blame();
+        // timestamps
+        table.timestamps();
+    });
 }
 
 // A user role
-async fn setup_role_users_table(_manager: &Manager) {
-    let _name = "_core_role_user";
+fn define_role_users_table(schema: &mut DesiredSchema) {
+    let name = "_core_role_user";
+    let pivot = PivotTable::new(name, "_app_core_role", &user_table_name(), 0);
+    schema.table(name, |table| {
+        // owner_id, related_id and created_at
+        pivot.build(table);
+    });
 }
 
-async fn setup_applications_table(_manager: &Manager) {}
+// The core tables, in the order they are created
+pub(crate) fn core_schema() -> DesiredSchema {
+    let mut schema = DesiredSchema::new();
+    // the table that will hold migration information
+    schema.add(migration_table());
+    // the seeders that ran
+    schema.add(seeder_table());
+    define_file_metadata_table(&mut schema);
+    define_company_table(&mut schema);
+    schema.add(users_table());
+    define_roles_table(&mut schema);
+    define_role_users_table(&mut schema);
+    // the tables that will contain the "collections" definitions
+    for table in collection_tables() {
+        schema.add(table);
+    }
+    schema
+}
 
-// The table that will contain the "collections" definitions
-async fn setup_schema_table(_manager: &Manager) {}
+// Destructive drift is reported, never applied on start up.
+// The server must not start on a schema that failed to converge
+pub(crate) async fn create_data_tables(manager: Manager) -> anyhow::Result<()> {
+    for (name, result) in core_schema().apply(&manager, false).await {
+        report(&name, result)?;
+    }
+    Ok(())
+}
 
-pub(crate) async fn create_data_tables(manager: Manager) {
-    setup_migration_table(&manager).await;
-    setup_file_metadata_table(&manager).await;
-    setup_company_table(&manager).await;
-    setup_users_table(&manager).await;
-    setup_roles_table(&manager).await;
-    setup_role_users_table(&manager).await;
-    setup_applications_table(&manager).await;
-    setup_schema_table(&manager).await;
+// Tables defined in JSON or YAML schema files
+pub(crate) async fn create_schema_file_tables(manager: Manager, dir: &str) -> anyhow::Result<()> {
+    report(dir, apply_schema_dir(&manager, dir).await)
 }
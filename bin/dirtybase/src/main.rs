@@ -1,17 +1,69 @@
 pub mod app;
 pub mod http;
 
-use std::env;
+use std::{env, time::Duration};
 
 use actix_web::{get, web, App, HttpResponse, HttpServer, Responder};
-use app::app_setup::Dirtybase;
+use app::{
+    app_setup::{DatabaseConfig, Dirtybase},
+    migrations::migrations,
+    seeders::seeders,
+};
+use clap::{Parser, Subcommand};
+use dirtybase_db::base::{migration::Rollback, pool_set::ReplicaStrategy};
 use dotenv::dotenv;
 use log::{error, info};
-use pretty_env_logger;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the server. The default
+    Serve,
+    /// Print Rust code that recreates every table in the database
+    GenerateSchema {
+        /// Write the code to this file instead
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Write a migration for the differences between the defined tables and the database
+    MakeMigration {
+        /// What the migration does, such as `add posts`
+        description: String,
+        /// The directory the migration file is written to
+        #[arg(short, long, default_value = "src/app/migrations")]
+        dir: String,
+    },
+    /// Apply the pending migrations
+    Migrate,
+    /// Run the seeders that did not run yet
+    Seed {
+        /// Run every seeder again
+        #[arg(long)]
+        force: bool,
+    },
+    /// List the migrations and the batch each was applied in
+    MigrationStatus,
+    /// Roll back the last batch of migrations
+    Rollback {
+        /// Roll back this many migrations instead
+        #[arg(long, conflicts_with = "batches")]
+        steps: Option<usize>,
+        /// Roll back this many batches
+        #[arg(long)]
+        batches: Option<usize>,
+    },
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     pretty_env_logger::init();
+    let cli = Cli::parse();
 
     if let Err(e) = dotenv() {
         error!("could not load .env file: {:#}", e);
@@ -30,10 +82,148 @@ async fn main() -> std::io::Result<()> {
         5
     };
 
-    let app = Dirtybase::new(&db_connection, max_connection)
+    let query_cache_capacity: usize =
+        if let Ok(capacity) = env::var("DTY_DATABASE_QUERY_CACHE_CAPACITY") {
+            capacity.parse().unwrap_or(1000)
+        } else {
+            1000
+        };
+
+    let read_replicas: Vec<String> = if let Ok(replicas) = env::var("DTY_DATABASE_READ_REPLICAS") {
+        replicas
+            .split(',')
+            .map(|url| url.trim().to_owned())
+            .filter(|url| !url.is_empty())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let replica_strategy = if let Ok(strategy) = env::var("DTY_DATABASE_REPLICA_STRATEGY") {
+        strategy.parse().unwrap_or(ReplicaStrategy::RoundRobin)
+    } else {
+        ReplicaStrategy::RoundRobin
+    };
+
+    let read_your_writes: bool = if let Ok(sticky) = env::var("DTY_DATABASE_READ_YOUR_WRITES") {
+        sticky.parse().unwrap_or(true)
+    } else {
+        true
+    };
+
+    let lock_timeout: u64 = if let Ok(seconds) = env::var("DTY_DATABASE_LOCK_TIMEOUT") {
+        seconds.parse().unwrap_or(60)
+    } else {
+        60
+    };
+
+    let app = Dirtybase::new(DatabaseConfig {
+        url: db_connection.clone(),
+        max_connection,
+        query_cache_capacity,
+        read_replicas,
+        replica_strategy,
+        read_your_writes,
+        lock_timeout: Duration::from_secs(lock_timeout),
+    })
+    .await
+    .unwrap();
+
+    if let Some(Command::GenerateSchema { output }) = cli.command {
+        let source = app.generate_schema().await.unwrap();
+        match output {
+            Some(path) => std::fs::write(path, source)?,
+            None => print!("{}", source),
+        }
+        return Ok(());
+    }
+
+    let schema_dir = env::var("DTY_SCHEMA_DIR")
+        .ok()
+        .filter(|dir| !dir.is_empty());
+
+    if let Some(Command::MakeMigration { description, dir }) = &cli.command {
+        match app
+            .generate_migration(description, schema_dir.as_deref())
+            .await
+            .map_err(|e| command_error("could not generate the migration", e))?
+        {
+            Some((name, source)) => {
+                let path = std::path::Path::new(dir).join(format!("m{}.rs", name));
+                std::fs::create_dir_all(dir)?;
+                std::fs::write(&path, source)?;
+                println!("created {}, register it in app::migrations", path.display());
+            }
+            None => println!("the database is up to date"),
+        }
+        return Ok(());
+    }
+
+    app.db_setup()
         .await
-        .unwrap();
-    app.db_setup().await;
+        .map_err(|e| command_error("could not set up the database", e))?;
+
+    match cli.command {
+        Some(Command::Migrate) => {
+            let names = app
+                .migrate(&migrations())
+                .await
+                .map_err(|e| command_error("could not migrate", e))?;
+            for name in names {
+                println!("migrated: {}", name);
+            }
+            return Ok(());
+        }
+        Some(Command::Seed { force }) => {
+            let names = app
+                .seed(&seeders(), force)
+                .await
+                .map_err(|e| command_error("could not seed", e))?;
+            for name in names {
+                println!("seeded: {}", name);
+            }
+            return Ok(());
+        }
+        Some(Command::MigrationStatus) => {
+            let statuses = app
+                .migration_status(&migrations())
+                .await
+                .map_err(|e| command_error("could not read the migrations", e))?;
+            for status in statuses {
+                let mut state = match (status.batch, status.is_missing) {
+                    (Some(batch), false) => format!("batch {}", batch),
+                    (Some(batch), true) => format!("batch {}, not registered", batch),
+                    (None, _) => "pending".to_owned(),
+                };
+                if status.is_incomplete {
+                    state.push_str(", failed part way through");
+                }
+                println!("{} ({})", status.name, state);
+            }
+            return Ok(());
+        }
+        Some(Command::Rollback { steps, batches }) => {
+            let rollback = match steps {
+                Some(steps) => Rollback::Steps(steps),
+                None => Rollback::Batches(batches.unwrap_or(1)),
+            };
+            let names = app
+                .rollback(&migrations(), rollback)
+                .await
+                .map_err(|e| command_error("could not roll back", e))?;
+            for name in names {
+                println!("rolled back: {}", name);
+            }
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    if let Some(dir) = &schema_dir {
+        app.load_schema_dir(dir)
+            .await
+            .map_err(|e| command_error("could not load the schema files", e))?;
+    }
 
     let data = web::Data::new(app);
     let port: u16 = if let Ok(p) = env::var("DTY_WEB_PORT") {
@@ -56,6 +246,12 @@ async fn main() -> std::io::Result<()> {
     .await
 }
 
+// Logs the failure and ends the command with it
+fn command_error(context: &str, e: anyhow::Error) -> std::io::Error {
+    error!("{}: {:#}", context, e);
+    std::io::Error::other(e.to_string())
+}
+
 #[get("/")]
 async fn hello() -> impl Responder {
     HttpResponse::Ok().body("Hello world!")
@@ -66,7 +262,9 @@ async fn serve_users(app: web::Data<Dirtybase>) -> impl Responder {
     let mut manager = app.schema_manger();
     let result = manager
         .table("_core_users", |query| {
-            query.is_in("internal_id", vec![2, 1, 40]);
+            query
+                .is_in("internal_id", vec![2, 1, 40])
+                .cache(Duration::from_secs(30));
         })
         .fetch_all_as_json()
         .await;
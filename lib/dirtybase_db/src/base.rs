@@ -1,14 +1,33 @@
+pub mod check;
+pub mod collection;
 pub mod column;
+pub mod desired_schema;
+pub mod factory;
+pub mod filter;
+pub mod foreign_key;
 pub mod helper;
+pub mod index;
 pub mod join_builder;
 pub mod manager;
+pub mod migration;
+pub mod migration_codegen;
+pub mod pivot;
+pub mod pool_set;
 pub mod query;
+pub mod query_cache;
 pub mod query_conditions;
 pub mod query_join_types;
 pub mod query_operators;
+pub mod query_serde;
 pub mod query_values;
+pub mod record_defaults;
 pub mod save;
 pub mod schema;
+pub mod schema_codegen;
+pub mod schema_diff;
+pub mod schema_loader;
+pub mod seeder;
 pub mod table;
 pub mod user_table;
+pub mod validation;
 pub mod where_join_operators;
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// A named CHECK constraint. Rows where the expression is false are rejected
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BaseCheck {
+    pub name: String,
+    pub expression: String,
+}
+
+impl BaseCheck {
+    pub fn new(name: &str, expression: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            expression: expression.to_owned(),
+        }
+    }
+
+    /// `<table>_<column>_check`
+    pub fn generated_name(table: &str, column: &str) -> String {
+        format!("{}_{}_check", table, column)
+    }
+}
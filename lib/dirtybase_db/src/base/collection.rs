@@ -0,0 +1,339 @@
+use super::{
+    column::BaseColumn, filter::TableLookup, helper::is_valid_name, manager::Manager,
+    schema_diff::SchemaChange, table::BaseTable,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const COLLECTION_TABLE: &str = "_core_collection";
+pub const COLLECTION_FIELD_TABLE: &str = "_core_collection_field";
+
+// Every collection's table has these columns
+const RESERVED_FIELDS: [&str; 4] = ["internal_id", "id", "created_at", "updated_at"];
+
+/// A table defined at runtime. Its definition is stored in the meta tables
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Collection {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub fields: Vec<BaseColumn>,
+}
+
+impl Collection {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            label: None,
+            description: None,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Adds the field. Redefining an existing field replaces it
+    pub fn field(&mut self, column: BaseColumn) -> &mut BaseColumn {
+        match self.fields.iter().position(|f| f.name == column.name) {
+            Some(position) => {
+                self.fields[position] = column;
+                &mut self.fields[position]
+            }
+            None => {
+                self.fields.push(column);
+                self.fields.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// The table that holds the collection's records
+    pub fn to_table(&self) -> BaseTable {
+        let mut table = BaseTable::new(&self.name);
+        table.id_set();
+        for field in &self.fields {
+            table.add_column(field.clone());
+        }
+        table.timestamps();
+        if let Some(label) = &self.label {
+            table.set_comment(label);
+        }
+        table
+    }
+
+    /// Core tables and the columns every collection has can not be redefined.
+    /// Collections are defined at runtime, so their fields can not hold
+    /// SQL expressions and every name they refer to must be an identifier
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !is_valid_name(&self.name) || self.name.starts_with("_core") {
+            anyhow::bail!("invalid collection name `{}`", self.name);
+        }
+
+        for field in &self.fields {
+            if !is_valid_name(&field.name) {
+                anyhow::bail!("invalid field name `{}` in `{}`", field.name, self.name);
+            }
+            if RESERVED_FIELDS.contains(&field.name.as_str()) {
+                anyhow::bail!("`{}` is reserved in `{}`", field.name, self.name);
+            }
+            if field.generated.is_some() || field.check.is_some() {
+                anyhow::bail!(
+                    "`{}` in `{}` can not be generated or checked",
+                    field.name,
+                    self.name
+                );
+            }
+            if let Some(name) = field
+                .referenced_names()
+                .into_iter()
+                .find(|name| !is_valid_name(name))
+            {
+                anyhow::bail!(
+                    "invalid name `{}` in field `{}` of `{}`",
+                    name,
+                    field.name,
+                    self.name
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The meta tables that hold the collections' definitions
+pub fn collection_tables() -> Vec<BaseTable> {
+    let mut collections = BaseTable::new(COLLECTION_TABLE);
+    collections.id_set();
+    collections.string("name").set_is_unique(true);
+    collections.string("label").set_is_nullable(true);
+    collections
+        .sized_string("description", 512)
+        .set_is_nullable(true);
+    collections.timestamps();
+
+    let mut fields = BaseTable::new(COLLECTION_FIELD_TABLE);
+    fields.id_set();
+    fields
+        .ulid("collection_id")
+        .references_with_cascade_delete(COLLECTION_TABLE, "id");
+    fields.string("name");
+    fields.integer("position");
+    // the serialized column definition
+    fields.json("definition");
+    fields.timestamps();
+    fields.unique("collection_field_name", &["collection_id", "name"]);
+
+    vec![collections, fields]
+}
+
+/// Creates the meta tables that hold the collections' definitions.
+/// Returns the destructive changes that were skipped
+pub async fn setup_collection_tables(manager: &Manager) -> anyhow::Result<Vec<SchemaChange>> {
+    let mut skipped = Vec::new();
+    for table in collection_tables() {
+        skipped.extend(manager.converge_table(table, false).await?);
+    }
+
+    Ok(skipped)
+}
+
+/// The collections currently defined, keyed by name. Rebuilt from the
+/// meta tables on start up and kept up to date as collections are saved.
+/// The tables defined in code or schema files are registered next to them.
+/// The live definitions writes fill their defaults from are cached here
+/// until the schema changes
+#[derive(Debug, Default)]
+pub struct SchemaRegistry {
+    collections: HashMap<String, Collection>,
+    tables: HashMap<String, BaseTable>,
+    live: HashMap<String, BaseTable>,
+}
+
+impl SchemaRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry of every collection stored in the meta tables
+    pub async fn load(manager: &mut Manager) -> anyhow::Result<Self> {
+        let mut registry = Self::new();
+        for collection in manager.fetch_collections().await? {
+            registry.register(collection);
+        }
+        Ok(registry)
+    }
+
+    pub fn register(&mut self, collection: Collection) {
+        self.tables
+            .insert(collection.name.clone(), collection.to_table());
+        self.collections.insert(collection.name.clone(), collection);
+    }
+
+    /// A table that is not a collection. Its records are validated against it
+    pub fn register_table(&mut self, table: BaseTable) {
+        self.tables.insert(table.name.clone(), table);
+    }
+
+    /// Registered with `register_table` rather than as a collection
+    pub fn is_schema_table(&self, name: &str) -> bool {
+        self.tables.contains_key(name) && !self.collections.contains_key(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Collection> {
+        self.tables.remove(name);
+        self.collections.remove(name)
+    }
+
+    /// The cached definition of the table as it exists in the database
+    pub fn live_table(&self, name: &str) -> Option<&BaseTable> {
+        self.live.get(name)
+    }
+
+    pub fn cache_live_table(&mut self, table: BaseTable) {
+        self.live.insert(table.name.clone(), table);
+    }
+
+    /// Drops the cached live definitions, for when the schema changed
+    pub fn forget_live_tables(&mut self) {
+        self.live.clear();
+    }
+
+    pub fn collection(&self, name: &str) -> Option<&Collection> {
+        self.collections.get(name)
+    }
+
+    pub fn collections(&self) -> impl Iterator<Item = &Collection> {
+        self.collections.values()
+    }
+}
+
+impl TableLookup for SchemaRegistry {
+    fn find_table(&self, name: &str) -> Option<&BaseTable> {
+        self.tables.get(name)
+    }
+}
+
+/// Rebuilds the collections from the rows of the meta tables.
+/// Fields are ordered by their position
+pub(crate) fn collections_from_rows(
+    collections: &[serde_json::Value],
+    fields: &[serde_json::Value],
+) -> anyhow::Result<Vec<Collection>> {
+    let text = |row: &serde_json::Value, key: &str| {
+        row.get(key).and_then(|v| v.as_str()).map(|v| v.to_owned())
+    };
+
+    let mut result = Vec::new();
+    for row in collections {
+        let id = text(row, "id");
+        let mut collection = Collection::new(&text(row, "name").unwrap_or_default());
+        collection.label = text(row, "label");
+        collection.description = text(row, "description");
+
+        let mut owned: Vec<(i64, BaseColumn)> = Vec::new();
+        for field in fields.iter().filter(|f| text(f, "collection_id") == id) {
+            let definition = match field.get("definition") {
+                // some servers return JSON columns as text
+                Some(serde_json::Value::String(json)) => serde_json::from_str(json)?,
+                Some(value) => serde_json::from_value(value.clone())?,
+                None => anyhow::bail!("field without a definition in `{}`", collection.name),
+            };
+            let position = field.get("position").and_then(|p| p.as_i64());
+            owned.push((position.unwrap_or_default(), definition));
+        }
+        owned.sort_by_key(|(position, _)| *position);
+        collection.fields = owned.into_iter().map(|(_, field)| field).collect();
+
+        result.push(collection);
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::column::ColumnType;
+    use serde_json::json;
+
+    #[test]
+    fn collections_are_rebuilt_from_meta_rows() {
+        let mut title = BaseColumn::new("title", ColumnType::String(100));
+        title.set_comment("Title");
+        let body = BaseColumn::new("body", ColumnType::Text);
+
+        let collections = vec![json!({"id": "c1", "name": "articles", "label": "Articles"})];
+        let fields = vec![
+            json!({"collection_id": "c1", "position": 1, "definition": serde_json::to_string(&body).unwrap()}),
+            json!({"collection_id": "c1", "position": 0, "definition": serde_json::to_value(&title).unwrap()}),
+            json!({"collection_id": "c2", "position": 0, "definition": serde_json::to_value(&body).unwrap()}),
+        ];
+
+        let collection = collections_from_rows(&collections, &fields)
+            .unwrap()
+            .remove(0);
+        assert_eq!(collection.fields, vec![title, body]);
+
+        let mut registry = SchemaRegistry::new();
+        registry.register(collection);
+        let table = registry.find_table("articles").unwrap();
+        assert_eq!(table.comment.as_deref(), Some("Articles"));
+        assert_eq!(
+            table
+                .columns()
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect::<Vec<&str>>(),
+            vec![
+                "internal_id",
+                "id",
+                "title",
+                "body",
+                "created_at",
+                "updated_at"
+            ]
+        );
+    }
+
+    #[test]
+    fn reserved_names_are_rejected() {
+        let mut collection = Collection::new("articles");
+        collection.field(BaseColumn::new("id", ColumnType::Integer));
+        assert!(collection.validate().is_err());
+        assert!(Collection::new("_core_users").validate().is_err());
+    }
+
+    #[test]
+    fn fields_can_not_inject_sql() {
+        let mut generated = Collection::new("articles");
+        generated
+            .field(BaseColumn::new("slug", ColumnType::String(100)))
+            .set_virtual_as("1); DROP TABLE users; --");
+        assert!(generated.validate().is_err());
+
+        let mut checked = Collection::new("articles");
+        checked
+            .field(BaseColumn::new("views", ColumnType::Integer))
+            .set_check("views >= 0");
+        assert!(checked.validate().is_err());
+
+        let mut related = Collection::new("articles");
+        related
+            .field(BaseColumn::new("author", ColumnType::Char(26)))
+            .references_with_cascade_delete("users` (id); --", "id");
+        assert!(related.validate().is_err());
+
+        let mut collated = Collection::new("articles");
+        collated
+            .field(BaseColumn::new("title", ColumnType::String(100)))
+            .set_collation("utf8mb4_bin' --");
+        assert!(collated.validate().is_err());
+
+        let mut valid = Collection::new("articles");
+        valid
+            .field(BaseColumn::new("author", ColumnType::Char(26)))
+            .references_with_cascade_delete("users", "id");
+        assert!(valid.validate().is_ok());
+    }
+}
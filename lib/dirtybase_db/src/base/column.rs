@@ -1,31 +1,98 @@
-#[derive(Debug)]
+use super::{
+    foreign_key::{BaseForeignKey, ForeignKeyAction},
+    validation::ValidationRule,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BaseColumn {
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub new_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub after: Option<String>,
+    #[serde(rename = "type")]
     pub column_type: ColumnType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub default: Option<ColumnDefault>,
+    #[serde(default)]
     pub is_unique: bool,
+    // columns are not nullable unless stated, as with the builders
+    #[serde(default = "not_nullable")]
     pub is_nullable: Option<bool>,
+    #[serde(default)]
+    pub is_unsigned: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub relationship: Option<ForeignKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pivot_table: Option<String>,
+    // a human label, shown by admin UIs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    // overrides the table's collation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generated: Option<GeneratedColumn>,
+    // a CHECK constraint on the column's values
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check: Option<String>,
+    // checked before a record is written, not by the database
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<ValidationRule>,
+}
+
+fn not_nullable() -> Option<bool> {
+    Some(false)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ForeignKey {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
     table: String,
     column: String,
-    cascade_delete: bool,
+    #[serde(default)]
+    on_delete: ForeignKeyAction,
+    #[serde(default)]
+    on_update: ForeignKeyAction,
 }
 
 impl ForeignKey {
     pub fn new(table: &str, column: &str, cascade_delete: bool) -> Self {
         Self {
+            name: None,
             table: table.to_owned(),
             column: column.to_owned(),
-            cascade_delete,
+            on_delete: if cascade_delete {
+                ForeignKeyAction::Cascade
+            } else {
+                ForeignKeyAction::NoAction
+            },
+            on_update: ForeignKeyAction::NoAction,
         }
     }
 
+    /// The constraint name of the foreign key
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    pub fn with_on_delete(mut self, action: ForeignKeyAction) -> Self {
+        self.on_delete = action;
+        self
+    }
+
+    pub fn with_on_update(mut self, action: ForeignKeyAction) -> Self {
+        self.on_update = action;
+        self
+    }
+
+    pub fn name(&self) -> Option<&String> {
+        self.name.as_ref()
+    }
+
     pub fn table(&self) -> String {
         self.table.clone()
     }
@@ -33,18 +100,57 @@ impl ForeignKey {
     pub fn column(&self) -> String {
         self.column.clone()
     }
+
     pub fn cascade_delete(&self) -> bool {
-        self.cascade_delete
+        self.on_delete == ForeignKeyAction::Cascade
+    }
+
+    pub fn on_delete(&self) -> ForeignKeyAction {
+        self.on_delete
+    }
+
+    pub fn on_update(&self) -> ForeignKeyAction {
+        self.on_update
+    }
+
+    /// The table level constraint of this key on the column
+    pub fn constraint(&self, column: &str) -> BaseForeignKey {
+        BaseForeignKey {
+            name: self.name.clone(),
+            columns: vec![column.to_owned()],
+            table: self.table.clone(),
+            references: vec![self.column.clone()],
+            on_delete: self.on_delete,
+            on_update: self.on_update,
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GeneratedStorage {
+    // computed when read
+    Virtual,
+    // computed when written and stored with the row
+    Stored,
+}
+
+/// A column whose value is computed from an expression on the other columns
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeneratedColumn {
+    pub expression: String,
+    pub storage: GeneratedStorage,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum RelationType {
     Single,
     Multiple(isize),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ColumnDefault {
     Custom(String),
     EmptyString,
@@ -57,27 +163,83 @@ pub enum ColumnDefault {
     Ulid,
 }
 
-#[derive(Debug)]
+impl ColumnDefault {
+    /// ULIDs have no database function, they are only filled in
+    /// by the application when a record is inserted
+    pub fn is_database_default(&self) -> bool {
+        !matches!(self, Self::Ulid)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ColumnType {
     AutoIncrementId,
+    Binary(usize),
+    Blob,
     Boolean,
     Char(usize),
     Date,
+    Datetime,
+    Decimal {
+        precision: usize,
+        scale: usize,
+    },
+    Enum(Vec<String>),
     File(RelationType),
     Float,
     Integer,
     Json,
+    MediumInteger,
     Number,
     Relation {
         relation_type: RelationType,
         table_name: String,
     },
     Select(RelationType),
+    SmallInteger,
     String(usize),
     Text,
+    Time,
+    // stored in UTC and converted to the connection's time zone
+    Timestamp,
+    TinyInteger,
     Uuid,
 }
 
+impl ColumnType {
+    /// The plain type the column is stored as. Types that only add meaning,
+    /// such as a single relation holding a ULID, map onto their storage
+    pub fn storage_type(&self) -> ColumnType {
+        match self {
+            Self::File(RelationType::Single) => Self::Char(26),
+            Self::File(RelationType::Multiple(_)) => Self::Json,
+            Self::Relation {
+                relation_type: RelationType::Single,
+                ..
+            } => Self::Char(26),
+            Self::Select(RelationType::Single) => Self::String(255),
+            Self::Select(RelationType::Multiple(_)) => Self::Json,
+            Self::Uuid => Self::Char(36),
+            other => other.clone(),
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Self::AutoIncrementId
+                | Self::Decimal { .. }
+                | Self::Float
+                | Self::Integer
+                | Self::MediumInteger
+                | Self::Number
+                | Self::SmallInteger
+                | Self::TinyInteger
+        )
+    }
+}
+
 impl BaseColumn {
     pub fn new(name: &str, column_type: ColumnType) -> Self {
         Self {
@@ -88,10 +250,23 @@ impl BaseColumn {
             after: None,
             is_unique: false,
             is_nullable: Some(false),
+            is_unsigned: false,
             relationship: None,
+            pivot_table: None,
+            comment: None,
+            collation: None,
+            generated: None,
+            check: None,
+            rules: Vec::new(),
         }
     }
 
+    /// Rename an existing column
+    pub fn rename(&mut self, new_name: &str) -> &mut Self {
+        self.new_name = Some(new_name.to_owned());
+        self
+    }
+
     pub fn set_type(&mut self, t: ColumnType) -> &mut Self {
         self.column_type = t;
         self
@@ -166,6 +341,55 @@ impl BaseColumn {
         self
     }
 
+    /// Only applies to numeric columns
+    pub fn set_is_unsigned(&mut self, unsigned: bool) -> &mut Self {
+        self.is_unsigned = unsigned;
+        self
+    }
+
+    pub fn set_comment(&mut self, comment: &str) -> &mut Self {
+        self.comment = Some(comment.to_owned());
+        self
+    }
+
+    /// Only applies to character columns
+    pub fn set_collation(&mut self, collation: &str) -> &mut Self {
+        self.collation = Some(collation.to_owned());
+        self
+    }
+
+    /// The column is computed from the expression when read.
+    /// MySQL stores `->>` as `json_unquote(json_extract(..))`, write the expression
+    /// that way to keep it from being redefined on every update
+    pub fn set_virtual_as(&mut self, expression: &str) -> &mut Self {
+        self.set_generated(expression, GeneratedStorage::Virtual)
+    }
+
+    /// The column is computed from the expression when the row is written
+    pub fn set_stored_as(&mut self, expression: &str) -> &mut Self {
+        self.set_generated(expression, GeneratedStorage::Stored)
+    }
+
+    pub fn set_generated(&mut self, expression: &str, storage: GeneratedStorage) -> &mut Self {
+        self.generated = Some(GeneratedColumn {
+            expression: expression.to_owned(),
+            storage,
+        });
+        self
+    }
+
+    /// Rejects rows where the expression, such as `price >= 0`, is false
+    pub fn set_check(&mut self, expression: &str) -> &mut Self {
+        self.check = Some(expression.to_owned());
+        self
+    }
+
+    /// Values written through a validating `SaveRecord` must satisfy the rule
+    pub fn add_rule(&mut self, rule: ValidationRule) -> &mut Self {
+        self.rules.push(rule);
+        self
+    }
+
     pub fn references(&mut self, table: &str, column: &str, cascade_delete: bool) -> &mut Self {
         self.relationship = Some(ForeignKey::new(table, column, cascade_delete));
         self
@@ -177,4 +401,61 @@ impl BaseColumn {
     pub fn references_without_cascade_delete(&mut self, table: &str, column: &str) -> &mut Self {
         self.references(table, column, false)
     }
+
+    /// The delete rule of the column's foreign key
+    pub fn set_on_delete(&mut self, action: ForeignKeyAction) -> &mut Self {
+        self.relationship = self.relationship.take().map(|k| k.with_on_delete(action));
+        self
+    }
+
+    /// The update rule of the column's foreign key
+    pub fn set_on_update(&mut self, action: ForeignKeyAction) -> &mut Self {
+        self.relationship = self.relationship.take().map(|k| k.with_on_update(action));
+        self
+    }
+
+    /// Names the column's foreign key constraint so that it can be dropped later
+    pub fn set_foreign_key_name(&mut self, name: &str) -> &mut Self {
+        self.relationship = self.relationship.take().map(|k| k.with_name(name));
+        self
+    }
+
+    /// Overrides the default pivot table name of a multiple relation
+    pub fn set_pivot_table(&mut self, name: &str) -> &mut Self {
+        self.pivot_table = Some(name.to_owned());
+        self
+    }
+
+    /// A multiple relation is stored in a pivot table instead of a column
+    pub fn has_pivot_table(&self) -> bool {
+        matches!(
+            self.column_type,
+            ColumnType::Relation {
+                relation_type: RelationType::Multiple(_),
+                ..
+            }
+        )
+    }
+
+    /// The identifiers other than its name that the column's DDL refers to
+    pub fn referenced_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = [
+            &self.new_name,
+            &self.after,
+            &self.pivot_table,
+            &self.collation,
+        ]
+        .into_iter()
+        .filter_map(|name| name.as_deref())
+        .collect();
+        if let Some(key) = &self.relationship {
+            names.extend(key.name.as_deref());
+            names.push(&key.table);
+            names.push(&key.column);
+        }
+        if let ColumnType::Relation { table_name, .. } = &self.column_type {
+            names.push(table_name);
+        }
+        names
+    }
 }
@@ -0,0 +1,73 @@
+use super::{filter::TableLookup, manager::Manager, schema_diff::SchemaChange, table::BaseTable};
+
+/// The tables the application defines, in the order they are created.
+/// Compared with the database to generate migrations
+#[derive(Debug, Clone, Default)]
+pub struct DesiredSchema {
+    tables: Vec<BaseTable>,
+}
+
+impl DesiredSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines a table with the builder API
+    pub fn table(&mut self, name: &str, callback: impl FnOnce(&mut BaseTable)) -> &mut Self {
+        let mut table = BaseTable::new(name);
+        callback(&mut table);
+        self.add(table)
+    }
+
+    /// Adds the table and its pivot tables. Redefining a table replaces it
+    pub fn add(&mut self, table: BaseTable) -> &mut Self {
+        let pivots = table.pivot_tables();
+        self.replace(table);
+
+        for pivot in pivots {
+            let mut table = BaseTable::new(pivot.name());
+            pivot.build(&mut table);
+            self.replace(table);
+        }
+
+        self
+    }
+
+    pub fn find(&self, name: &str) -> Option<&BaseTable> {
+        self.tables.iter().find(|t| t.name == name)
+    }
+
+    pub fn tables(&self) -> &[BaseTable] {
+        &self.tables
+    }
+
+    /// Creates or converges every table. Destructive changes are only
+    /// applied when allowed. The skipped ones are returned with their table
+    pub async fn apply(
+        &self,
+        manager: &Manager,
+        allow_destructive: bool,
+    ) -> Vec<(String, anyhow::Result<Vec<SchemaChange>>)> {
+        let mut results = Vec::new();
+        for table in &self.tables {
+            let result = manager
+                .converge_table(table.clone(), allow_destructive)
+                .await;
+            results.push((table.name.clone(), result));
+        }
+        results
+    }
+
+    fn replace(&mut self, table: BaseTable) {
+        match self.tables.iter().position(|t| t.name == table.name) {
+            Some(position) => self.tables[position] = table,
+            None => self.tables.push(table),
+        }
+    }
+}
+
+impl TableLookup for DesiredSchema {
+    fn find_table(&self, name: &str) -> Option<&BaseTable> {
+        self.find(name)
+    }
+}
@@ -0,0 +1,441 @@
+use super::{
+    column::{BaseColumn, ColumnDefault, ColumnType},
+    filter::TableLookup,
+    helper::generate_ulid,
+    manager::Manager,
+    table::BaseTable,
+    validation::ValidationRule,
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::{collections::HashMap, future::Future, pin::Pin};
+
+const FIRST_NAMES: [&str; 12] = [
+    "Ada", "Alan", "Amara", "Chen", "Grace", "Ines", "Kofi", "Linus", "Maya", "Noor", "Sven",
+    "Yuki",
+];
+const LAST_NAMES: [&str; 12] = [
+    "Adeyemi", "Berg", "Costa", "Dubois", "Hopper", "Ito", "Kowalski", "Lovelace", "Mensah",
+    "Okafor", "Silva", "Turing",
+];
+const WORDS: [&str; 24] = [
+    "amber", "bright", "cedar", "delta", "ember", "forest", "granite", "harbor", "island",
+    "juniper", "kestrel", "lantern", "meadow", "north", "orchid", "pine", "quartz", "river",
+    "summit", "timber", "umber", "valley", "willow", "zephyr",
+];
+
+/// Generates fake rows for a table from its column definitions and inserts
+/// them. Rows referenced through foreign keys are created first
+pub struct Factory<'a> {
+    table: BaseTable,
+    lookup: &'a dyn TableLookup,
+    overrides: HashMap<String, String>,
+    rng: StdRng,
+    sequence: u64,
+}
+
+impl<'a> Factory<'a> {
+    /// The factory of a table known to the lookup
+    pub fn new(table: &str, lookup: &'a dyn TableLookup) -> anyhow::Result<Self> {
+        match lookup.find_table(table) {
+            Some(table) => Ok(Self {
+                table: table.clone(),
+                lookup,
+                overrides: HashMap::new(),
+                rng: StdRng::from_entropy(),
+                sequence: 0,
+            }),
+            None => anyhow::bail!("no definition for table `{}`", table),
+        }
+    }
+
+    /// The same seed generates the same rows
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Every row gets this value instead of a generated one
+    pub fn set(&mut self, column: &str, value: &str) -> &mut Self {
+        self.overrides.insert(column.to_owned(), value.to_owned());
+        self
+    }
+
+    /// A row's values, without inserting it. Foreign key columns are
+    /// left out unless they are set
+    pub fn make(&mut self) -> HashMap<String, String> {
+        self.sequence += 1;
+        fake_row(&self.table, &self.overrides, &mut self.rng, self.sequence)
+    }
+
+    /// Inserts a row, and the rows it references. Returns its values
+    pub async fn create(&mut self, manager: &Manager) -> anyhow::Result<HashMap<String, String>> {
+        self.sequence += 1;
+        let mut chain = Vec::new();
+        create_row(
+            manager,
+            self.lookup,
+            &self.table,
+            self.overrides.clone(),
+            &mut self.rng,
+            self.sequence,
+            &mut chain,
+        )
+        .await
+    }
+
+    pub async fn create_many(
+        &mut self,
+        manager: &Manager,
+        count: usize,
+    ) -> anyhow::Result<Vec<HashMap<String, String>>> {
+        let mut rows = Vec::with_capacity(count);
+        for _ in 0..count {
+            rows.push(self.create(manager).await?);
+        }
+        Ok(rows)
+    }
+}
+
+type RowFuture<'f> = Pin<Box<dyn Future<Output = anyhow::Result<HashMap<String, String>>> + 'f>>;
+
+// `chain` holds the tables being created, to stop at reference cycles
+fn create_row<'f>(
+    manager: &'f Manager,
+    lookup: &'f dyn TableLookup,
+    table: &'f BaseTable,
+    mut values: HashMap<String, String>,
+    rng: &'f mut StdRng,
+    sequence: u64,
+    chain: &'f mut Vec<String>,
+) -> RowFuture<'f> {
+    Box::pin(async move {
+        chain.push(table.name.clone());
+
+        for key in table.foreign_key_constraints() {
+            if key.columns.iter().all(|c| values.contains_key(c)) {
+                continue;
+            }
+
+            let is_optional = key
+                .columns
+                .iter()
+                .all(|c| table.find_column(c).and_then(|c| c.is_nullable) == Some(true));
+            let parent = match lookup.find_table(&key.table) {
+                Some(parent) if !chain.contains(&parent.name) => parent,
+                _ if is_optional => continue,
+                _ => anyhow::bail!(
+                    "can not create the `{}` row referenced by `{}`",
+                    key.table,
+                    table.name
+                ),
+            };
+
+            let parent_row = create_row(
+                manager,
+                lookup,
+                parent,
+                HashMap::new(),
+                rng,
+                sequence,
+                chain,
+            )
+            .await?;
+            for (column, referenced) in key.columns.iter().zip(&key.references) {
+                match parent_row.get(referenced) {
+                    Some(value) => values.insert(column.clone(), value.clone()),
+                    None => anyhow::bail!(
+                        "`{}.{}` is generated by the database and can not be referenced",
+                        key.table,
+                        referenced
+                    ),
+                };
+            }
+        }
+
+        let row = fake_row(table, &values, rng, sequence);
+        manager
+            .insert(&table.name)
+            .set_many(row.clone())
+            .validate_with(table.clone())
+            .save()
+            .await?;
+
+        chain.pop();
+        Ok(row)
+    })
+}
+
+// Columns the database or the application fill in are left out,
+// as are foreign keys that are not set
+pub(crate) fn fake_row(
+    table: &BaseTable,
+    overrides: &HashMap<String, String>,
+    rng: &mut StdRng,
+    sequence: u64,
+) -> HashMap<String, String> {
+    let foreign_keys: Vec<String> = table
+        .foreign_key_constraints()
+        .into_iter()
+        .flat_map(|key| key.columns)
+        .collect();
+
+    let mut row = HashMap::new();
+    for column in table.columns() {
+        if let Some(value) = overrides.get(&column.name) {
+            row.insert(column.name.clone(), value.clone());
+        } else if !foreign_keys.contains(&column.name) {
+            if let Some(value) = fake_value(column, rng, sequence) {
+                row.insert(column.name.clone(), value);
+            }
+        }
+    }
+    row
+}
+
+fn fake_value(column: &BaseColumn, rng: &mut StdRng, sequence: u64) -> Option<String> {
+    if column.generated.is_some() || column.has_pivot_table() {
+        return None;
+    }
+    match &column.default {
+        // kept so that the row can be referenced
+        Some(ColumnDefault::Ulid) => return Some(generate_ulid()),
+        Some(ColumnDefault::Uuid) => return Some(uuid::Uuid::new_v4().to_string()),
+        Some(ColumnDefault::CreatedAt | ColumnDefault::UpdatedAt) => return None,
+        _ => (),
+    }
+    // some nullable columns are left empty
+    if column.is_nullable == Some(true) && rng.gen_ratio(1, 5) {
+        return None;
+    }
+
+    for rule in &column.rules {
+        match rule {
+            ValidationRule::OneOf(values) => return values.choose(rng).cloned(),
+            ValidationRule::Email => return Some(fake_email(rng, sequence)),
+            ValidationRule::Url => return Some(fake_url(rng, sequence)),
+            _ => (),
+        }
+    }
+
+    let value = match column.column_type.storage_type() {
+        ColumnType::AutoIncrementId => return None,
+        ColumnType::Boolean => rng.gen_range(0..=1).to_string(),
+        ColumnType::Char(26) => generate_ulid(),
+        ColumnType::Char(36) => uuid::Uuid::new_v4().to_string(),
+        ColumnType::Char(length) | ColumnType::String(length) => {
+            truncated(fake_text(column, rng, sequence), length)
+        }
+        ColumnType::Binary(length) => truncated(word(rng).to_owned(), length),
+        ColumnType::Blob | ColumnType::Text => sentences(rng, 3),
+        ColumnType::Date => fake_datetime(rng, "%Y-%m-%d"),
+        ColumnType::Datetime | ColumnType::Timestamp => fake_datetime(rng, "%Y-%m-%d %H:%M:%S"),
+        ColumnType::Time => fake_datetime(rng, "%H:%M:%S"),
+        ColumnType::Decimal { precision, scale } => {
+            let whole = 10_u64.pow(precision.saturating_sub(scale).min(6) as u32);
+            let fraction = 10_u64.pow(scale.min(6) as u32);
+            match scale {
+                0 => rng.gen_range(0..whole).to_string(),
+                _ => format!(
+                    "{}.{:0width$}",
+                    rng.gen_range(0..whole),
+                    rng.gen_range(0..fraction),
+                    width = scale.min(6)
+                ),
+            }
+        }
+        ColumnType::Enum(values) => values.choose(rng).cloned()?,
+        ColumnType::Float | ColumnType::Number => {
+            format!("{:.2}", rng.gen_range(0.0..1000.0))
+        }
+        ColumnType::TinyInteger => rng.gen_range(0..=100).to_string(),
+        ColumnType::SmallInteger => rng.gen_range(0..=1000).to_string(),
+        ColumnType::Integer | ColumnType::MediumInteger => rng.gen_range(0..=100_000).to_string(),
+        ColumnType::Json => "{}".to_owned(),
+        _ => return None,
+    };
+
+    Some(within_rules(column, value, rng))
+}
+
+// Text that suits the column's name. Unique columns get the sequence
+fn fake_text(column: &BaseColumn, rng: &mut StdRng, sequence: u64) -> String {
+    let name = column.name.to_lowercase();
+    let text = if name.contains("email") {
+        return fake_email(rng, sequence);
+    } else if name.contains("url") || name.contains("website") || name.contains("link") {
+        return fake_url(rng, sequence);
+    } else if name.contains("first_name") {
+        pick(rng, &FIRST_NAMES).to_owned()
+    } else if name.contains("last_name") || name.contains("surname") {
+        pick(rng, &LAST_NAMES).to_owned()
+    } else if name == "username" || name == "login" {
+        format!("{}{}", pick(rng, &FIRST_NAMES).to_lowercase(), sequence)
+    } else if name.contains("name") {
+        format!("{} {}", pick(rng, &FIRST_NAMES), pick(rng, &LAST_NAMES))
+    } else if name.contains("password") || name.contains("token") {
+        (0..16)
+            .map(|_| rng.sample(rand::distributions::Alphanumeric) as char)
+            .collect()
+    } else if name.contains("phone") {
+        format!("+1 555 {:04}", rng.gen_range(0..10_000))
+    } else if name.contains("slug") {
+        format!("{}-{}-{}", word(rng), word(rng), sequence)
+    } else if name.contains("title") {
+        let mut title = sentence(rng, 4);
+        title.pop();
+        title
+    } else {
+        sentence(rng, 6)
+    };
+
+    if column.is_unique && !text.ends_with(&sequence.to_string()) {
+        format!("{} {}", text, sequence)
+    } else {
+        text
+    }
+}
+
+fn fake_email(rng: &mut StdRng, sequence: u64) -> String {
+    format!(
+        "{}.{}{}@example.com",
+        pick(rng, &FIRST_NAMES).to_lowercase(),
+        pick(rng, &LAST_NAMES).to_lowercase(),
+        sequence
+    )
+}
+
+fn fake_url(rng: &mut StdRng, sequence: u64) -> String {
+    format!("https://example.com/{}-{}", word(rng), sequence)
+}
+
+// within the last year
+fn fake_datetime(rng: &mut StdRng, format: &str) -> String {
+    let seconds = rng.gen_range(0..365 * 24 * 60 * 60);
+    (chrono::Utc::now() - chrono::Duration::seconds(seconds))
+        .format(format)
+        .to_string()
+}
+
+// Moves numbers into their min and max, pads and cuts text to its lengths
+fn within_rules(column: &BaseColumn, mut value: String, rng: &mut StdRng) -> String {
+    for rule in &column.rules {
+        match rule {
+            ValidationRule::Min(min) if value.parse::<f64>().is_ok_and(|v| v < *min) => {
+                value = min.to_string();
+            }
+            ValidationRule::Max(max) if value.parse::<f64>().is_ok_and(|v| v > *max) => {
+                value = max.to_string();
+            }
+            ValidationRule::MinLength(length) => {
+                while value.chars().count() < *length {
+                    value.push(' ');
+                    value.push_str(word(rng));
+                }
+            }
+            ValidationRule::MaxLength(length) => value = truncated(value, *length),
+            _ => (),
+        }
+    }
+    value
+}
+
+fn sentence(rng: &mut StdRng, words: usize) -> String {
+    let mut sentence = (0..words)
+        .map(|_| word(rng))
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if let Some(first) = sentence.get(..1) {
+        sentence = first.to_uppercase() + &sentence[1..];
+    }
+    sentence.push('.');
+    sentence
+}
+
+fn sentences(rng: &mut StdRng, count: usize) -> String {
+    (0..count)
+        .map(|_| {
+            let words = rng.gen_range(5..12);
+            sentence(rng, words)
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn word(rng: &mut StdRng) -> &'static str {
+    pick(rng, &WORDS)
+}
+
+fn pick(rng: &mut StdRng, values: &[&'static str]) -> &'static str {
+    values.choose(rng).copied().unwrap_or_default()
+}
+
+fn truncated(value: String, length: usize) -> String {
+    value.chars().take(length).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::validation::validate_record;
+
+    fn posts() -> BaseTable {
+        let mut table = BaseTable::new("posts");
+        table.id_set();
+        table
+            .ulid("author")
+            .references_without_cascade_delete("users", "id");
+        table.sized_string("title", 20);
+        table.string("slug").set_is_unique(true);
+        table
+            .string("status")
+            .add_rule(ValidationRule::OneOf(vec!["draft".into(), "live".into()]));
+        table.string("contact_email");
+        table.decimal("price", 5, 2);
+        table
+            .tiny_integer("rating")
+            .add_rule(ValidationRule::Min(1.0));
+        table.enumeration("kind", &["news", "blog"]);
+        table.timestamps();
+        table
+    }
+
+    #[test]
+    fn rows_follow_the_column_definitions() {
+        let table = posts();
+        let mut overrides = HashMap::new();
+        overrides.insert("rating".to_owned(), "3".to_owned());
+        let row = fake_row(&table, &overrides, &mut StdRng::seed_from_u64(7), 4);
+
+        let mut columns: Vec<&str> = row.keys().map(|k| k.as_str()).collect();
+        columns.sort();
+        assert_eq!(
+            columns,
+            vec![
+                "contact_email",
+                "id",
+                "kind",
+                "price",
+                "rating",
+                "slug",
+                "status",
+                "title"
+            ]
+        );
+        assert_eq!(row["rating"], "3");
+        assert_eq!(row["id"].len(), 26);
+        assert!(row["title"].chars().count() <= 20);
+        assert!(row["slug"].ends_with("-4"));
+        assert!(row["contact_email"].ends_with("4@example.com"));
+        assert!(row["price"].parse::<f64>().unwrap() < 1000.0);
+        assert!(validate_record(&table, &row, false).is_ok());
+    }
+
+    #[test]
+    fn seeds_repeat_rows() {
+        let table = posts();
+        let make = || fake_row(&table, &HashMap::new(), &mut StdRng::seed_from_u64(1), 1);
+        let (first, second) = (make(), make());
+        assert_eq!(first["title"], second["title"]);
+        assert_eq!(first["price"], second["price"]);
+    }
+}
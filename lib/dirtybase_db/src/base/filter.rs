@@ -0,0 +1,625 @@
+use super::{
+    column::{BaseColumn, ColumnType},
+    pivot::{PivotTable, PIVOT_OWNER_COLUMN, PIVOT_RELATED_COLUMN},
+    query::{QueryBuilder, WhereJoin},
+    query_operators::{Operator, LIKE_ESCAPE},
+    query_values::Value,
+    table::BaseTable,
+};
+use std::collections::HashMap;
+
+// Guards against filters that nest deep enough to blow the stack
+const MAX_DEPTH: usize = 16;
+
+/// Finds the definition of a table by name. Used to resolve relation paths
+pub trait TableLookup {
+    fn find_table(&self, name: &str) -> Option<&BaseTable>;
+}
+
+impl TableLookup for HashMap<String, BaseTable> {
+    fn find_table(&self, name: &str) -> Option<&BaseTable> {
+        self.get(name)
+    }
+}
+
+impl TableLookup for [BaseTable] {
+    fn find_table(&self, name: &str) -> Option<&BaseTable> {
+        self.iter().find(|t| t.name == name)
+    }
+}
+
+impl TableLookup for Vec<BaseTable> {
+    fn find_table(&self, name: &str) -> Option<&BaseTable> {
+        self.as_slice().find_table(name)
+    }
+}
+
+/// Compiles a client supplied filter into query conditions.
+///
+/// ```json
+/// {"_and": [{"age": {"_gt": 5}}, {"name": {"_contains": "x"}}]}
+/// ```
+/// Sibling keys are joined with AND. Relation columns accept a nested
+/// filter on the related table, either as `{"author": {"name": {"_eq": "x"}}}`
+/// or as the path `{"author.name": {"_eq": "x"}}`
+pub struct FilterParser<'a> {
+    table: &'a BaseTable,
+    lookup: &'a dyn TableLookup,
+}
+
+impl<'a> FilterParser<'a> {
+    pub fn new(table: &'a BaseTable, lookup: &'a dyn TableLookup) -> Self {
+        Self { table, lookup }
+    }
+
+    /// Adds the filter's conditions to the query
+    pub fn apply(
+        &self,
+        query: &mut QueryBuilder,
+        filter: &serde_json::Value,
+    ) -> anyhow::Result<()> {
+        let mut group = QueryBuilder::new(Vec::new());
+        self.apply_filter(self.table, &mut group, filter, 0)?;
+        query.where_group_operator(group.into_where_clauses(), Some(WhereJoin::And));
+        Ok(())
+    }
+
+    /// Adds the conditions of a query string filter such as `filter[age][_gt]=5`
+    pub fn apply_query_string(
+        &self,
+        query: &mut QueryBuilder,
+        query_string: &str,
+    ) -> anyhow::Result<()> {
+        self.apply(query, &parse_query_string(query_string)?)
+    }
+
+    fn apply_filter(
+        &self,
+        table: &BaseTable,
+        query: &mut QueryBuilder,
+        filter: &serde_json::Value,
+        depth: usize,
+    ) -> anyhow::Result<()> {
+        if depth > MAX_DEPTH {
+            anyhow::bail!("filter is nested more than {} levels deep", MAX_DEPTH);
+        }
+
+        let object = match filter.as_object() {
+            Some(object) => object,
+            None => anyhow::bail!("a filter must be an object"),
+        };
+
+        for (key, value) in object {
+            match key.as_str() {
+                "_and" | "_or" => {
+                    let items = match value.as_array() {
+                        Some(items) => items,
+                        None => anyhow::bail!("`{}` expects an array of filters", key),
+                    };
+
+                    let mut group = QueryBuilder::new(Vec::new());
+                    for item in items {
+                        let mut sub_group = QueryBuilder::new(Vec::new());
+                        self.apply_filter(table, &mut sub_group, item, depth + 1)?;
+                        let join = if key == "_and" {
+                            WhereJoin::And
+                        } else {
+                            WhereJoin::Or
+                        };
+                        group.where_group_operator(sub_group.into_where_clauses(), Some(join));
+                    }
+
+                    query.where_group_operator(group.into_where_clauses(), Some(WhereJoin::And));
+                }
+                _ => self.apply_field(table, query, key, value, depth)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn apply_field(
+        &self,
+        table: &BaseTable,
+        query: &mut QueryBuilder,
+        key: &str,
+        value: &serde_json::Value,
+        depth: usize,
+    ) -> anyhow::Result<()> {
+        // relation path: "author.name"
+        if let Some((relation, rest)) = key.split_once('.') {
+            let mut nested = serde_json::Map::new();
+            nested.insert(rest.to_owned(), value.clone());
+            let column = self.find_column(table, relation)?;
+            return self.apply_relation(
+                table,
+                column,
+                query,
+                &serde_json::Value::Object(nested),
+                depth,
+            );
+        }
+
+        let column = self.find_column(table, key)?;
+
+        if is_relation(column) && is_nested_filter(value) {
+            return self.apply_relation(table, column, query, value, depth);
+        }
+
+        if column.has_pivot_table() {
+            anyhow::bail!(
+                "`{}` is a multiple relation and can only be filtered by the related columns",
+                key
+            );
+        }
+
+        let name = format!("{}.{}", &table.name, &column.name);
+        match value {
+            serde_json::Value::Object(operators) => {
+                for (operator, operand) in operators {
+                    self.apply_operator(query, &name, operator, operand)?;
+                }
+                Ok(())
+            }
+            _ => self.apply_operator(query, &name, "_eq", value),
+        }
+    }
+
+    fn apply_relation(
+        &self,
+        table: &BaseTable,
+        column: &BaseColumn,
+        query: &mut QueryBuilder,
+        filter: &serde_json::Value,
+        depth: usize,
+    ) -> anyhow::Result<()> {
+        let related_name = match &column.column_type {
+            ColumnType::Relation { table_name, .. } => table_name,
+            _ => anyhow::bail!("`{}` is not a relation", &column.name),
+        };
+
+        let related = match self.lookup.find_table(related_name) {
+            Some(related) => related,
+            None => anyhow::bail!("unknown related table `{}`", related_name),
+        };
+
+        let mut related_query = QueryBuilder::new(vec![related.name.clone()]);
+        related_query.select(&format!("{}.id", &related.name));
+        self.apply_filter(related, &mut related_query, filter, depth + 1)?;
+
+        match PivotTable::from_column(&table.name, column) {
+            Some(pivot) => {
+                let mut pivot_query = QueryBuilder::new(vec![pivot.name().to_owned()]);
+                pivot_query
+                    .select(&format!("{}.{}", pivot.name(), PIVOT_OWNER_COLUMN))
+                    .where_operator(
+                        &format!("{}.{}", pivot.name(), PIVOT_RELATED_COLUMN),
+                        Operator::In,
+                        Value::SubQuery(related_query),
+                        None,
+                    );
+                query.where_operator(
+                    &format!("{}.id", &table.name),
+                    Operator::In,
+                    Value::SubQuery(pivot_query),
+                    Some(WhereJoin::And),
+                );
+            }
+            None => {
+                query.where_operator(
+                    &format!("{}.{}", &table.name, &column.name),
+                    Operator::In,
+                    Value::SubQuery(related_query),
+                    Some(WhereJoin::And),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn apply_operator(
+        &self,
+        query: &mut QueryBuilder,
+        column: &str,
+        operator: &str,
+        operand: &serde_json::Value,
+    ) -> anyhow::Result<()> {
+        let join = Some(WhereJoin::And);
+        let (operator, value) = match operator {
+            "_eq" => (Operator::Equal, json_to_value(operand)?),
+            "_neq" => (Operator::NotEqual, json_to_value(operand)?),
+            "_gt" => (Operator::Greater, json_to_value(operand)?),
+            "_ngt" => (Operator::NotGreater, json_to_value(operand)?),
+            "_gte" => (Operator::GreaterOrEqual, json_to_value(operand)?),
+            "_ngte" => (Operator::NotGreaterOrEqual, json_to_value(operand)?),
+            "_lt" => (Operator::Less, json_to_value(operand)?),
+            "_nlt" => (Operator::NotLess, json_to_value(operand)?),
+            "_lte" => (Operator::LessOrEqual, json_to_value(operand)?),
+            "_nlte" => (Operator::NotLessOrEqual, json_to_value(operand)?),
+            "_like" => (Operator::Like, json_to_value(operand)?),
+            "_nlike" => (Operator::NotLike, json_to_value(operand)?),
+            "_contains" => (Operator::Like, like_value(operand, "%", "%")?),
+            "_ncontains" => (Operator::NotLike, like_value(operand, "%", "%")?),
+            "_starts_with" => (Operator::Like, like_value(operand, "", "%")?),
+            "_ends_with" => (Operator::Like, like_value(operand, "%", "")?),
+            "_null" | "_nnull" => {
+                let is_null = match operand {
+                    serde_json::Value::Bool(b) => *b,
+                    serde_json::Value::String(s) => s != "false" && s != "0",
+                    _ => true,
+                };
+                let operator = if is_null == (operator == "_null") {
+                    Operator::Null
+                } else {
+                    Operator::NotNull
+                };
+                (operator, Value::Null)
+            }
+            "_in" | "_nin" => {
+                let value = match operand {
+                    serde_json::Value::Array(_) => json_to_value(operand)?,
+                    serde_json::Value::String(s) => {
+                        Value::Strings(s.split(',').map(|v| v.to_owned()).collect())
+                    }
+                    _ => json_to_value(&serde_json::Value::Array(vec![operand.clone()]))?,
+                };
+                let is_empty = match operand {
+                    serde_json::Value::Array(values) => values.is_empty(),
+                    serde_json::Value::String(s) => s.is_empty(),
+                    _ => false,
+                };
+                if is_empty {
+                    anyhow::bail!("`{}` expects at least one value", operator);
+                }
+                let operator = if operator == "_in" {
+                    Operator::In
+                } else {
+                    Operator::NotIn
+                };
+                (operator, value)
+            }
+            _ => anyhow::bail!("unknown filter operator `{}`", operator),
+        };
+
+        query.where_operator(column, operator, value, join);
+        Ok(())
+    }
+
+    fn find_column<'t>(&self, table: &'t BaseTable, name: &str) -> anyhow::Result<&'t BaseColumn> {
+        match table.columns().iter().find(|c| c.name == name) {
+            Some(column) => Ok(column),
+            None => anyhow::bail!("unknown column `{}` in `{}`", name, &table.name),
+        }
+    }
+}
+
+fn is_relation(column: &BaseColumn) -> bool {
+    matches!(column.column_type, ColumnType::Relation { .. })
+}
+
+// A nested filter has at least one key that is not an operator
+fn is_nested_filter(value: &serde_json::Value) -> bool {
+    match value.as_object() {
+        Some(object) => object.keys().any(|k| !k.starts_with('_')),
+        None => false,
+    }
+}
+
+// The operand is matched literally, its wildcards are escaped
+fn like_value(operand: &serde_json::Value, prefix: &str, suffix: &str) -> anyhow::Result<Value> {
+    let literal = match operand {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Number(n) => n.to_string(),
+        _ => anyhow::bail!("pattern operators expect a string"),
+    };
+
+    let mut pattern = prefix.to_owned();
+    for c in literal.chars() {
+        if c == '%' || c == '_' || c == LIKE_ESCAPE {
+            pattern.push(LIKE_ESCAPE);
+        }
+        pattern.push(c);
+    }
+    pattern.push_str(suffix);
+
+    Ok(Value::String(pattern))
+}
+
+fn json_to_value(value: &serde_json::Value) -> anyhow::Result<Value> {
+    Ok(match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Boolean(*b),
+        serde_json::Value::String(s) => Value::String(s.clone()),
+        serde_json::Value::Number(n) => {
+            if let Some(v) = n.as_i64() {
+                Value::I64(v)
+            } else if let Some(v) = n.as_u64() {
+                Value::U64(v)
+            } else {
+                Value::F64(n.as_f64().unwrap_or_default())
+            }
+        }
+        serde_json::Value::Array(items) => {
+            if items.iter().all(|i| i.is_i64()) {
+                Value::I64s(items.iter().filter_map(|i| i.as_i64()).collect())
+            } else if items.iter().all(|i| i.is_number()) {
+                Value::F64s(items.iter().filter_map(|i| i.as_f64()).collect())
+            } else if items.iter().all(|i| i.is_string() || i.is_number()) {
+                Value::Strings(
+                    items
+                        .iter()
+                        .map(|i| match i {
+                            serde_json::Value::String(s) => s.clone(),
+                            _ => i.to_string(),
+                        })
+                        .collect(),
+                )
+            } else {
+                anyhow::bail!("lists can only contain strings and numbers")
+            }
+        }
+        serde_json::Value::Object(_) => anyhow::bail!("unexpected object as a filter value"),
+    })
+}
+
+/// Converts the `filter[...]` entries of a query string into the JSON form.
+/// `filter[_or][0][age][_gt]=5` becomes `{"_or": [{"age": {"_gt": "5"}}]}`
+pub fn parse_query_string(query_string: &str) -> anyhow::Result<serde_json::Value> {
+    let mut root = serde_json::Value::Object(serde_json::Map::new());
+
+    for pair in query_string.trim_start_matches('?').split('&') {
+        if pair.is_empty() {
+            continue;
+        }
+
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let key = url_decode(key);
+        let value = url_decode(value);
+
+        let path = match key.strip_prefix("filter") {
+            Some(path) if path.starts_with('[') => path,
+            _ => continue,
+        };
+
+        let segments: Vec<&str> = path
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .split("][")
+            .collect();
+
+        if segments.len() > MAX_DEPTH * 3 {
+            anyhow::bail!("filter is nested too deep");
+        }
+
+        let mut current = &mut root;
+        for (index, segment) in segments.iter().enumerate() {
+            let object = match current.as_object_mut() {
+                Some(object) => object,
+                None => anyhow::bail!("conflicting filter entry `{}`", key),
+            };
+
+            if index == segments.len() - 1 {
+                object.insert(
+                    segment.to_string(),
+                    serde_json::Value::String(value.clone()),
+                );
+                break;
+            }
+
+            current = object
+                .entry(segment.to_string())
+                .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+        }
+    }
+
+    numbered_objects_to_arrays(root)
+}
+
+// `_and`/`_or` entries are indexed objects in a query string
+fn numbered_objects_to_arrays(value: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+    let object = match value {
+        serde_json::Value::Object(object) => object,
+        other => return Ok(other),
+    };
+
+    let is_list = !object.is_empty() && object.keys().all(|k| k.parse::<usize>().is_ok());
+    if is_list {
+        let mut entries = Vec::new();
+        for (k, v) in object {
+            entries.push((k.parse::<usize>()?, numbered_objects_to_arrays(v)?));
+        }
+        entries.sort_by_key(|(k, _)| *k);
+        return Ok(serde_json::Value::Array(
+            entries.into_iter().map(|(_, v)| v).collect(),
+        ));
+    }
+
+    let mut converted = serde_json::Map::new();
+    for (k, v) in object {
+        let v = numbered_objects_to_arrays(v)?;
+        if (k == "_and" || k == "_or") && v.is_object() {
+            anyhow::bail!("`{}` entries must be numbered, as in `filter[{}][0]`", k, k);
+        }
+        converted.insert(k, v);
+    }
+    Ok(serde_json::Value::Object(converted))
+}
+
+fn url_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' => decoded.push(b' '),
+            b'%' if index + 2 < bytes.len() => {
+                match (hex_digit(bytes[index + 1]), hex_digit(bytes[index + 2])) {
+                    (Some(high), Some(low)) => {
+                        decoded.push(high * 16 + low);
+                        index += 2;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::column::RelationType;
+    use crate::driver::mysql::mysql_schema_manager::tests::offline_manager;
+
+    fn tables() -> Vec<BaseTable> {
+        let mut users = BaseTable::new("users");
+        users.id_set();
+        users.string("name");
+        users.integer("age");
+        users.relation("company", RelationType::Single, "companies");
+        users.relation("roles", RelationType::Multiple(0), "roles");
+
+        let mut companies = BaseTable::new("companies");
+        companies.id_set();
+        companies.string("name");
+
+        let mut roles = BaseTable::new("roles");
+        roles.id_set();
+        roles.string("name");
+
+        vec![users, companies, roles]
+    }
+
+    fn compile(filter: serde_json::Value) -> anyhow::Result<(String, Vec<String>)> {
+        let tables = tables();
+        let mut query = QueryBuilder::new(vec!["users".to_owned()]);
+        FilterParser::new(&tables[0], &tables).apply(&mut query, &filter)?;
+        Ok(offline_manager().to_sql(&query))
+    }
+
+    #[test]
+    fn and_or_groups() {
+        let (sql, params) = compile(serde_json::json!({
+            "_or": [{"age": {"_gt": 5}}, {"name": {"_contains": "x"}}]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            sql,
+            "SELECT users.* FROM users WHERE ((users.age > ?) OR (users.name LIKE ? ESCAPE '!'))"
+        );
+        assert_eq!(params, vec!["5", "%x%"]);
+    }
+
+    #[test]
+    fn every_operator_is_supported() {
+        let (sql, _) = compile(serde_json::json!({
+            "age": {
+                "_eq": 1, "_neq": 1, "_gt": 1, "_ngt": 1, "_gte": 1, "_ngte": 1,
+                "_lt": 1, "_nlt": 1, "_lte": 1, "_nlte": 1, "_in": [1, 2], "_nin": [3],
+                "_null": false
+            },
+            "name": {"_like": "a%", "_nlike": "b%", "_nnull": false}
+        }))
+        .unwrap();
+
+        for expected in [
+            "users.age = ?",
+            "users.age <> ?",
+            "users.age > ?",
+            "NOT users.age > ?",
+            "NOT users.age >= ?",
+            "NOT users.age < ?",
+            "NOT users.age <= ?",
+            "users.age IN (?,?)",
+            "users.age NOT IN (?)",
+            "users.age IS NOT NULL",
+            "NOT users.name LIKE ?",
+            "users.name IS NULL",
+        ] {
+            assert!(sql.contains(expected), "missing `{}` in {}", expected, sql);
+        }
+    }
+
+    #[test]
+    fn pattern_operands_are_matched_literally() {
+        let (_, params) = compile(serde_json::json!({
+            "name": {"_contains": "50%_off!", "_starts_with": "a_", "_ends_with": 5}
+        }))
+        .unwrap();
+
+        assert_eq!(params, vec!["%50!%!_off!!%", "%5", "a!_%"]);
+    }
+
+    #[test]
+    fn empty_in_lists_are_rejected() {
+        assert!(compile(serde_json::json!({"age": {"_in": []}})).is_err());
+        assert!(compile(serde_json::json!({"age": {"_nin": []}})).is_err());
+        assert!(compile(serde_json::json!({"age": {"_in": ""}})).is_err());
+    }
+
+    #[test]
+    fn unknown_columns_and_operators_are_rejected() {
+        assert!(compile(serde_json::json!({"password": "x"})).is_err());
+        assert!(compile(serde_json::json!({"age": {"_sleep": 5}})).is_err());
+        assert!(compile(serde_json::json!({"company.secret": "x"})).is_err());
+    }
+
+    #[test]
+    fn relation_paths_become_sub_queries() {
+        let (single, params) = compile(serde_json::json!({"company.name": "acme"})).unwrap();
+        assert_eq!(
+            single,
+            "SELECT users.* FROM users WHERE (users.company IN (SELECT companies.id FROM companies WHERE companies.name = ?))"
+        );
+        assert_eq!(params, vec!["acme"]);
+
+        let (multiple, _) =
+            compile(serde_json::json!({"roles": {"name": {"_eq": "admin"}}})).unwrap();
+        assert!(multiple.contains(
+            "users.id IN (SELECT users_roles.owner_id FROM users_roles WHERE users_roles.related_id IN (SELECT roles.id FROM roles WHERE roles.name = ?))"
+        ));
+    }
+
+    #[test]
+    fn sub_query_tables_are_read_tables() {
+        let tables = tables();
+        let mut query = QueryBuilder::new(vec!["users".to_owned()]);
+        FilterParser::new(&tables[0], &tables)
+            .apply(
+                &mut query,
+                &serde_json::json!({"_or": [{"roles.name": "admin"}, {"age": 5}]}),
+            )
+            .unwrap();
+
+        assert_eq!(query.all_tables(), vec!["users", "users_roles", "roles"]);
+    }
+
+    #[test]
+    fn query_string_form() {
+        let filter =
+            parse_query_string("filter[_or][0][age][_gt]=5&filter[_or][1][name][_eq]=a%20b&page=2")
+                .unwrap();
+
+        assert_eq!(
+            filter,
+            serde_json::json!({"_or": [{"age": {"_gt": "5"}}, {"name": {"_eq": "a b"}}]})
+        );
+    }
+
+    #[test]
+    fn unnumbered_query_string_entries_are_rejected() {
+        assert!(parse_query_string("filter[_or][x][age]=5&filter[_or][0][age]=6").is_err());
+        assert!(parse_query_string("filter[_and][age]=5").is_err());
+    }
+}
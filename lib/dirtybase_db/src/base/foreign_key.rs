@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+/// What happens to the referencing rows when the referenced row is
+/// deleted or its key updated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForeignKeyAction {
+    Cascade,
+    SetNull,
+    Restrict,
+    #[default]
+    NoAction,
+}
+
+impl ForeignKeyAction {
+    /// InnoDB checks `RESTRICT` and `NO ACTION` the same way
+    pub fn is_same_as(&self, other: &ForeignKeyAction) -> bool {
+        self.is_rejecting() && other.is_rejecting() || self == other
+    }
+
+    fn is_rejecting(&self) -> bool {
+        matches!(self, Self::Restrict | Self::NoAction)
+    }
+}
+
+/// A foreign key constraint on one or more columns of a table
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BaseForeignKey {
+    // generated from the table and columns when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub columns: Vec<String>,
+    // the referenced table
+    pub table: String,
+    pub references: Vec<String>,
+    #[serde(default)]
+    pub on_delete: ForeignKeyAction,
+    #[serde(default)]
+    pub on_update: ForeignKeyAction,
+}
+
+impl BaseForeignKey {
+    pub fn new(columns: &[&str], table: &str, references: &[&str]) -> Self {
+        Self {
+            name: None,
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            table: table.to_owned(),
+            references: references.iter().map(|c| c.to_string()).collect(),
+            on_delete: ForeignKeyAction::NoAction,
+            on_update: ForeignKeyAction::NoAction,
+        }
+    }
+
+    pub fn set_name(&mut self, name: &str) -> &mut Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    pub fn set_on_delete(&mut self, action: ForeignKeyAction) -> &mut Self {
+        self.on_delete = action;
+        self
+    }
+
+    pub fn set_on_update(&mut self, action: ForeignKeyAction) -> &mut Self {
+        self.on_update = action;
+        self
+    }
+
+    /// The constraint name, the generated one when none was set
+    pub fn name_in(&self, table: &str) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| Self::generated_name(table, &self.columns))
+    }
+
+    /// `<table>_<columns>_foreign`
+    pub fn generated_name(table: &str, columns: &[String]) -> String {
+        format!("{}_{}_foreign", table, columns.join("_"))
+    }
+
+    /// True when both constraints reference the same columns with the same rules.
+    /// The names are not compared
+    pub fn is_same_as(&self, other: &BaseForeignKey) -> bool {
+        self.columns == other.columns
+            && self.table == other.table
+            && self.references == other.references
+            && self.on_delete.is_same_as(&other.on_delete)
+            && self.on_update.is_same_as(&other.on_update)
+    }
+}
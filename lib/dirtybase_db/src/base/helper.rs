@@ -3,3 +3,96 @@ use ulid::Ulid;
 pub fn generate_ulid() -> String {
     Ulid::new().to_string()
 }
+
+/// Binary values have no JSON type, they are base64 encoded strings
+pub fn binary_to_json(bytes: Vec<u8>) -> serde_json::Value {
+    use base64::Engine;
+    serde_json::Value::String(base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+/// A table, column or other name that can be put in a statement unquoted
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Whether an expression, such as a check or a generated column's, only holds
+/// names, literals, operators and function calls. Statement separators,
+/// comments, sub queries and backslash escapes are rejected
+pub(crate) fn is_safe_expression(expression: &str) -> bool {
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            // a string literal, quotes are doubled when escaped
+            '\'' => loop {
+                match chars.next() {
+                    Some('\'') if chars.peek() == Some(&'\'') => {
+                        chars.next();
+                    }
+                    Some('\'') => break,
+                    Some('\\') | None => return false,
+                    Some(_) => (),
+                }
+            },
+            // a quoted name
+            '`' | '"' => {
+                let name: String = chars.by_ref().take_while(|n| *n != c).collect();
+                if !is_valid_name(&name) {
+                    return false;
+                }
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(n) = chars.next_if(|n| n.is_ascii_alphanumeric() || *n == '_') {
+                    word.push(n);
+                }
+                if word.eq_ignore_ascii_case("select") {
+                    return false;
+                }
+            }
+            '-' if chars.peek() == Some(&'-') => return false,
+            '/' if chars.peek() == Some(&'*') => return false,
+            '(' | ')' | ',' | '.' | '+' | '-' | '*' | '/' | '%' | '=' | '<' | '>' | '!' | '|'
+            | '&' => (),
+            _ => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expressions_are_checked() {
+        for expression in [
+            "price >= 0",
+            "json_extract(meta, '$.sku')",
+            "status IN ('draft', 'it''s')",
+            "`first_name` || ' ' || \"last_name\"",
+            "(a + b) * 1.5 <> c AND NOT d",
+        ] {
+            assert!(is_safe_expression(expression), "{}", expression);
+        }
+
+        for expression in [
+            "1); DROP TABLE users; --",
+            "a -- comment",
+            "a /* comment */",
+            "a IN (SELECT id FROM users)",
+            "'\\' OR 1",
+            "'unterminated",
+            "`bad name`",
+            "a # comment",
+        ] {
+            assert!(!is_safe_expression(expression), "{}", expression);
+        }
+    }
+}
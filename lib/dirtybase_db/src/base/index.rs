@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexKind {
+    Index,
+    Unique,
+    Fulltext,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexOrder {
+    Asc,
+    Desc,
+}
+
+/// A column of an index with its optional prefix length and ordering
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexColumn {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<usize>,
+    #[serde(default = "ascending")]
+    pub order: IndexOrder,
+}
+
+fn ascending() -> IndexOrder {
+    IndexOrder::Asc
+}
+
+impl IndexColumn {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            length: None,
+            order: IndexOrder::Asc,
+        }
+    }
+}
+
+/// A secondary index on one or more columns of a table
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BaseIndex {
+    pub name: String,
+    pub kind: IndexKind,
+    pub columns: Vec<IndexColumn>,
+}
+
+impl BaseIndex {
+    pub fn new(name: &str, kind: IndexKind, columns: &[&str]) -> Self {
+        Self {
+            name: name.to_owned(),
+            kind,
+            columns: columns.iter().map(|c| IndexColumn::new(c)).collect(),
+        }
+    }
+
+    /// Only index the first `length` characters of the column
+    pub fn set_length(&mut self, column: &str, length: usize) -> &mut Self {
+        if let Some(c) = self.columns.iter_mut().find(|c| c.name == column) {
+            c.length = Some(length);
+        }
+        self
+    }
+
+    pub fn set_order(&mut self, column: &str, order: IndexOrder) -> &mut Self {
+        if let Some(c) = self.columns.iter_mut().find(|c| c.name == column) {
+            c.order = order;
+        }
+        self
+    }
+
+    pub fn descending(&mut self, column: &str) -> &mut Self {
+        self.set_order(column, IndexOrder::Desc)
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.kind == IndexKind::Unique
+    }
+
+    pub fn column_names(&self) -> Vec<&str> {
+        self.columns.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    /// True when this is a plain unique index on just the column
+    pub fn is_unique_on(&self, column: &str) -> bool {
+        self.is_single_unique() && self.columns[0].name == column
+    }
+
+    /// A unique index on one whole column, the same as a unique column
+    pub fn is_single_unique(&self) -> bool {
+        self.is_unique()
+            && self.columns.len() == 1
+            && self.columns[0].length.is_none()
+            && self.columns[0].order == IndexOrder::Asc
+    }
+}
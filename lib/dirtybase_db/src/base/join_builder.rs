@@ -1,9 +1,13 @@
-use super::query_join_types::JoinType;
+use super::{query_join_types::JoinType, query_serde::JoinDto};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "JoinDto", try_from = "JoinDto")]
 pub struct JoinQueryBuilder {
     table: String,
-    join_clause: String,
+    left_table: String,
+    operator: String,
+    right_table: String,
     select_columns: Option<Vec<String>>,
     join_type: JoinType,
 }
@@ -19,7 +23,9 @@ impl JoinQueryBuilder {
     ) -> Self {
         Self {
             table: table.to_owned(),
-            join_clause: format!("{} {} {}", left_table, operator, right_table),
+            left_table: left_table.to_owned(),
+            operator: operator.to_owned(),
+            right_table: right_table.to_owned(),
             join_type,
             select_columns: select_columns
                 .map(|columns| columns.iter().map(|f| f.to_string()).collect()),
@@ -53,8 +59,20 @@ impl JoinQueryBuilder {
         &self.select_columns
     }
 
-    pub fn join_clause(&self) -> &str {
-        &self.join_clause
+    pub fn join_clause(&self) -> String {
+        format!("{} {} {}", self.left_table, self.operator, self.right_table)
+    }
+
+    pub fn left_table(&self) -> &str {
+        &self.left_table
+    }
+
+    pub fn operator(&self) -> &str {
+        &self.operator
+    }
+
+    pub fn right_table(&self) -> &str {
+        &self.right_table
     }
 
     pub fn table(&self) -> &str {
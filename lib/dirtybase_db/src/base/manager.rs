@@ -1,13 +1,112 @@
-use super::{query::QueryBuilder, save::SaveRecord, schema::SchemaManagerTrait, table::BaseTable};
+use super::{
+    collection::{
+        collections_from_rows, Collection, SchemaRegistry, COLLECTION_FIELD_TABLE, COLLECTION_TABLE,
+    },
+    filter::TableLookup,
+    helper::generate_ulid,
+    migration::DEFAULT_LOCK_TIMEOUT,
+    pivot::{PivotTable, PIVOT_OWNER_ALIAS, PIVOT_OWNER_COLUMN, PIVOT_RELATED_COLUMN},
+    query::QueryBuilder,
+    query_cache::QueryCache,
+    query_values::{ColumnAndValue, Value},
+    record_defaults::{generated_values, UserContext},
+    save::SaveRecord,
+    schema::SchemaManagerTrait,
+    schema_diff::{SchemaChange, SchemaDiff},
+    table::BaseTable,
+};
 use sqlx::any::AnyKind;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+/// Taken while tables are created, altered or migrated
+pub const SCHEMA_LOCK: &str = "dirtybase_schema";
 
 pub struct Manager {
     schema: Box<dyn SchemaManagerTrait>,
+    user: Option<UserContext>,
+    lock_timeout: Duration,
+    registry: Option<Arc<RwLock<SchemaRegistry>>>,
 }
 
 impl Manager {
     pub fn new(schema: Box<dyn SchemaManagerTrait>) -> Self {
-        Self { schema }
+        Self {
+            schema,
+            user: None,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            registry: None,
+        }
+    }
+
+    /// Records are validated against the definitions in the registry
+    /// before they are saved
+    pub fn with_registry(mut self, registry: Arc<RwLock<SchemaRegistry>>) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// The registered definition of the table
+    pub(crate) fn registered_table(&self, name: &str) -> Option<BaseTable> {
+        let registry = self.registry.as_ref()?.read().unwrap();
+        registry.find_table(name).cloned()
+    }
+
+    /// The table as it exists in the database. Cached in the registry
+    /// until this manager changes the schema
+    pub(crate) async fn live_table(&self, name: &str) -> anyhow::Result<Option<BaseTable>> {
+        let registry = match &self.registry {
+            Some(registry) => registry,
+            None => return self.fetch_table(name).await,
+        };
+        if let Some(table) = registry.read().unwrap().live_table(name) {
+            return Ok(Some(table.clone()));
+        }
+
+        let live = self.fetch_table(name).await?;
+        if let Some(table) = &live {
+            registry.write().unwrap().cache_live_table(table.clone());
+        }
+        Ok(live)
+    }
+
+    fn forget_live_tables(&self) {
+        if let Some(registry) = &self.registry {
+            registry.write().unwrap().forget_live_tables();
+        }
+    }
+
+    /// How long schema changes wait for the schema lock
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
+    /// Records written through this manager are blamed on the user
+    pub fn with_user(mut self, user: UserContext) -> Self {
+        self.user = Some(user);
+        self
+    }
+
+    pub fn user(&self) -> Option<&UserContext> {
+        self.user.as_ref()
+    }
+
+    /// Enables caching for the queries that opt in with `QueryBuilder::cache`
+    pub fn with_query_cache(mut self, cache: Arc<QueryCache>) -> Self {
+        self.schema.set_query_cache(cache);
+        self
+    }
+
+    /// Sends reads to the primary once this manager has written,
+    /// so that a request always sees its own changes
+    pub fn with_read_your_writes(mut self, enabled: bool) -> Self {
+        self.schema.set_read_your_writes(enabled);
+        self
     }
 
     pub fn db_kind(&self) -> AnyKind {
@@ -22,6 +121,10 @@ impl Manager {
         self.schema.as_mut()
     }
 
+    pub(crate) fn inner_ref(&self) -> &dyn SchemaManagerTrait {
+        self.schema.as_ref()
+    }
+
     pub fn table<F>(&mut self, table: &str, callback: F) -> &dyn SchemaManagerTrait
     where
         F: FnMut(&mut QueryBuilder),
@@ -39,32 +142,617 @@ impl Manager {
     }
 
     // Create a new table
-    pub async fn create(&self, name: &str, mut callback: impl FnMut(&mut BaseTable)) {
+    pub async fn create(
+        &self,
+        name: &str,
+        mut callback: impl FnMut(&mut BaseTable),
+    ) -> anyhow::Result<()> {
         if !self.has_table(name).await {
-            let mut table = self.schema.fetch_table_for_update(name);
-            table.set_is_new(true);
+            let mut table = BaseTable::new(name);
 
             callback(&mut table);
-            self.schema.commit(table).await;
+            let pivots = table.pivot_tables();
+            let result = self.schema.commit(table).await;
+            self.forget_live_tables();
+            result?;
+            self.create_pivot_tables(pivots).await?;
         }
+        Ok(())
     }
 
     // Get an existing table to updating
-    pub async fn update(&self, name: &str, mut callback: impl FnMut(&mut BaseTable)) {
+    pub async fn update(
+        &self,
+        name: &str,
+        mut callback: impl FnMut(&mut BaseTable),
+    ) -> anyhow::Result<()> {
         if self.has_table(name).await {
-            let mut table = self.schema.fetch_table_for_update(name);
+            let mut table = self.schema.fetch_table_for_update(name).await?;
             table.set_is_new(false);
 
             callback(&mut table);
-            self.schema.commit(table).await;
+            let pivots = table.pivot_tables();
+            let result = self.schema.commit(table).await;
+            self.forget_live_tables();
+            result?;
+            self.create_pivot_tables(pivots).await?;
+        }
+        Ok(())
+    }
+
+    /// The DDL `create` would run, without running it. Empty when the table exists
+    pub async fn plan_create(
+        &self,
+        name: &str,
+        mut callback: impl FnMut(&mut BaseTable),
+    ) -> anyhow::Result<Vec<String>> {
+        if self.has_table(name).await {
+            return Ok(Vec::new());
+        }
+
+        let mut table = BaseTable::new(name);
+        callback(&mut table);
+
+        let mut statements = self.schema.table_statements(&table, None);
+        statements.extend(self.plan_pivot_tables(table.pivot_tables()).await?);
+        Ok(statements)
+    }
+
+    /// The DDL `update` would run, without running it. Empty when the table does not exist
+    pub async fn plan_update(
+        &self,
+        name: &str,
+        mut callback: impl FnMut(&mut BaseTable),
+    ) -> anyhow::Result<Vec<String>> {
+        let live = match self.fetch_table(name).await? {
+            Some(live) => live,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut table = self.schema.fetch_table_for_update(name).await?;
+        table.set_is_new(false);
+        callback(&mut table);
+
+        let mut statements = self.schema.table_statements(&table, Some(&live));
+        statements.extend(self.plan_pivot_tables(table.pivot_tables()).await?);
+        Ok(statements)
+    }
+
+    /// The changes needed to bring the existing table in line with the definition.
+    /// A table that does not exist yet has every column added
+    pub async fn diff(
+        &self,
+        name: &str,
+        mut callback: impl FnMut(&mut BaseTable),
+    ) -> anyhow::Result<SchemaDiff> {
+        let mut desired = BaseTable::new(name);
+        callback(&mut desired);
+
+        let live = self
+            .fetch_table(name)
+            .await?
+            .unwrap_or_else(|| BaseTable::new(name));
+        Ok(SchemaDiff::between(&desired, &live))
+    }
+
+    /// Creates the table or brings the existing one in line with the definition.
+    /// Destructive changes are only applied when allowed, the skipped ones are returned
+    pub async fn converge(
+        &self,
+        name: &str,
+        mut callback: impl FnMut(&mut BaseTable),
+        allow_destructive: bool,
+    ) -> anyhow::Result<Vec<SchemaChange>> {
+        let mut desired = BaseTable::new(name);
+        callback(&mut desired);
+        self.converge_table(desired, allow_destructive).await
+    }
+
+    /// `converge` for a table that is already defined
+    pub async fn converge_table(
+        &self,
+        desired: BaseTable,
+        allow_destructive: bool,
+    ) -> anyhow::Result<Vec<SchemaChange>> {
+        let pivots = desired.pivot_tables();
+
+        let mut skipped = Vec::new();
+        let result = match self.fetch_table(&desired.name).await? {
+            None => self.schema.commit(desired).await,
+            Some(live) => {
+                let mut diff = SchemaDiff::between(&desired, &live);
+                if !allow_destructive {
+                    (diff, skipped) = diff.split_destructive();
+                }
+                if diff.is_empty() {
+                    Ok(())
+                } else {
+                    self.schema.apply_diff(&diff).await
+                }
+            }
+        };
+        // a failed change may still have altered the table
+        self.forget_live_tables();
+        result?;
+
+        self.create_pivot_tables(pivots).await?;
+        Ok(skipped)
+    }
+
+    /// Drops the table. Does nothing when it does not exist
+    pub async fn drop_table(&self, name: &str) -> anyhow::Result<()> {
+        let result = self.schema.drop_table(name).await;
+        self.forget_live_tables();
+        result
+    }
+
+    /// Creates or alters the collection's table, then stores its definition
+    /// in the meta tables. Destructive changes, such as removed fields, are only
+    /// applied when allowed. The skipped ones are returned. A table that exists
+    /// without being a collection is never taken over. The schema lock is held
+    /// throughout, and the definition is only stored once the table is in place
+    pub async fn save_collection(
+        &self,
+        collection: &Collection,
+        allow_destructive: bool,
+    ) -> anyhow::Result<Vec<SchemaChange>> {
+        collection.validate()?;
+        self.with_lock(SCHEMA_LOCK, self.lock_timeout, async {
+            let id = self.collection_id(&collection.name).await?;
+            if id.is_none() && self.has_table(&collection.name).await {
+                anyhow::bail!("the table `{}` is not a collection", collection.name);
+            }
+
+            let skipped = self
+                .converge_table(collection.to_table(), allow_destructive)
+                .await?;
+            self.in_transaction(self.store_collection(collection, id))
+                .await?;
+
+            Ok(skipped)
+        })
+        .await
+    }
+
+    /// Every collection defined in the meta tables
+    pub async fn fetch_collections(&mut self) -> anyhow::Result<Vec<Collection>> {
+        let collections = self
+            .table(COLLECTION_TABLE, |_| ())
+            .fetch_all_as_json()
+            .await;
+        let fields = self
+            .table(COLLECTION_FIELD_TABLE, |_| ())
+            .fetch_all_as_json()
+            .await;
+
+        collections_from_rows(&collections, &fields)
+    }
+
+    // The id of the collection's row in the meta table
+    async fn collection_id(&self, name: &str) -> anyhow::Result<Option<String>> {
+        let mut query = QueryBuilder::new(vec![COLLECTION_TABLE.to_owned()]);
+        query.select("id").eq("name", name).on_primary();
+
+        Ok(self
+            .fetch(query)
+            .await?
+            .first()
+            .and_then(|row| row.get("id"))
+            .and_then(|id| id.as_str())
+            .map(|id| id.to_owned()))
+    }
+
+    // The fields are replaced as a whole
+    async fn store_collection(
+        &self,
+        collection: &Collection,
+        id: Option<String>,
+    ) -> anyhow::Result<()> {
+        let optional = |value: &Option<String>| match value {
+            Some(value) => Value::from(value.as_str()),
+            None => Value::from(()),
+        };
+
+        let id = match id {
+            Some(id) => {
+                let mut query = QueryBuilder::new(vec![COLLECTION_TABLE.to_owned()]);
+                query
+                    .set("label", optional(&collection.label))
+                    .set("description", optional(&collection.description))
+                    .eq("id", id.as_str());
+                self.schema.update(query).await?;
+
+                let mut query = QueryBuilder::new(vec![COLLECTION_FIELD_TABLE.to_owned()]);
+                query.eq("collection_id", id.as_str());
+                self.schema.delete(query).await?;
+                id
+            }
+            None => {
+                let id = generate_ulid();
+                let mut record = ColumnAndValue::new();
+                record.insert("id".to_owned(), Value::from(id.as_str()));
+                record.insert("name".to_owned(), Value::from(collection.name.as_str()));
+                record.insert("label".to_owned(), optional(&collection.label));
+                record.insert("description".to_owned(), optional(&collection.description));
+                self.insert_with_defaults(COLLECTION_TABLE, record).await?;
+                id
+            }
+        };
+
+        for (position, field) in collection.fields.iter().enumerate() {
+            let mut record = ColumnAndValue::new();
+            record.insert("id".to_owned(), Value::from(generate_ulid()));
+            record.insert("collection_id".to_owned(), Value::from(id.as_str()));
+            record.insert("name".to_owned(), Value::from(field.name.as_str()));
+            record.insert("position".to_owned(), Value::from(position as u32));
+            record.insert(
+                "definition".to_owned(),
+                Value::from(serde_json::to_string(field)?),
+            );
+            self.insert_with_defaults(COLLECTION_FIELD_TABLE, record)
+                .await?;
         }
+
+        Ok(())
+    }
+
+    /// The rows matching the query. Unlike `table`, failures are errors
+    pub async fn fetch(&self, query: QueryBuilder) -> anyhow::Result<Vec<serde_json::Value>> {
+        self.schema.fetch(query).await
+    }
+
+    pub fn insert(&self, name: &str) -> SaveRecord<'_> {
+        SaveRecord::new(self, name)
+    }
+
+    /// The table as it currently exists in the database
+    pub async fn fetch_table(&self, name: &str) -> anyhow::Result<Option<BaseTable>> {
+        self.schema.fetch_table(name).await
     }
 
-    pub fn insert(&self, name: &str) -> SaveRecord {
-        SaveRecord::new(name)
+    pub async fn table_names(&self) -> anyhow::Result<Vec<String>> {
+        self.schema.table_names().await
+    }
+
+    /// Every table in the database as it currently exists
+    pub async fn fetch_all_tables(&self) -> anyhow::Result<Vec<BaseTable>> {
+        let mut tables = Vec::new();
+        for name in self.table_names().await? {
+            tables.extend(self.fetch_table(&name).await?);
+        }
+        Ok(tables)
     }
 
     pub async fn has_table(&self, name: &str) -> bool {
         self.schema.has_table(name).await
     }
+
+    /// Starts a transaction on the primary. Reads and writes go through
+    /// the transaction until it is committed or rolled back
+    pub async fn begin_transaction(&self) -> anyhow::Result<()> {
+        self.schema.begin_transaction().await
+    }
+
+    pub async fn commit_transaction(&self) -> anyhow::Result<()> {
+        self.schema.commit_transaction().await
+    }
+
+    pub async fn rollback_transaction(&self) -> anyhow::Result<()> {
+        self.schema.rollback_transaction().await
+    }
+
+    /// Runs the work in a transaction. It is committed when the work
+    /// succeeds and rolled back when it fails
+    pub async fn in_transaction<T>(
+        &self,
+        work: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        self.begin_transaction().await?;
+        match work.await {
+            Ok(value) => {
+                self.commit_transaction().await?;
+                Ok(value)
+            }
+            Err(e) => {
+                self.rollback_transaction().await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Takes the named lock, waiting up to `timeout` for other connections
+    /// to release it. Fails when it could not be acquired in time
+    pub async fn lock(&self, name: &str, timeout: Duration) -> anyhow::Result<()> {
+        self.schema.acquire_lock(name, timeout).await
+    }
+
+    pub async fn unlock(&self, name: &str) -> anyhow::Result<()> {
+        self.schema.release_lock(name).await
+    }
+
+    /// Runs the work while holding the named lock. The lock is released
+    /// whether the work succeeds or not. Work under the schema lock may
+    /// change any table, the cached live definitions are dropped after it
+    pub async fn with_lock<T>(
+        &self,
+        name: &str,
+        timeout: Duration,
+        work: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        self.lock(name, timeout).await?;
+        let result = work.await;
+        if name == SCHEMA_LOCK {
+            self.forget_live_tables();
+        }
+        let released = self.unlock(name).await;
+        let value = result?;
+        released?;
+        Ok(value)
+    }
+
+    /// Links the related records to the owner, skipping the ones already linked.
+    /// The owner's row is locked while its relations are counted and linked
+    pub async fn attach(
+        &self,
+        pivot: &PivotTable,
+        owner_id: &str,
+        related_ids: &[&str],
+    ) -> anyhow::Result<()> {
+        self.in_transaction(self.attach_in_transaction(pivot, owner_id, related_ids))
+            .await
+    }
+
+    async fn attach_in_transaction(
+        &self,
+        pivot: &PivotTable,
+        owner_id: &str,
+        related_ids: &[&str],
+    ) -> anyhow::Result<()> {
+        self.lock_owner(pivot, owner_id).await?;
+        let existing = self.related_ids(pivot, owner_id).await?;
+        let mut new_ids: Vec<&str> = Vec::new();
+        for id in related_ids {
+            if !existing.iter().any(|e| e == id) && !new_ids.contains(id) {
+                new_ids.push(id);
+            }
+        }
+
+        if !pivot.is_within_limit(existing.len() + new_ids.len()) {
+            anyhow::bail!(
+                "relation limit of {} exceeded for pivot table `{}`",
+                pivot.limit().unwrap_or_default(),
+                pivot.name()
+            );
+        }
+
+        let live = self.live_table(pivot.name()).await?;
+        for id in new_ids {
+            self.insert_pivot_record(pivot, live.as_ref(), owner_id, id)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Unlinks the related records from the owner. All the owner's
+    /// relations are removed when `related_ids` is empty
+    pub async fn detach(
+        &self,
+        pivot: &PivotTable,
+        owner_id: &str,
+        related_ids: &[&str],
+    ) -> anyhow::Result<u64> {
+        let mut query = QueryBuilder::new(vec![pivot.name().to_owned()]);
+        query.eq(PIVOT_OWNER_COLUMN, owner_id);
+        if !related_ids.is_empty() {
+            query.and_is_in(PIVOT_RELATED_COLUMN, related_ids.to_vec());
+        }
+
+        self.schema.delete(query).await
+    }
+
+    /// Makes the related records the only ones linked to the owner
+    pub async fn sync(
+        &self,
+        pivot: &PivotTable,
+        owner_id: &str,
+        related_ids: &[&str],
+    ) -> anyhow::Result<()> {
+        self.in_transaction(self.sync_in_transaction(pivot, owner_id, related_ids))
+            .await
+    }
+
+    async fn sync_in_transaction(
+        &self,
+        pivot: &PivotTable,
+        owner_id: &str,
+        related_ids: &[&str],
+    ) -> anyhow::Result<()> {
+        let mut wanted: Vec<&str> = Vec::new();
+        for id in related_ids {
+            if !wanted.contains(id) {
+                wanted.push(id);
+            }
+        }
+
+        if !pivot.is_within_limit(wanted.len()) {
+            anyhow::bail!(
+                "relation limit of {} exceeded for pivot table `{}`",
+                pivot.limit().unwrap_or_default(),
+                pivot.name()
+            );
+        }
+
+        self.lock_owner(pivot, owner_id).await?;
+        let existing = self.related_ids(pivot, owner_id).await?;
+        let stale: Vec<&str> = existing
+            .iter()
+            .filter(|id| !wanted.contains(&id.as_str()))
+            .map(|id| id.as_str())
+            .collect();
+
+        if !stale.is_empty() {
+            self.detach(pivot, owner_id, &stale).await?;
+        }
+
+        let live = self.live_table(pivot.name()).await?;
+        for id in wanted {
+            if !existing.iter().any(|e| e == id) {
+                self.insert_pivot_record(pivot, live.as_ref(), owner_id, id)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Eager loads the related records of each row in a single query.
+    /// The related records are added to each row under `key`
+    pub async fn load_related(
+        &mut self,
+        pivot: &PivotTable,
+        rows: &mut [serde_json::Value],
+        key: &str,
+    ) {
+        let owner_ids: Vec<String> = rows
+            .iter()
+            .filter_map(|row| row.get("id").and_then(|id| id.as_str()))
+            .map(|id| id.to_owned())
+            .collect();
+
+        let mut grouped: HashMap<String, Vec<serde_json::Value>> = HashMap::new();
+
+        if !owner_ids.is_empty() {
+            let related = pivot.related_table().to_owned();
+            let owner_column = format!("{}.{}", pivot.name(), PIVOT_OWNER_COLUMN);
+            let related_column = format!("{}.{}", pivot.name(), PIVOT_RELATED_COLUMN);
+            let alias = format!("{} AS {}", owner_column, PIVOT_OWNER_ALIAS);
+
+            let results = self
+                .table(&related, |query| {
+                    query
+                        .inner_join_and_select(
+                            pivot.name(),
+                            &related_column,
+                            "=",
+                            &format!("{}.id", &related),
+                            &[&alias],
+                        )
+                        .is_in(&owner_column, owner_ids.clone());
+                })
+                .fetch_all_as_json()
+                .await;
+
+            for mut result in results {
+                let owner = result
+                    .as_object_mut()
+                    .and_then(|r| r.remove(PIVOT_OWNER_ALIAS))
+                    .and_then(|id| id.as_str().map(|id| id.to_owned()));
+
+                if let Some(owner) = owner {
+                    grouped.entry(owner).or_default().push(result);
+                }
+            }
+        }
+
+        for row in rows.iter_mut() {
+            let id = row.get("id").and_then(|id| id.as_str()).map(String::from);
+            if let (Some(id), Some(row)) = (id, row.as_object_mut()) {
+                let related = grouped.remove(&id).unwrap_or_default();
+                row.insert(key.to_owned(), serde_json::Value::Array(related));
+            }
+        }
+    }
+
+    // Held until the transaction ends, concurrent changes to the
+    // owner's relations wait for it
+    async fn lock_owner(&self, pivot: &PivotTable, owner_id: &str) -> anyhow::Result<()> {
+        let mut query = QueryBuilder::new(vec![pivot.owner_table().to_owned()]);
+        query.select("id").eq("id", owner_id).lock_for_update();
+        self.fetch(query).await?;
+        Ok(())
+    }
+
+    async fn related_ids(&self, pivot: &PivotTable, owner_id: &str) -> anyhow::Result<Vec<String>> {
+        let mut query = QueryBuilder::new(vec![pivot.name().to_owned()]);
+        query
+            .select(PIVOT_RELATED_COLUMN)
+            .eq(PIVOT_OWNER_COLUMN, owner_id);
+
+        Ok(self
+            .fetch(query)
+            .await?
+            .iter()
+            .filter_map(|row| row.get(PIVOT_RELATED_COLUMN).and_then(|id| id.as_str()))
+            .map(|id| id.to_owned())
+            .collect())
+    }
+
+    async fn insert_pivot_record(
+        &self,
+        pivot: &PivotTable,
+        live: Option<&BaseTable>,
+        owner_id: &str,
+        related_id: &str,
+    ) -> anyhow::Result<()> {
+        let mut record = ColumnAndValue::new();
+        record.insert(PIVOT_OWNER_COLUMN.to_owned(), Value::from(owner_id));
+        record.insert(PIVOT_RELATED_COLUMN.to_owned(), Value::from(related_id));
+
+        self.insert_into(pivot.name(), live, record).await
+    }
+
+    // fills in the values the live table's columns generate
+    async fn insert_with_defaults(
+        &self,
+        table: &str,
+        record: ColumnAndValue,
+    ) -> anyhow::Result<()> {
+        let live = self.live_table(table).await?;
+        self.insert_into(table, live.as_ref(), record).await
+    }
+
+    async fn insert_into(
+        &self,
+        table: &str,
+        live: Option<&BaseTable>,
+        mut record: ColumnAndValue,
+    ) -> anyhow::Result<()> {
+        if let Some(live) = live {
+            let generated = generated_values(live, |c| record.contains_key(c), false, self.user());
+            record.extend(
+                generated
+                    .into_iter()
+                    .map(|(column, value)| (column, Value::from(value))),
+            );
+        }
+
+        self.schema.insert(table, record).await
+    }
+
+    async fn plan_pivot_tables(&self, pivots: Vec<PivotTable>) -> anyhow::Result<Vec<String>> {
+        let mut statements = Vec::new();
+        for pivot in pivots {
+            if self.fetch_table(pivot.name()).await?.is_none() {
+                let mut table = BaseTable::new(pivot.name());
+                pivot.build(&mut table);
+                statements.extend(self.schema.table_statements(&table, None));
+            }
+        }
+        Ok(statements)
+    }
+
+    async fn create_pivot_tables(&self, pivots: Vec<PivotTable>) -> anyhow::Result<()> {
+        for pivot in pivots {
+            if !self.has_table(pivot.name()).await {
+                let mut table = BaseTable::new(pivot.name());
+
+                pivot.build(&mut table);
+                let result = self.schema.commit(table).await;
+                self.forget_live_tables();
+                result?;
+            }
+        }
+        Ok(())
+    }
 }
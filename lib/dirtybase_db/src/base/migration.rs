@@ -0,0 +1,429 @@
+use super::{
+    manager::{Manager, SCHEMA_LOCK},
+    query::QueryBuilder,
+    schema_diff::SchemaChange,
+    table::BaseTable,
+};
+use async_trait::async_trait;
+use std::time::Duration;
+
+pub const MIGRATION_TABLE: &str = "_core_migration";
+
+/// How long a run waits for another one to finish
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A versioned change to the database. Migrations are applied in the
+/// order they are registered and rolled back in reverse
+#[async_trait(?Send)]
+pub trait Migration {
+    // unique, recorded in the migration table once applied
+    fn name(&self) -> &str;
+
+    async fn up(&self, manager: &Manager) -> anyhow::Result<()>;
+
+    async fn down(&self, manager: &Manager) -> anyhow::Result<()>;
+}
+
+/// The known migrations, in the order they are applied
+#[derive(Default)]
+pub struct MigrationRegistry {
+    migrations: Vec<Box<dyn Migration>>,
+}
+
+impl MigrationRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registering a name twice is an error
+    pub fn register(&mut self, migration: Box<dyn Migration>) -> anyhow::Result<&mut Self> {
+        if self.find(migration.name()).is_some() {
+            anyhow::bail!("migration `{}` is already registered", migration.name());
+        }
+        self.migrations.push(migration);
+        Ok(self)
+    }
+
+    pub fn find(&self, name: &str) -> Option<&dyn Migration> {
+        self.migrations
+            .iter()
+            .find(|m| m.name() == name)
+            .map(|m| m.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.migrations.iter().map(|m| m.name()).collect()
+    }
+}
+
+/// A migration recorded in the migration table
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppliedMigration {
+    pub id: u64,
+    pub name: String,
+    pub batch: u64,
+    // recorded before it ran on MySQL, still set when it failed part way
+    pub is_pending: bool,
+}
+
+/// A registered or applied migration and the batch it was applied in
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationStatus {
+    pub name: String,
+    // `None` while pending
+    pub batch: Option<u64>,
+    // applied, but no longer registered
+    pub is_missing: bool,
+    // started, but failed part way through
+    pub is_incomplete: bool,
+}
+
+/// How much of the applied migrations to roll back
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rollback {
+    // the last `n` migrations
+    Steps(usize),
+    // every migration of the last `n` batches
+    Batches(usize),
+}
+
+/// The table that records the applied migrations
+pub fn migration_table() -> BaseTable {
+    let mut table = BaseTable::new(MIGRATION_TABLE);
+    // id
+    table.id(None);
+    // migration name
+    table.string("name").set_is_unique(true);
+    // the run that applied it
+    table.integer("batch").set_is_unsigned(true);
+    // set until the migration completes
+    table.boolean("is_pending").default_is_zero();
+    // created at
+    table.created_at();
+    // updated at
+    table.updated_at();
+    table
+}
+
+/// Creates the table that records the applied migrations.
+/// Returns the destructive changes that were skipped
+pub async fn setup_migration_table(manager: &Manager) -> anyhow::Result<Vec<SchemaChange>> {
+    manager.converge_table(migration_table(), false).await
+}
+
+/// Applies, reports and rolls back the registry's migrations.
+/// Runs hold the schema lock, so only one instance migrates at a time.
+/// A migration that failed part way on MySQL blocks later runs until
+/// the schema is repaired
+pub struct MigrationRunner<'a> {
+    manager: &'a mut Manager,
+    registry: &'a MigrationRegistry,
+    lock_timeout: Duration,
+}
+
+impl<'a> MigrationRunner<'a> {
+    pub fn new(manager: &'a mut Manager, registry: &'a MigrationRegistry) -> Self {
+        Self {
+            manager,
+            registry,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+        }
+    }
+
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
+    /// The migrations in the migration table, in the order they were applied.
+    /// Read from the primary, a lagging replica would have them run again
+    pub async fn applied(&mut self) -> anyhow::Result<Vec<AppliedMigration>> {
+        let rows = self
+            .manager
+            .table(MIGRATION_TABLE, |query| {
+                query.on_primary();
+            })
+            .try_fetch_all_as_json()
+            .await?;
+        Ok(applied_from_rows(&rows))
+    }
+
+    /// Every registered migration, followed by the applied ones that
+    /// are no longer registered
+    pub async fn status(&mut self) -> anyhow::Result<Vec<MigrationStatus>> {
+        let applied = self.applied().await?;
+        Ok(status_of(self.registry, &applied))
+    }
+
+    /// Applies the pending migrations as one batch. Returns their names
+    pub async fn migrate(&mut self) -> anyhow::Result<Vec<String>> {
+        self.manager.lock(SCHEMA_LOCK, self.lock_timeout).await?;
+        let result = self.apply_pending().await;
+        let released = self.manager.unlock(SCHEMA_LOCK).await;
+        let names = result?;
+        released?;
+        Ok(names)
+    }
+
+    /// Runs the `down` of the migrations, latest first. Returns their names
+    pub async fn rollback(&mut self, rollback: Rollback) -> anyhow::Result<Vec<String>> {
+        self.manager.lock(SCHEMA_LOCK, self.lock_timeout).await?;
+        let result = self.roll_back(rollback).await;
+        let released = self.manager.unlock(SCHEMA_LOCK).await;
+        let names = result?;
+        released?;
+        Ok(names)
+    }
+
+    async fn apply_pending(&mut self) -> anyhow::Result<Vec<String>> {
+        let applied = self.applied().await?;
+        if let Some(incomplete) = applied.iter().find(|m| m.is_pending) {
+            anyhow::bail!(
+                "migration `{}` failed part way through, repair the schema and remove its row from `{}`",
+                incomplete.name,
+                MIGRATION_TABLE
+            );
+        }
+        let batch = applied.iter().map(|m| m.batch).max().unwrap_or_default() + 1;
+
+        let mut names = Vec::new();
+        for name in self.registry.names() {
+            if applied.iter().any(|m| m.name == name) {
+                continue;
+            }
+            let migration = self.registry.find(name).unwrap();
+            let manager: &Manager = self.manager;
+            // MySQL commits schema changes implicitly, a migration that fails
+            // part way could not be undone. It is recorded as pending first
+            let recorded_first = manager.is_mysql();
+            if recorded_first {
+                record(manager, name, batch, true).await?;
+            }
+            in_transaction(manager, name, async {
+                migration.up(manager).await?;
+                if recorded_first {
+                    let mut query = QueryBuilder::new(vec![MIGRATION_TABLE.to_owned()]);
+                    query.set("is_pending", "0").eq("name", name);
+                    manager.inner_ref().update(query).await?;
+                    Ok(())
+                } else {
+                    record(manager, name, batch, false).await
+                }
+            })
+            .await?;
+            names.push(name.to_owned());
+        }
+
+        Ok(names)
+    }
+
+    async fn roll_back(&mut self, rollback: Rollback) -> anyhow::Result<Vec<String>> {
+        let applied = self.applied().await?;
+
+        let mut names = Vec::new();
+        for record in rollback_plan(&applied, rollback) {
+            let migration = match self.registry.find(&record.name) {
+                Some(migration) => migration,
+                None => anyhow::bail!("migration `{}` is not registered", record.name),
+            };
+            let manager: &Manager = self.manager;
+            in_transaction(manager, &record.name, async {
+                migration.down(manager).await?;
+                let mut query = QueryBuilder::new(vec![MIGRATION_TABLE.to_owned()]);
+                query.eq("id", record.id);
+                manager.inner_ref().delete(query).await?;
+                Ok(())
+            })
+            .await?;
+            names.push(record.name);
+        }
+
+        Ok(names)
+    }
+}
+
+async fn record(manager: &Manager, name: &str, batch: u64, is_pending: bool) -> anyhow::Result<()> {
+    manager
+        .insert(MIGRATION_TABLE)
+        .set("name", name.to_owned())
+        .set("batch", batch.to_string())
+        .set("is_pending", if is_pending { "1" } else { "0" }.to_owned())
+        .save()
+        .await
+}
+
+// MySQL commits schema changes implicitly, only the data changes
+// are undone when the migration fails
+async fn in_transaction(
+    manager: &Manager,
+    name: &str,
+    step: impl std::future::Future<Output = anyhow::Result<()>>,
+) -> anyhow::Result<()> {
+    manager
+        .in_transaction(step)
+        .await
+        .map_err(|e| e.context(format!("migration `{}` failed", name)))
+}
+
+// Rows are sorted by id, the order they were applied in
+pub(crate) fn applied_from_rows(rows: &[serde_json::Value]) -> Vec<AppliedMigration> {
+    let number = |row: &serde_json::Value, key: &str| {
+        row.get(key)
+            .and_then(|v| {
+                v.as_u64()
+                    .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+            })
+            .unwrap_or_default()
+    };
+
+    let mut applied: Vec<AppliedMigration> = rows
+        .iter()
+        .map(|row| AppliedMigration {
+            id: number(row, "id"),
+            name: row
+                .get("name")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_owned(),
+            batch: number(row, "batch"),
+            is_pending: row
+                .get("is_pending")
+                .map(|v| v.as_bool().unwrap_or(number(row, "is_pending") != 0))
+                .unwrap_or_default(),
+        })
+        .collect();
+    applied.sort_by_key(|m| m.id);
+    applied
+}
+
+pub(crate) fn status_of(
+    registry: &MigrationRegistry,
+    applied: &[AppliedMigration],
+) -> Vec<MigrationStatus> {
+    let mut status: Vec<MigrationStatus> = registry
+        .names()
+        .into_iter()
+        .map(|name| {
+            let record = applied.iter().find(|m| m.name == name);
+            MigrationStatus {
+                name: name.to_owned(),
+                batch: record.map(|m| m.batch),
+                is_missing: false,
+                is_incomplete: record.is_some_and(|m| m.is_pending),
+            }
+        })
+        .collect();
+
+    for record in applied {
+        if registry.find(&record.name).is_none() {
+            status.push(MigrationStatus {
+                name: record.name.clone(),
+                batch: Some(record.batch),
+                is_missing: true,
+                is_incomplete: record.is_pending,
+            });
+        }
+    }
+
+    status
+}
+
+// The migrations to roll back, latest first
+pub(crate) fn rollback_plan(
+    applied: &[AppliedMigration],
+    rollback: Rollback,
+) -> Vec<AppliedMigration> {
+    let latest_first = applied.iter().rev().cloned();
+    match rollback {
+        Rollback::Steps(steps) => latest_first.take(steps).collect(),
+        Rollback::Batches(batches) => {
+            let mut seen: Vec<u64> = Vec::new();
+            latest_first
+                .take_while(|m| {
+                    if !seen.contains(&m.batch) {
+                        seen.push(m.batch);
+                    }
+                    seen.len() <= batches
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    struct Noop(&'static str);
+
+    #[async_trait(?Send)]
+    impl Migration for Noop {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        async fn up(&self, _manager: &Manager) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn down(&self, _manager: &Manager) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn applied() -> Vec<AppliedMigration> {
+        applied_from_rows(&[
+            json!({"id": 3, "name": "add_posts", "batch": 2}),
+            json!({"id": 1, "name": "create_users", "batch": 1}),
+            json!({"id": 2, "name": "add_roles", "batch": "1"}),
+            json!({"id": 4, "name": "add_tags", "batch": 2, "is_pending": 1}),
+        ])
+    }
+
+    fn names(migrations: &[AppliedMigration]) -> Vec<&str> {
+        migrations.iter().map(|m| m.name.as_str()).collect()
+    }
+
+    #[test]
+    fn rollbacks_go_by_step_or_batch() {
+        assert_eq!(
+            names(&applied()),
+            vec!["create_users", "add_roles", "add_posts", "add_tags"]
+        );
+        assert_eq!(
+            names(&rollback_plan(&applied(), Rollback::Steps(3))),
+            vec!["add_tags", "add_posts", "add_roles"]
+        );
+        assert_eq!(
+            names(&rollback_plan(&applied(), Rollback::Batches(1))),
+            vec!["add_tags", "add_posts"]
+        );
+        assert_eq!(rollback_plan(&applied(), Rollback::Batches(5)).len(), 4);
+    }
+
+    #[test]
+    fn status_lists_pending_and_missing_migrations() {
+        let mut registry = MigrationRegistry::new();
+        for name in ["create_users", "add_roles", "add_posts", "add_comments"] {
+            registry.register(Box::new(Noop(name))).unwrap();
+        }
+        assert!(registry.register(Box::new(Noop("add_roles"))).is_err());
+
+        let status = status_of(&registry, &applied());
+        let batches: Vec<(&str, Option<u64>, bool, bool)> = status
+            .iter()
+            .map(|s| (s.name.as_str(), s.batch, s.is_missing, s.is_incomplete))
+            .collect();
+        assert_eq!(
+            batches,
+            vec![
+                ("create_users", Some(1), false, false),
+                ("add_roles", Some(1), false, false),
+                ("add_posts", Some(2), false, false),
+                ("add_comments", None, false, false),
+                ("add_tags", Some(2), true, true),
+            ]
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use super::{
+    desired_schema::DesiredSchema,
+    schema_codegen::{builder_lines, dependency_order, push_lines},
+    schema_diff::{SchemaDiff, SchemaOperation},
+    table::BaseTable,
+};
+
+/// `<YYYYMMDDhhmmss>_<description>`, so that names sort in creation order
+pub fn timestamped_name(description: &str) -> String {
+    let description: String = description
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!(
+        "{}_{}",
+        chrono::Utc::now().format("%Y%m%d%H%M%S"),
+        description.trim_matches('_')
+    )
+}
+
+/// The source of a migration that brings the live tables in line with the
+/// desired schema, `None` when there is nothing to change. `up` converges the
+/// changed tables to their definition and `down` back to their live state.
+/// Live tables that are not defined, such as collections, are left alone
+pub fn generate_migration(
+    name: &str,
+    desired: &DesiredSchema,
+    live: &[BaseTable],
+) -> Option<String> {
+    // (desired, live) of the tables that changed
+    let mut changed: Vec<(&BaseTable, Option<&BaseTable>, Vec<String>)> = Vec::new();
+    for table in dependency_order(desired.tables()) {
+        let existing = live.iter().find(|t| t.name == table.name);
+        let changes = match existing {
+            Some(existing) => SchemaDiff::between(table, existing)
+                .changes
+                .iter()
+                .map(|c| describe(&c.operation))
+                .collect(),
+            None => vec!["create the table".to_owned()],
+        };
+        if !changes.is_empty() {
+            changed.push((table, existing, changes));
+        }
+    }
+
+    if changed.is_empty() {
+        return None;
+    }
+
+    let mut up = String::new();
+    for (table, _, changes) in &changed {
+        push_converge(&mut up, table, changes);
+    }
+
+    let mut down = String::new();
+    for (table, existing, _) in changed.iter().rev() {
+        match existing {
+            Some(existing) => push_converge(&mut down, existing, &[]),
+            None => down.push_str(&format!(
+                "        manager.drop_table({:?}).await?;\n",
+                table.name
+            )),
+        }
+    }
+
+    let type_name = type_name(name);
+    Some(format!(
+        "// Generated from the differences between the defined schema and the database\n\
+         #![allow(unused_imports)]\n\n\
+         use async_trait::async_trait;\n\
+         use dirtybase_db::base::{{\n    \
+             column::RelationType, foreign_key::ForeignKeyAction, manager::Manager, migration::Migration,\n\
+         }};\n\n\
+         pub struct {type_name};\n\n\
+         #[async_trait(?Send)]\n\
+         impl Migration for {type_name} {{\n    \
+             fn name(&self) -> &str {{\n        \
+                 {name:?}\n    \
+             }}\n\n    \
+             async fn up(&self, manager: &Manager) -> anyhow::Result<()> {{\n\
+         {up}        Ok(())\n    \
+             }}\n\n    \
+             async fn down(&self, manager: &Manager) -> anyhow::Result<()> {{\n\
+         {down}        Ok(())\n    \
+             }}\n\
+         }}\n"
+    ))
+}
+
+fn push_converge(source: &mut String, table: &BaseTable, changes: &[String]) {
+    for change in changes {
+        source.push_str(&format!("        // {}: {}\n", table.name, change));
+    }
+    source.push_str(&format!(
+        "        manager\n            .converge(\n                {:?},\n                |table| {{\n",
+        table.name
+    ));
+    push_lines(source, &builder_lines(table), 20);
+    source.push_str(
+        "                },\n                true,\n            )\n            .await?;\n",
+    );
+}
+
+// `20240101120000_add_posts` becomes `M20240101120000AddPosts`
+fn type_name(name: &str) -> String {
+    let words: String = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect();
+    format!("M{}", words)
+}
+
+fn describe(operation: &SchemaOperation) -> String {
+    match operation {
+        SchemaOperation::DropForeignKey(name) => format!("drop foreign key `{}`", name),
+        SchemaOperation::DropIndex(name) => format!("drop index `{}`", name),
+        SchemaOperation::DropCheck(name) => format!("drop check `{}`", name),
+        SchemaOperation::AddColumn(column) => format!("add column `{}`", column.name),
+        SchemaOperation::AlterColumn(column) => match &column.new_name {
+            Some(new_name) => format!("rename column `{}` to `{}`", column.name, new_name),
+            None => format!("change column `{}`", column.name),
+        },
+        SchemaOperation::DropPrimaryKey => "drop the primary key".to_owned(),
+        SchemaOperation::AddPrimaryKey(columns) => {
+            format!("set the primary key to `{}`", columns.join("`, `"))
+        }
+        SchemaOperation::AddUnique(column) => format!("make `{}` unique", column),
+        SchemaOperation::AddIndex(index) => format!("add index `{}`", index.name),
+        SchemaOperation::AddCheck(check) => format!("add check `{}`", check.name),
+        SchemaOperation::DropColumn(name) => format!("drop column `{}`", name),
+        SchemaOperation::AddForeignKey(key) => {
+            format!("add foreign key on `{}`", key.columns.join("`, `"))
+        }
+        SchemaOperation::SetEngine(engine) => format!("set the engine to {}", engine),
+        SchemaOperation::SetCharset(charset) => format!("set the charset to {}", charset),
+        SchemaOperation::SetCollation(collation) => {
+            format!("set the collation to {}", collation)
+        }
+        SchemaOperation::SetComment(_) => "change the comment".to_owned(),
+        SchemaOperation::RenameTable(name) => format!("rename to `{}`", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_tables_are_converged_both_ways() {
+        let mut desired = DesiredSchema::new();
+        desired.table("posts", |table| {
+            table.id(None);
+            table.sized_string("title", 100);
+            table.text("body").set_is_nullable(true);
+        });
+        desired.table("tags", |table| {
+            table.id(None);
+            table.string("name");
+        });
+
+        let mut posts = BaseTable::new("posts");
+        posts.id(None);
+        posts.sized_string("title", 100);
+        posts.string("slug");
+
+        let source = generate_migration("20260101120000_add_body", &desired, &[posts]).unwrap();
+
+        assert!(source.contains("pub struct M20260101120000AddBody;"));
+        assert!(source.contains("        \"20260101120000_add_body\"\n"));
+        assert!(source.contains(
+            "        // posts: add column `body`
+        // posts: drop column `slug`
+        manager
+            .converge(
+                \"posts\",
+                |table| {
+                    table.id(None);
+                    table.sized_string(\"title\", 100);
+                    table.text(\"body\").set_is_nullable(true);
+                },
+                true,
+            )
+            .await?;
+        // tags: create the table
+"
+        ));
+
+        let down = &source[source.find("async fn down").unwrap()..];
+        assert!(down.contains(
+            "        manager.drop_table(\"tags\").await?;
+        manager
+            .converge(
+                \"posts\",
+                |table| {
+                    table.id(None);
+                    table.sized_string(\"title\", 100);
+                    table.string(\"slug\");
+                },"
+        ));
+    }
+
+    #[test]
+    fn nothing_is_generated_without_changes() {
+        let mut desired = DesiredSchema::new();
+        desired.table("tags", |table| {
+            table.id(None);
+        });
+        let live = desired.tables().to_vec();
+        assert!(generate_migration("20260101120000_noop", &desired, &live).is_none());
+        assert!(timestamped_name("Add Tags!").ends_with("_add_tags"));
+    }
+}
@@ -0,0 +1,136 @@
+use super::{
+    column::{BaseColumn, ColumnType, RelationType},
+    table::BaseTable,
+};
+
+// The column in the pivot table that points to the owning record
+pub const PIVOT_OWNER_COLUMN: &str = "owner_id";
+
+// The column in the pivot table that points to the related record
+pub const PIVOT_RELATED_COLUMN: &str = "related_id";
+
+// Alias used when eager loading, so that the owner's ID does not clash
+// with the related table's columns
+pub const PIVOT_OWNER_ALIAS: &str = "__pivot_owner_id";
+
+/// Describes the table that links two tables in a many to many relation
+#[derive(Debug, Clone)]
+pub struct PivotTable {
+    name: String,
+    owner_table: String,
+    related_table: String,
+    limit: Option<usize>,
+}
+
+impl PivotTable {
+    pub fn new(name: &str, owner_table: &str, related_table: &str, limit: isize) -> Self {
+        Self {
+            name: name.to_owned(),
+            owner_table: owner_table.to_owned(),
+            related_table: related_table.to_owned(),
+            limit: if limit > 0 {
+                Some(limit as usize)
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Returns the pivot table for a `RelationType::Multiple` relation column
+    pub fn from_column(owner_table: &str, column: &BaseColumn) -> Option<Self> {
+        match &column.column_type {
+            ColumnType::Relation {
+                relation_type: RelationType::Multiple(limit),
+                table_name,
+            } => {
+                let name = column
+                    .pivot_table
+                    .clone()
+                    .unwrap_or_else(|| pivot_table_name(owner_table, &column.name));
+                Some(Self::new(&name, owner_table, table_name, *limit))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn owner_table(&self) -> &str {
+        &self.owner_table
+    }
+
+    pub fn related_table(&self) -> &str {
+        &self.related_table
+    }
+
+    /// The maximum number of related records. `None` means unlimited
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn is_within_limit(&self, total: usize) -> bool {
+        match self.limit {
+            Some(limit) => total <= limit,
+            None => true,
+        }
+    }
+
+    /// Adds the pivot columns to the table. The pair is the primary key
+    pub fn build(&self, table: &mut BaseTable) {
+        table
+            .ulid(PIVOT_OWNER_COLUMN)
+            .set_is_nullable(false)
+            .references_with_cascade_delete(&self.owner_table, "id");
+        table
+            .ulid(PIVOT_RELATED_COLUMN)
+            .set_is_nullable(false)
+            .references_with_cascade_delete(&self.related_table, "id");
+        table.created_at();
+        table.primary_key(&[PIVOT_OWNER_COLUMN, PIVOT_RELATED_COLUMN]);
+    }
+}
+
+// Default pivot table name for a relation column
+pub fn pivot_table_name(owner_table: &str, column: &str) -> String {
+    format!("{}_{}", owner_table, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pivot_from_relation_column() {
+        let mut table = BaseTable::new("_app_core_role");
+        table.relation("users", RelationType::Multiple(3), "_core_users");
+
+        let pivots = table.pivot_tables();
+        assert_eq!(pivots.len(), 1);
+        assert_eq!(pivots[0].name(), "_app_core_role_users");
+        assert_eq!(pivots[0].related_table(), "_core_users");
+        assert!(pivots[0].is_within_limit(3));
+        assert!(!pivots[0].is_within_limit(4));
+    }
+
+    #[test]
+    fn pivot_with_custom_name_and_no_limit() {
+        let mut table = BaseTable::new("_app_core_role");
+        table
+            .relation("users", RelationType::Multiple(0), "_core_users")
+            .set_pivot_table("_core_role_user");
+
+        let pivots = table.pivot_tables();
+        assert_eq!(pivots[0].name(), "_core_role_user");
+        assert_eq!(pivots[0].limit(), None);
+    }
+
+    #[test]
+    fn single_relation_has_no_pivot() {
+        let mut table = BaseTable::new("_app_core_role");
+        table.relation("owner", RelationType::Single, "_core_users");
+
+        assert!(table.pivot_tables().is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+use sqlx::{Database, Pool};
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// How a read replica is picked for a SELECT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplicaStrategy {
+    RoundRobin,
+    LeastConnections,
+}
+
+impl FromStr for ReplicaStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "round_robin" | "roundrobin" | "" => Ok(Self::RoundRobin),
+            "least_connections" | "leastconnections" => Ok(Self::LeastConnections),
+            _ => Err(anyhow::anyhow!("unknown replica strategy: {}", s)),
+        }
+    }
+}
+
+/// The primary connection pool and the optional read replica pools.
+/// Writes always go to the primary, reads are spread over the replicas
+pub struct PoolSet<DB: Database> {
+    primary: Pool<DB>,
+    replicas: Vec<Pool<DB>>,
+    strategy: ReplicaStrategy,
+    next: AtomicUsize,
+}
+
+impl<DB: Database> PoolSet<DB> {
+    pub fn new(primary: Pool<DB>) -> Self {
+        Self {
+            primary,
+            replicas: Vec::new(),
+            strategy: ReplicaStrategy::RoundRobin,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn add_replica(mut self, replica: Pool<DB>) -> Self {
+        self.replicas.push(replica);
+        self
+    }
+
+    pub fn set_strategy(mut self, strategy: ReplicaStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn strategy(&self) -> ReplicaStrategy {
+        self.strategy
+    }
+
+    pub fn primary(&self) -> &Pool<DB> {
+        &self.primary
+    }
+
+    pub fn replicas(&self) -> &Vec<Pool<DB>> {
+        &self.replicas
+    }
+
+    /// The pool a read should use. Falls back to the primary when
+    /// there are no replicas
+    pub fn reader(&self) -> &Pool<DB> {
+        if self.replicas.is_empty() {
+            return &self.primary;
+        }
+
+        let index = match self.strategy {
+            ReplicaStrategy::RoundRobin => {
+                self.next.fetch_add(1, Ordering::Relaxed) % self.replicas.len()
+            }
+            ReplicaStrategy::LeastConnections => least_busy(
+                &self
+                    .replicas
+                    .iter()
+                    // read apart, a released connection can count as idle
+                    // before the size catches up
+                    .map(|pool| (pool.size() as usize).saturating_sub(pool.num_idle()))
+                    .collect::<Vec<usize>>(),
+            ),
+        };
+
+        &self.replicas[index]
+    }
+}
+
+// Index of the pool with the fewest connections in use
+fn least_busy(in_use: &[usize]) -> usize {
+    in_use
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, total)| **total)
+        .map(|(index, _)| index)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strategy_from_str() {
+        assert_eq!(
+            ReplicaStrategy::from_str("least_connections").unwrap(),
+            ReplicaStrategy::LeastConnections
+        );
+        assert_eq!(
+            ReplicaStrategy::from_str("round_robin").unwrap(),
+            ReplicaStrategy::RoundRobin
+        );
+        assert!(ReplicaStrategy::from_str("random").is_err());
+    }
+
+    #[test]
+    fn least_busy_picks_the_first_idlest_pool() {
+        assert_eq!(least_busy(&[3, 1, 1, 4]), 1);
+        assert_eq!(least_busy(&[]), 0);
+    }
+}
@@ -1,9 +1,15 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use super::{
-    join_builder::JoinQueryBuilder, query_conditions::Condition, query_join_types::JoinType,
-    query_operators::Operator, query_values::Value, where_join_operators::WhereJoinOperator,
+    join_builder::JoinQueryBuilder,
+    query_conditions::Condition,
+    query_join_types::JoinType,
+    query_operators::Operator,
+    query_serde::QueryDto,
+    query_values::Value,
+    where_join_operators::{WhereClause, WhereJoinOperator},
 };
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug)]
 pub enum WhereJoin {
@@ -11,13 +17,18 @@ pub enum WhereJoin {
     Or,
 }
 
-#[derive(Debug)]
+/// Serialized through its wire format, identifiers are validated when read
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "QueryDto", try_from = "QueryDto")]
 pub struct QueryBuilder {
     where_clauses: Vec<WhereJoinOperator>,
     tables: Vec<String>,
     select_columns: Option<Vec<String>>,
-    set_columns: Option<HashMap<String, String>>,
+    set_columns: Option<HashMap<String, Value>>,
     joins: Option<Vec<JoinQueryBuilder>>,
+    cache_ttl: Option<Duration>,
+    on_primary: bool,
+    for_update: bool,
 }
 
 impl QueryBuilder {
@@ -28,6 +39,9 @@ impl QueryBuilder {
             select_columns: None,
             set_columns: None,
             joins: None,
+            cache_ttl: None,
+            on_primary: false,
+            for_update: false,
         }
     }
 
@@ -39,27 +53,72 @@ impl QueryBuilder {
         &self.select_columns
     }
 
-    pub fn set_columns(&self) -> &Option<Vec<String>> {
-        &self.select_columns
+    pub fn set_columns(&self) -> &Option<HashMap<String, Value>> {
+        &self.set_columns
     }
 
     pub fn joins(&self) -> &Option<Vec<JoinQueryBuilder>> {
         &self.joins
     }
 
-    pub fn set<T: ToString>(&mut self, column: &str, value: T) -> &mut Self {
+    /// Caches the result of this query for the specified duration
+    pub fn cache(&mut self, ttl: Duration) -> &mut Self {
+        self.cache_ttl = Some(ttl);
+        self
+    }
+
+    pub fn cache_ttl(&self) -> Option<Duration> {
+        self.cache_ttl
+    }
+
+    /// Reads from the primary even when there are replicas. For reads
+    /// that must see what another connection has just written
+    pub fn on_primary(&mut self) -> &mut Self {
+        self.on_primary = true;
+        self
+    }
+
+    pub fn is_on_primary(&self) -> bool {
+        self.on_primary
+    }
+
+    /// Locks the rows read until the transaction ends. SQLite has no row
+    /// locks, it takes the database's write lock instead.
+    /// Locked reads always go to the primary
+    pub fn lock_for_update(&mut self) -> &mut Self {
+        self.for_update = true;
+        self.on_primary = true;
+        self
+    }
+
+    pub fn is_for_update(&self) -> bool {
+        self.for_update
+    }
+
+    /// All the tables this query reads from, including joined tables
+    /// and those of its sub queries
+    pub fn all_tables(&self) -> Vec<String> {
+        let mut tables = self.tables.clone();
+        if let Some(joins) = &self.joins {
+            tables.extend(joins.iter().map(|j| j.table().to_owned()));
+        }
+        sub_query_tables(&self.where_clauses, &mut tables);
+        tables
+    }
+
+    pub fn set<T: Into<Value>>(&mut self, column: &str, value: T) -> &mut Self {
         if self.set_columns.is_none() {
             self.set_columns = Some(HashMap::new());
         }
 
         if let Some(columns) = &mut self.set_columns {
-            columns.insert(column.to_string(), value.to_string());
+            columns.insert(column.to_string(), value.into());
         }
 
         self
     }
 
-    pub fn set_multiple<T: ToString>(
+    pub fn set_multiple<T: Into<Value>>(
         &mut self,
         column_and_values: HashMap<String, T>,
     ) -> &mut Self {
@@ -69,7 +128,7 @@ impl QueryBuilder {
 
         if let Some(columns) = &mut self.set_columns {
             for entry in column_and_values {
-                columns.insert(entry.0, entry.1.to_string());
+                columns.insert(entry.0, entry.1.into());
             }
         }
 
@@ -370,14 +429,69 @@ impl QueryBuilder {
         self
     }
 
-    fn first_or_and(&mut self, condition: Condition) -> &mut Self {
+    fn first_or_and(&mut self, clause: WhereClause) -> &mut Self {
         if self.where_clauses.is_empty() {
-            self.where_(WhereJoinOperator::None(condition))
+            self.where_(WhereJoinOperator::None(clause))
         } else {
-            self.and_where(condition)
+            self.and_where(clause)
         }
     }
 
+    /// Wraps the conditions added by the callback in parentheses
+    pub fn where_group(&mut self, callback: impl FnOnce(&mut QueryBuilder)) -> &mut Self {
+        self.where_group_callback(callback, None)
+    }
+
+    pub fn and_where_group(&mut self, callback: impl FnOnce(&mut QueryBuilder)) -> &mut Self {
+        self.where_group_callback(callback, Some(WhereJoin::And))
+    }
+
+    pub fn or_where_group(&mut self, callback: impl FnOnce(&mut QueryBuilder)) -> &mut Self {
+        self.where_group_callback(callback, Some(WhereJoin::Or))
+    }
+
+    fn where_group_callback(
+        &mut self,
+        callback: impl FnOnce(&mut QueryBuilder),
+        and_or: Option<WhereJoin>,
+    ) -> &mut Self {
+        let mut group = QueryBuilder::new(Vec::new());
+        callback(&mut group);
+        self.where_group_operator(group.into_where_clauses(), and_or)
+    }
+
+    pub fn where_group_operator(
+        &mut self,
+        where_clauses: Vec<WhereJoinOperator>,
+        and_or: Option<WhereJoin>,
+    ) -> &mut Self {
+        if where_clauses.is_empty() {
+            return self;
+        }
+
+        // a group that only wraps another group does not need its own parentheses
+        let clause = if where_clauses.len() == 1 {
+            match where_clauses.into_iter().next().unwrap().into_clause() {
+                WhereClause::Condition(c) => {
+                    WhereClause::Group(vec![WhereJoinOperator::None(WhereClause::Condition(c))])
+                }
+                group => group,
+            }
+        } else {
+            WhereClause::Group(where_clauses)
+        };
+
+        match and_or {
+            Some(WhereJoin::And) => self.and_where(clause),
+            Some(WhereJoin::Or) => self.or_where(clause),
+            None => self.first_or_and(clause),
+        }
+    }
+
+    pub fn into_where_clauses(self) -> Vec<WhereJoinOperator> {
+        self.where_clauses
+    }
+
     pub fn where_operator<T: Into<Value>>(
         &mut self,
         column: &str,
@@ -385,7 +499,7 @@ impl QueryBuilder {
         value: T,
         and_or: Option<WhereJoin>,
     ) -> &mut Self {
-        let condition = Condition::new(column, operator, value);
+        let condition = Condition::new(column, operator, value).into();
         match and_or {
             Some(j) => match j {
                 WhereJoin::And => self.and_where(condition),
@@ -395,12 +509,12 @@ impl QueryBuilder {
         }
     }
 
-    fn or_where(&mut self, condition: Condition) -> &mut Self {
-        self.where_(WhereJoinOperator::Or(condition))
+    fn or_where(&mut self, clause: WhereClause) -> &mut Self {
+        self.where_(WhereJoinOperator::Or(clause))
     }
 
-    fn and_where(&mut self, condition: Condition) -> &mut Self {
-        self.where_(WhereJoinOperator::Or(condition))
+    fn and_where(&mut self, clause: WhereClause) -> &mut Self {
+        self.where_(WhereJoinOperator::And(clause))
     }
 
     pub fn join(
@@ -412,10 +526,6 @@ impl QueryBuilder {
         join_type: JoinType,
         select_columns: Option<&[&str]>,
     ) -> &mut Self {
-        if self.joins.is_none() {
-            self.joins = Some(Vec::new());
-        }
-
         let join = JoinQueryBuilder::new(
             table,
             left_table,
@@ -424,8 +534,11 @@ impl QueryBuilder {
             join_type,
             select_columns,
         );
-        self.joins.as_mut().unwrap().push(join);
+        self.push_join(join)
+    }
 
+    pub fn push_join(&mut self, join: JoinQueryBuilder) -> &mut Self {
+        self.joins.get_or_insert_with(Vec::new).push(join);
         self
     }
 
@@ -534,3 +647,16 @@ impl QueryBuilder {
         )
     }
 }
+
+fn sub_query_tables(where_clauses: &[WhereJoinOperator], tables: &mut Vec<String>) {
+    for where_clause in where_clauses {
+        match where_clause.clause() {
+            WhereClause::Condition(condition) => {
+                if let Value::SubQuery(query) = condition.value() {
+                    tables.extend(query.all_tables());
+                }
+            }
+            WhereClause::Group(group) => sub_query_tables(group, tables),
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+struct CacheEntry {
+    rows: Vec<serde_json::Value>,
+    tables: Vec<String>,
+    expires_at: Instant,
+    last_used: u64,
+}
+
+// How long replicas may lag behind the primary
+const DEFAULT_WRITE_WINDOW: Duration = Duration::from_secs(5);
+
+struct CacheStore {
+    entries: HashMap<String, CacheEntry>,
+    tick: u64,
+    // counts the writes, each table remembers the last one to it
+    generation: u64,
+    written_at: HashMap<String, (u64, Instant)>,
+}
+
+/// A least recently used cache of query results.
+/// Entries are keyed on the compiled statement and its params and expire
+/// after their TTL or when one of the tables they read from is written to
+pub struct QueryCache {
+    capacity: usize,
+    write_window: Duration,
+    store: Mutex<CacheStore>,
+}
+
+impl QueryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            write_window: DEFAULT_WRITE_WINDOW,
+            store: Mutex::new(CacheStore {
+                entries: HashMap::new(),
+                tick: 0,
+                generation: 0,
+                written_at: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn with_write_window(mut self, window: Duration) -> Self {
+        self.write_window = window;
+        self
+    }
+
+    pub fn key(statement: &str, params: &[String]) -> String {
+        let mut key = statement.to_owned();
+        for p in params {
+            key.push('\u{1f}');
+            key.push_str(p);
+        }
+        key
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<serde_json::Value>> {
+        let mut store = self.store.lock().unwrap();
+        store.tick += 1;
+        let tick = store.tick;
+
+        let expired = match store.entries.get_mut(key) {
+            Some(entry) if entry.expires_at > Instant::now() => {
+                entry.last_used = tick;
+                return Some(entry.rows.clone());
+            }
+            Some(_) => true,
+            None => false,
+        };
+
+        if expired {
+            store.entries.remove(key);
+        }
+
+        None
+    }
+
+    /// Taken before running a query that missed the cache, then handed to `put`
+    pub fn generation(&self) -> u64 {
+        self.store.lock().unwrap().generation
+    }
+
+    /// Caches the rows, unless one of the tables was written to since the
+    /// generation they were read at. They may predate that write
+    pub fn put(
+        &self,
+        key: &str,
+        tables: Vec<String>,
+        rows: Vec<serde_json::Value>,
+        ttl: Duration,
+        generation: u64,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut store = self.store.lock().unwrap();
+        let is_stale = tables.iter().any(|t| {
+            store
+                .written_at
+                .get(t)
+                .is_some_and(|(at, _)| *at > generation)
+        });
+        if is_stale {
+            return;
+        }
+
+        store.tick += 1;
+        let tick = store.tick;
+
+        if !store.entries.contains_key(key) && store.entries.len() >= self.capacity {
+            let now = Instant::now();
+            store.entries.retain(|_, entry| entry.expires_at > now);
+
+            if store.entries.len() >= self.capacity {
+                let oldest = store
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    store.entries.remove(&oldest);
+                }
+            }
+        }
+
+        store.entries.insert(
+            key.to_owned(),
+            CacheEntry {
+                rows,
+                tables,
+                expires_at: Instant::now() + ttl,
+                last_used: tick,
+            },
+        );
+    }
+
+    /// Drops every entry that reads from the table
+    pub fn invalidate_table(&self, table: &str) {
+        let mut store = self.store.lock().unwrap();
+        store.generation += 1;
+        let generation = store.generation;
+        store
+            .written_at
+            .insert(table.to_owned(), (generation, Instant::now()));
+        store
+            .entries
+            .retain(|_, entry| !entry.tables.iter().any(|t| t == table));
+    }
+
+    /// Whether one of the tables was written to within the write window.
+    /// Cached reads of them go to the primary, a lagging replica could
+    /// hand back rows from before the write
+    pub fn is_recently_written(&self, tables: &[String]) -> bool {
+        let store = self.store.lock().unwrap();
+        tables.iter().any(|t| {
+            store
+                .written_at
+                .get(t)
+                .is_some_and(|(_, at)| at.elapsed() < self.write_window)
+        })
+    }
+
+    pub fn clear(&self) {
+        self.store.lock().unwrap().entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.store.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<serde_json::Value> {
+        vec![serde_json::json!({"id": 1})]
+    }
+
+    #[test]
+    fn cached_rows_are_returned_until_invalidated() {
+        let cache = QueryCache::new(10);
+        let key = QueryCache::key("SELECT * FROM users WHERE id = ?", &["1".to_owned()]);
+        cache.put(
+            &key,
+            vec!["users".to_owned()],
+            rows(),
+            Duration::from_secs(60),
+            cache.generation(),
+        );
+
+        assert_eq!(cache.get(&key), Some(rows()));
+
+        cache.invalidate_table("roles");
+        assert!(cache.get(&key).is_some());
+
+        cache.invalidate_table("users");
+        assert!(cache.get(&key).is_none());
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let cache = QueryCache::new(10);
+        cache.put("a", vec![], rows(), Duration::from_secs(0), 0);
+
+        assert!(cache.get("a").is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn least_recently_used_entry_is_evicted() {
+        let cache = QueryCache::new(2);
+        cache.put("a", vec![], rows(), Duration::from_secs(60), 0);
+        cache.put("b", vec![], rows(), Duration::from_secs(60), 0);
+        cache.get("a");
+        cache.put("c", vec![], rows(), Duration::from_secs(60), 0);
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn rows_read_before_a_write_are_not_cached() {
+        let cache = QueryCache::new(10);
+        let generation = cache.generation();
+        cache.invalidate_table("users");
+        cache.invalidate_table("roles");
+
+        let users = vec!["users".to_owned()];
+        cache.put(
+            "a",
+            users.clone(),
+            rows(),
+            Duration::from_secs(60),
+            generation,
+        );
+        assert!(cache.get("a").is_none());
+
+        cache.put(
+            "a",
+            users,
+            rows(),
+            Duration::from_secs(60),
+            cache.generation(),
+        );
+        assert!(cache.get("a").is_some());
+    }
+
+    #[test]
+    fn tables_are_recently_written_within_the_window() {
+        let cache = QueryCache::new(10);
+        cache.invalidate_table("users");
+
+        assert!(cache.is_recently_written(&["users".to_owned()]));
+        assert!(!cache.is_recently_written(&["roles".to_owned()]));
+
+        let cache = QueryCache::new(10).with_write_window(Duration::ZERO);
+        cache.invalidate_table("users");
+        assert!(!cache.is_recently_written(&["users".to_owned()]));
+    }
+
+    #[test]
+    fn params_are_part_of_the_key() {
+        assert_ne!(
+            QueryCache::key("SELECT ?", &["1".to_owned()]),
+            QueryCache::key("SELECT ?", &["2".to_owned()])
+        );
+    }
+}
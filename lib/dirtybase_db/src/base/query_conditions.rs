@@ -1,6 +1,8 @@
-use super::{query_operators::Operator, query_values::Value};
+use super::{query_operators::Operator, query_serde::ConditionDto, query_values::Value};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "ConditionDto", try_from = "ConditionDto")]
 pub struct Condition {
     pub column: String,
     pub operator: Operator,
@@ -1,4 +1,12 @@
-#[derive(Debug, PartialEq)]
+use super::query_serde::OperatorDto;
+use serde::{Deserialize, Serialize};
+
+/// Escapes the wildcards in LIKE patterns. Unlike a backslash,
+/// it needs no escaping in the string literals of either database
+pub const LIKE_ESCAPE: char = '!';
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "OperatorDto", try_from = "OperatorDto")]
 pub enum Operator {
     Equal,
     NotEqual,
@@ -24,15 +32,18 @@ impl Operator {
             Self::Equal => format!("{} = {}", column, placeholder),
             Self::NotEqual => format!("{} <> {}", column, placeholder),
             Self::Greater => format!("{} > {}", column, placeholder),
-            Self::NotGreater => format!("NOT {} >= {}", column, placeholder),
+            Self::NotGreater => format!("NOT {} > {}", column, placeholder),
             Self::GreaterOrEqual => format!("{} >= {}", column, placeholder),
-            Self::NotGreaterOrEqual => format!("{} >= {}", column, placeholder),
+            Self::NotGreaterOrEqual => format!("NOT {} >= {}", column, placeholder),
             Self::Less => format!("{} < {}", column, placeholder),
             Self::NotLess => format!("NOT {} < {}", column, placeholder),
             Self::LessOrEqual => format!("{} <= {}", column, placeholder),
             Self::NotLessOrEqual => format!("NOT {} <= {}", column, placeholder),
-            Self::Like => format!("{} LIKE {}", column, placeholder),
-            Self::NotLike => format!("NOT {} LIKE {}", column, placeholder),
+            Self::Like => format!("{} LIKE {} ESCAPE '{}'", column, placeholder, LIKE_ESCAPE),
+            Self::NotLike => format!(
+                "NOT {} LIKE {} ESCAPE '{}'",
+                column, placeholder, LIKE_ESCAPE
+            ),
             Self::Null => format!("{} IS NULL", column),
             Self::NotNull => format!("{} IS NOT NULL", column),
             Self::In => format!("{} IN ({})", column, placeholder),
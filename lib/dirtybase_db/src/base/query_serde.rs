@@ -0,0 +1,663 @@
+use super::{
+    helper::is_valid_name,
+    join_builder::JoinQueryBuilder,
+    query::QueryBuilder,
+    query_conditions::Condition,
+    query_join_types::JoinType,
+    query_operators::Operator,
+    query_values::Value,
+    where_join_operators::{WhereClause, WhereJoinOperator},
+};
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+use std::{collections::HashMap, time::Duration};
+
+/// The current version of the serialized query format
+pub const QUERY_FORMAT_VERSION: u32 = 1;
+
+// Operators allowed between the two sides of a join
+const JOIN_OPERATORS: [&str; 7] = ["=", "<>", "!=", "<", ">", "<=", ">="];
+
+/// A query in its stable, versioned JSON form. Used to persist saved views,
+/// send queries between services and replay logged queries.
+/// The version is checked before the query is read, and identifiers
+/// are validated while it is
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    into = "Envelope<QueryBuilder>",
+    try_from = "Envelope<serde_json::Value>"
+)]
+pub struct SerializedQuery {
+    pub version: u32,
+    pub query: QueryBuilder,
+}
+
+impl SerializedQuery {
+    pub fn new(query: QueryBuilder) -> Self {
+        Self {
+            version: QUERY_FORMAT_VERSION,
+            query,
+        }
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+// The wire format. It only changes with the version,
+// whatever happens to the query builder's layout
+
+#[derive(Serialize, Deserialize)]
+struct Envelope<Q> {
+    version: u32,
+    query: Q,
+}
+
+impl From<SerializedQuery> for Envelope<QueryBuilder> {
+    fn from(serialized: SerializedQuery) -> Self {
+        Self {
+            version: serialized.version,
+            query: serialized.query,
+        }
+    }
+}
+
+impl TryFrom<Envelope<serde_json::Value>> for SerializedQuery {
+    type Error = anyhow::Error;
+
+    fn try_from(envelope: Envelope<serde_json::Value>) -> Result<Self, Self::Error> {
+        if envelope.version != QUERY_FORMAT_VERSION {
+            anyhow::bail!(
+                "unsupported query format version {}, expected {}",
+                envelope.version,
+                QUERY_FORMAT_VERSION
+            );
+        }
+
+        Ok(Self::new(serde_json::from_value(envelope.query)?))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct QueryDto {
+    #[serde(deserialize_with = "identifiers")]
+    tables: Vec<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "select_columns"
+    )]
+    select: Option<Vec<String>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "set_columns"
+    )]
+    set: Option<HashMap<String, ValueDto>>,
+    #[serde(default, rename = "where", skip_serializing_if = "Vec::is_empty")]
+    wheres: Vec<WhereDto>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    joins: Vec<JoinDto>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cache_ttl_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WhereDto {
+    join: WhereJoinDto,
+    #[serde(flatten)]
+    clause: ClauseDto,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum WhereJoinDto {
+    None,
+    And,
+    Or,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ClauseDto {
+    Condition(ConditionDto),
+    Group(Vec<WhereDto>),
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ConditionDto {
+    #[serde(deserialize_with = "identifier")]
+    column: String,
+    operator: OperatorDto,
+    value: ValueDto,
+}
+
+// Named like the filter operators
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OperatorDto {
+    Eq,
+    Neq,
+    Gt,
+    Ngt,
+    Gte,
+    Ngte,
+    Lt,
+    Lte,
+    Nlt,
+    Nlte,
+    Like,
+    Nlike,
+    Null,
+    Nnull,
+    In,
+    Nin,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub(crate) enum ValueDto {
+    Null,
+    U64(u64),
+    U64s(Vec<u64>),
+    I64(i64),
+    I64s(Vec<i64>),
+    F64(f64),
+    F64s(Vec<f64>),
+    String(String),
+    Strings(Vec<String>),
+    Boolean(bool),
+    SubQuery(Box<QueryDto>),
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct JoinDto {
+    #[serde(rename = "type")]
+    join_type: JoinTypeDto,
+    #[serde(deserialize_with = "identifier")]
+    table: String,
+    #[serde(deserialize_with = "identifier")]
+    left: String,
+    #[serde(deserialize_with = "join_operator")]
+    operator: String,
+    #[serde(deserialize_with = "identifier")]
+    right: String,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "select_columns"
+    )]
+    select: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum JoinTypeDto {
+    Inner,
+    Left,
+    Right,
+}
+
+impl From<&QueryBuilder> for QueryDto {
+    fn from(query: &QueryBuilder) -> Self {
+        Self {
+            tables: query.tables().clone(),
+            select: query.select_columns().clone(),
+            set: query.set_columns().as_ref().map(|columns| {
+                columns
+                    .iter()
+                    .map(|(column, value)| (column.clone(), value.into()))
+                    .collect()
+            }),
+            wheres: query.where_clauses().iter().map(WhereDto::from).collect(),
+            joins: query.joins().iter().flatten().map(JoinDto::from).collect(),
+            cache_ttl_ms: query.cache_ttl().map(|ttl| ttl.as_millis() as u64),
+        }
+    }
+}
+
+impl From<QueryBuilder> for QueryDto {
+    fn from(query: QueryBuilder) -> Self {
+        Self::from(&query)
+    }
+}
+
+impl From<QueryDto> for QueryBuilder {
+    fn from(dto: QueryDto) -> Self {
+        let mut query = QueryBuilder::new(dto.tables);
+        if let Some(columns) = dto.select {
+            query.select_multiple(&columns.iter().map(|c| c.as_str()).collect::<Vec<_>>());
+        }
+        for (column, value) in dto.set.into_iter().flatten() {
+            query.set(&column, Value::from(value));
+        }
+        for where_clause in dto.wheres {
+            query.where_(where_clause.into());
+        }
+        for join in dto.joins {
+            query.push_join(join.into());
+        }
+        if let Some(ttl) = dto.cache_ttl_ms {
+            query.cache(Duration::from_millis(ttl));
+        }
+        query
+    }
+}
+
+impl From<&WhereJoinOperator> for WhereDto {
+    fn from(where_clause: &WhereJoinOperator) -> Self {
+        let join = match where_clause {
+            WhereJoinOperator::None(_) => WhereJoinDto::None,
+            WhereJoinOperator::And(_) => WhereJoinDto::And,
+            WhereJoinOperator::Or(_) => WhereJoinDto::Or,
+        };
+        let clause = match where_clause.clause() {
+            WhereClause::Condition(condition) => ClauseDto::Condition(condition.into()),
+            WhereClause::Group(group) => {
+                ClauseDto::Group(group.iter().map(WhereDto::from).collect())
+            }
+        };
+        Self { join, clause }
+    }
+}
+
+impl From<WhereDto> for WhereJoinOperator {
+    fn from(dto: WhereDto) -> Self {
+        let clause = match dto.clause {
+            ClauseDto::Condition(condition) => WhereClause::Condition(condition.into()),
+            ClauseDto::Group(group) => {
+                WhereClause::Group(group.into_iter().map(WhereJoinOperator::from).collect())
+            }
+        };
+        match dto.join {
+            WhereJoinDto::None => Self::None(clause),
+            WhereJoinDto::And => Self::And(clause),
+            WhereJoinDto::Or => Self::Or(clause),
+        }
+    }
+}
+
+impl From<&Condition> for ConditionDto {
+    fn from(condition: &Condition) -> Self {
+        Self {
+            column: condition.column().clone(),
+            operator: condition.operator().into(),
+            value: condition.value().into(),
+        }
+    }
+}
+
+impl From<Condition> for ConditionDto {
+    fn from(condition: Condition) -> Self {
+        Self::from(&condition)
+    }
+}
+
+impl From<ConditionDto> for Condition {
+    fn from(dto: ConditionDto) -> Self {
+        Condition::new(&dto.column, dto.operator.into(), Value::from(dto.value))
+    }
+}
+
+impl From<Operator> for OperatorDto {
+    fn from(operator: Operator) -> Self {
+        Self::from(&operator)
+    }
+}
+
+impl From<&Operator> for OperatorDto {
+    fn from(operator: &Operator) -> Self {
+        match operator {
+            Operator::Equal => Self::Eq,
+            Operator::NotEqual => Self::Neq,
+            Operator::Greater => Self::Gt,
+            Operator::NotGreater => Self::Ngt,
+            Operator::GreaterOrEqual => Self::Gte,
+            Operator::NotGreaterOrEqual => Self::Ngte,
+            Operator::Less => Self::Lt,
+            Operator::LessOrEqual => Self::Lte,
+            Operator::NotLess => Self::Nlt,
+            Operator::NotLessOrEqual => Self::Nlte,
+            Operator::Like => Self::Like,
+            Operator::NotLike => Self::Nlike,
+            Operator::Null => Self::Null,
+            Operator::NotNull => Self::Nnull,
+            Operator::In => Self::In,
+            Operator::NotIn => Self::Nin,
+        }
+    }
+}
+
+impl From<OperatorDto> for Operator {
+    fn from(dto: OperatorDto) -> Self {
+        match dto {
+            OperatorDto::Eq => Self::Equal,
+            OperatorDto::Neq => Self::NotEqual,
+            OperatorDto::Gt => Self::Greater,
+            OperatorDto::Ngt => Self::NotGreater,
+            OperatorDto::Gte => Self::GreaterOrEqual,
+            OperatorDto::Ngte => Self::NotGreaterOrEqual,
+            OperatorDto::Lt => Self::Less,
+            OperatorDto::Lte => Self::LessOrEqual,
+            OperatorDto::Nlt => Self::NotLess,
+            OperatorDto::Nlte => Self::NotLessOrEqual,
+            OperatorDto::Like => Self::Like,
+            OperatorDto::Nlike => Self::NotLike,
+            OperatorDto::Null => Self::Null,
+            OperatorDto::Nnull => Self::NotNull,
+            OperatorDto::In => Self::In,
+            OperatorDto::Nin => Self::NotIn,
+        }
+    }
+}
+
+impl From<&Value> for ValueDto {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::U64(v) => Self::U64(*v),
+            Value::U64s(v) => Self::U64s(v.clone()),
+            Value::I64(v) => Self::I64(*v),
+            Value::I64s(v) => Self::I64s(v.clone()),
+            Value::F64(v) => Self::F64(*v),
+            Value::F64s(v) => Self::F64s(v.clone()),
+            Value::String(v) => Self::String(v.clone()),
+            Value::Strings(v) => Self::Strings(v.clone()),
+            Value::Boolean(v) => Self::Boolean(*v),
+            Value::SubQuery(query) => Self::SubQuery(Box::new(query.into())),
+        }
+    }
+}
+
+impl From<Value> for ValueDto {
+    fn from(value: Value) -> Self {
+        Self::from(&value)
+    }
+}
+
+impl From<ValueDto> for Value {
+    fn from(dto: ValueDto) -> Self {
+        match dto {
+            ValueDto::Null => Self::Null,
+            ValueDto::U64(v) => Self::U64(v),
+            ValueDto::U64s(v) => Self::U64s(v),
+            ValueDto::I64(v) => Self::I64(v),
+            ValueDto::I64s(v) => Self::I64s(v),
+            ValueDto::F64(v) => Self::F64(v),
+            ValueDto::F64s(v) => Self::F64s(v),
+            ValueDto::String(v) => Self::String(v),
+            ValueDto::Strings(v) => Self::Strings(v),
+            ValueDto::Boolean(v) => Self::Boolean(v),
+            ValueDto::SubQuery(query) => Self::SubQuery((*query).into()),
+        }
+    }
+}
+
+impl From<&JoinQueryBuilder> for JoinDto {
+    fn from(join: &JoinQueryBuilder) -> Self {
+        Self {
+            join_type: match join.join_type() {
+                JoinType::Inner => JoinTypeDto::Inner,
+                JoinType::Left => JoinTypeDto::Left,
+                JoinType::Right => JoinTypeDto::Right,
+            },
+            table: join.table().to_owned(),
+            left: join.left_table().to_owned(),
+            operator: join.operator().to_owned(),
+            right: join.right_table().to_owned(),
+            select: join.select_columns().clone(),
+        }
+    }
+}
+
+impl From<JoinQueryBuilder> for JoinDto {
+    fn from(join: JoinQueryBuilder) -> Self {
+        Self::from(&join)
+    }
+}
+
+impl From<JoinDto> for JoinQueryBuilder {
+    fn from(dto: JoinDto) -> Self {
+        let select: Option<Vec<&str>> = dto
+            .select
+            .as_ref()
+            .map(|columns| columns.iter().map(|c| c.as_str()).collect());
+        JoinQueryBuilder::new(
+            &dto.table,
+            &dto.left,
+            &dto.operator,
+            &dto.right,
+            dto.join_type.into(),
+            select.as_deref(),
+        )
+    }
+}
+
+impl From<JoinTypeDto> for JoinType {
+    fn from(dto: JoinTypeDto) -> Self {
+        match dto {
+            JoinTypeDto::Inner => Self::Inner,
+            JoinTypeDto::Left => Self::Left,
+            JoinTypeDto::Right => Self::Right,
+        }
+    }
+}
+
+/// A table or column name, optionally qualified: `table`, `table.column`, `table.*` or `*`
+pub fn is_valid_identifier(name: &str) -> bool {
+    if name == "*" {
+        return true;
+    }
+
+    let mut parts = name.split('.');
+    let first = parts.next().unwrap_or_default();
+    let second = parts.next();
+
+    if parts.next().is_some() || !is_valid_name(first) {
+        return false;
+    }
+
+    match second {
+        Some(second) => second == "*" || is_valid_name(second),
+        None => true,
+    }
+}
+
+/// An identifier with an optional alias: `table.column AS alias`
+pub fn is_valid_select_column(column: &str) -> bool {
+    let pieces: Vec<&str> = column.split_whitespace().collect();
+    match pieces.as_slice() {
+        [identifier] => is_valid_identifier(identifier),
+        [identifier, as_keyword, alias] => {
+            as_keyword.eq_ignore_ascii_case("as")
+                && is_valid_identifier(identifier)
+                && is_valid_name(alias)
+        }
+        _ => false,
+    }
+}
+
+pub fn is_valid_join_operator(operator: &str) -> bool {
+    JOIN_OPERATORS.contains(&operator)
+}
+
+fn identifier<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    if is_valid_identifier(&name) {
+        Ok(name)
+    } else {
+        Err(D::Error::custom(format!("invalid identifier `{}`", name)))
+    }
+}
+
+fn identifiers<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let names = Vec::<String>::deserialize(deserializer)?;
+    match names.iter().find(|name| !is_valid_identifier(name)) {
+        Some(name) => Err(D::Error::custom(format!("invalid identifier `{}`", name))),
+        None => Ok(names),
+    }
+}
+
+fn select_columns<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    let columns = Option::<Vec<String>>::deserialize(deserializer)?;
+    if let Some(column) = columns
+        .iter()
+        .flatten()
+        .find(|column| !is_valid_select_column(column))
+    {
+        return Err(D::Error::custom(format!(
+            "invalid select column `{}`",
+            column
+        )));
+    }
+
+    Ok(columns)
+}
+
+fn set_columns<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<HashMap<String, T>>, D::Error> {
+    let columns = Option::<HashMap<String, T>>::deserialize(deserializer)?;
+    if let Some(column) = columns
+        .iter()
+        .flat_map(|c| c.keys())
+        .find(|column| !is_valid_identifier(column))
+    {
+        return Err(D::Error::custom(format!("invalid identifier `{}`", column)));
+    }
+
+    Ok(columns)
+}
+
+fn join_operator<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let operator = String::deserialize(deserializer)?;
+    if is_valid_join_operator(&operator) {
+        Ok(operator)
+    } else {
+        Err(D::Error::custom(format!(
+            "invalid join operator `{}`",
+            operator
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::mysql::mysql_schema_manager::tests::offline_manager;
+
+    #[test]
+    fn identifiers() {
+        assert!(is_valid_identifier("users"));
+        assert!(is_valid_identifier("users.*"));
+        assert!(is_valid_identifier("_core_users.internal_id"));
+        assert!(!is_valid_identifier("users; DROP TABLE users"));
+        assert!(!is_valid_identifier("a.b.c"));
+        assert!(!is_valid_identifier("1users"));
+        assert!(is_valid_select_column("pivot.owner_id AS owner"));
+        assert!(!is_valid_select_column("count(*)"));
+    }
+
+    #[test]
+    fn query_round_trip() {
+        let mut query = QueryBuilder::new(vec!["users".to_owned()]);
+        query
+            .select_multiple(&["users.id", "users.name"])
+            .eq("age", 5)
+            .or_where_group(|group| {
+                group.like("name", "a%").and_is_in("role", vec!["a", "b"]);
+            })
+            .left_join("roles", "roles.id", "=", "users.role")
+            .cache(Duration::from_secs(5));
+
+        let json = SerializedQuery::new(query).to_json().unwrap();
+        let restored = SerializedQuery::from_json(&json).unwrap();
+
+        assert_eq!(restored.version, QUERY_FORMAT_VERSION);
+        assert_eq!(restored.query.cache_ttl(), Some(Duration::from_secs(5)));
+        assert_eq!(
+            offline_manager().to_sql(&restored.query).0,
+            "SELECT users.id,users.name, roles.* FROM users left join roles on roles.id = users.role WHERE age = ? OR (name LIKE ? ESCAPE '!' AND role IN (?,?))"
+        );
+    }
+
+    #[test]
+    fn invalid_identifiers_are_rejected() {
+        let query = QueryBuilder::new(vec!["users".to_owned()]);
+        let json = SerializedQuery::new(query).to_json().unwrap();
+
+        let bad_table = json.replace("\"users\"", "\"users;--\"");
+        assert!(SerializedQuery::from_json(&bad_table).is_err());
+
+        let bad_version = json.replace("\"version\":1", "\"version\":99");
+        assert!(SerializedQuery::from_json(&bad_version).is_err());
+    }
+
+    #[test]
+    fn queries_embed_in_other_types() {
+        #[derive(Serialize, Deserialize)]
+        struct SavedView {
+            name: String,
+            query: QueryBuilder,
+        }
+
+        let mut query = QueryBuilder::new(vec!["users".to_owned()]);
+        query.eq("age", 5);
+        let json = serde_json::to_string(&SavedView {
+            name: "adults".to_owned(),
+            query,
+        })
+        .unwrap();
+
+        let view: SavedView = serde_json::from_str(&json).unwrap();
+        assert_eq!(view.name, "adults");
+        assert_eq!(
+            offline_manager().to_sql(&view.query).0,
+            "SELECT users.* FROM users WHERE age = ?"
+        );
+
+        let bad_column = json.replace("\"age\"", "\"age; --\"");
+        assert!(serde_json::from_str::<SavedView>(&bad_column).is_err());
+    }
+
+    #[test]
+    fn version_is_checked_before_the_query() {
+        let other_shape = r#"{"version": 2, "query": {"from": "users"}}"#;
+        let error = SerializedQuery::from_json(other_shape).unwrap_err();
+        assert!(error.to_string().contains("version 2"), "{}", error);
+    }
+
+    #[test]
+    fn wire_format_is_independent_of_the_builder() {
+        let mut query = QueryBuilder::new(vec!["users".to_owned()]);
+        query.ngt("age", 5).cache(Duration::from_millis(1500));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&SerializedQuery::new(query).to_json().unwrap()).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": 1,
+                "query": {
+                    "tables": ["users"],
+                    "where": [{
+                        "join": "none",
+                        "condition": {
+                            "column": "age",
+                            "operator": "ngt",
+                            "value": {"type": "i64", "value": 5}
+                        }
+                    }],
+                    "cache_ttl_ms": 1500
+                }
+            })
+        );
+    }
+}
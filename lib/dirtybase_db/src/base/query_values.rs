@@ -1,6 +1,12 @@
-use super::query::QueryBuilder;
+use std::collections::HashMap;
 
-#[derive(Debug)]
+use super::{query::QueryBuilder, query_serde::ValueDto};
+use serde::{Deserialize, Serialize};
+
+pub type ColumnAndValue = HashMap<String, Value>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "ValueDto", try_from = "ValueDto")]
 pub enum Value {
     Null,
     U64(u64),
@@ -52,6 +58,12 @@ impl FromIterator<u32> for Value {
     }
 }
 
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Self::U64(value)
+    }
+}
+
 impl From<f32> for Value {
     fn from(value: f32) -> Self {
         Self::F64(value as f64)
@@ -125,18 +137,16 @@ impl Value {
             Self::U64(v) => params.push(v.to_string()),
             Self::I64(v) => params.push(v.to_string()),
             Self::F64(v) => params.push(v.to_string()),
-            Self::String(v) => params.push(format!("'{}'", v)),
+            Self::String(v) => params.push(v.clone()),
             Self::Boolean(v) => {
                 params.push(if *v { 1.to_string() } else { 0.to_string() });
             }
-            Self::U64s(v) => params.push(format!(
-                "({})",
+            Self::U64s(v) => params.extend(
                 v.as_slice()
                     .iter()
                     .map(|x| x.to_string())
-                    .collect::<Vec<String>>()
-                    .join(",")
-            )),
+                    .collect::<Vec<String>>(),
+            ),
             Self::I64s(v) => params.extend(
                 v.as_slice()
                     .iter()
@@ -149,13 +159,7 @@ impl Value {
                     .map(|x| x.to_string())
                     .collect::<Vec<String>>(),
             ),
-            Self::Strings(v) => {
-                let s = v
-                    .iter()
-                    .map(|x| format!("'{}'", x))
-                    .collect::<Vec<String>>();
-                params.extend(s);
-            }
+            Self::Strings(v) => params.extend(v.iter().cloned()),
             Self::SubQuery(_) => {
                 // Do not append. The specific database driver may handle this differently
             }
@@ -0,0 +1,123 @@
+use super::{
+    column::ColumnDefault,
+    helper::generate_ulid,
+    table::{BaseTable, CREATOR_COLUMN, EDITOR_COLUMN},
+};
+
+/// The user a manager writes on behalf of. Fills the `blame` columns
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserContext {
+    id: String,
+}
+
+impl UserContext {
+    pub fn new(id: &str) -> Self {
+        Self { id: id.to_owned() }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// `YYYY-MM-DD hh:mm:ss` in UTC, understood by every driver. MySQL sessions
+/// are connected in UTC so that `CURRENT_TIMESTAMP` agrees
+pub fn current_timestamp() -> String {
+    chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// The values the application generates for a record of the table.
+/// Inserts get ULID and UUID keys, timestamps and their creator, updates
+/// a new `updated_at` and their editor. Columns that are set are kept
+pub fn generated_values(
+    table: &BaseTable,
+    is_set: impl Fn(&str) -> bool,
+    is_update: bool,
+    user: Option<&UserContext>,
+) -> Vec<(String, String)> {
+    let mut values = Vec::new();
+    let mut now = None;
+
+    for column in table.columns() {
+        if is_set(&column.name) {
+            continue;
+        }
+
+        let value = match (&column.default, is_update) {
+            (Some(ColumnDefault::Ulid), false) => Some(generate_ulid()),
+            (Some(ColumnDefault::Uuid), false) => Some(uuid::Uuid::new_v4().to_string()),
+            (Some(ColumnDefault::CreatedAt), false) | (Some(ColumnDefault::UpdatedAt), _) => {
+                Some(now.get_or_insert_with(current_timestamp).clone())
+            }
+            _ => {
+                let blame = if is_update {
+                    EDITOR_COLUMN
+                } else {
+                    CREATOR_COLUMN
+                };
+                user.filter(|_| column.name == blame)
+                    .map(|user| user.id().to_owned())
+            }
+        };
+
+        if let Some(value) = value {
+            values.push((column.name.clone(), value));
+        }
+    }
+
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn posts() -> BaseTable {
+        let mut table = BaseTable::new("posts");
+        table.id_set();
+        table.uuid("token").default_is_uuid();
+        table.string("title");
+        table.blame();
+        table.timestamps();
+        table
+    }
+
+    fn names(values: &[(String, String)]) -> Vec<&str> {
+        values.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn inserts_get_keys_timestamps_and_creator() {
+        let user = UserContext::new("01GRQJ3ZRX5A8B9C0D1E2F3G4H");
+        let values = generated_values(&posts(), |c| c == "title", false, Some(&user));
+
+        assert_eq!(
+            names(&values),
+            vec!["id", "token", "creator", "created_at", "updated_at"]
+        );
+        assert_eq!(values[0].1.len(), 26);
+        assert_eq!(values[1].1.len(), 36);
+        assert_eq!(values[2].1, user.id());
+        assert_eq!(values[3].1, values[4].1);
+    }
+
+    #[test]
+    fn updates_only_touch_updated_at_and_editor() {
+        let user = UserContext::new("01GRQJ3ZRX5A8B9C0D1E2F3G4H");
+        let values = generated_values(&posts(), |c| c == "internal_id", true, Some(&user));
+        assert_eq!(names(&values), vec!["editor", "updated_at"]);
+
+        let values = generated_values(&posts(), |c| c == "updated_at", true, None);
+        assert!(values.is_empty());
+    }
+
+    #[test]
+    fn live_id_sets_get_ulids() {
+        let mut live = posts();
+        live.columns.iter_mut().for_each(|c| c.default = None);
+        live.detect_id_set();
+
+        let values = generated_values(&live, |_| false, false, None);
+        assert_eq!(names(&values), vec!["id"]);
+    }
+}
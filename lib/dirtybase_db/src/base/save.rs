@@ -1,18 +1,38 @@
 use std::collections::HashMap;
 
-pub struct SaveRecord {
+use super::{
+    manager::Manager,
+    query::QueryBuilder,
+    query_values::{ColumnAndValue, Value},
+    record_defaults::generated_values,
+    table::BaseTable,
+    validation::validate_record,
+};
+
+pub struct SaveRecord<'a> {
+    manager: &'a Manager,
     table: String,
     columns: HashMap<String, String>,
+    definition: Option<BaseTable>,
 }
 
-impl SaveRecord {
-    pub fn new(table: &str) -> Self {
+impl<'a> SaveRecord<'a> {
+    pub fn new(manager: &'a Manager, table: &str) -> Self {
         Self {
+            manager,
             table: table.to_owned(),
             columns: HashMap::new(),
+            definition: None,
         }
     }
 
+    /// Validates the record against this definition instead of the one
+    /// registered for the table
+    pub fn validate_with(&mut self, definition: BaseTable) -> &mut Self {
+        self.definition = Some(definition);
+        self
+    }
+
     pub fn set(&mut self, column: &str, value: String) -> &mut Self {
         self.columns.insert(column.to_owned(), value);
         self
@@ -23,10 +43,62 @@ impl SaveRecord {
         self
     }
 
-    pub async fn save(&self) {
+    /// Inserts the record, or updates it when `internal_id` is set.
+    /// Keys, timestamps and blame columns are filled in when missing.
+    /// The record is validated against the rules of the table's registered
+    /// definition first, the error is a `ValidationErrors` when it fails.
+    /// Without a definition the values are generated from the live table,
+    /// cached in the manager's registry
+    pub async fn save(&self) -> anyhow::Result<()> {
+        let is_update = self.columns.contains_key("internal_id");
+        let registered;
+        let definition = match &self.definition {
+            Some(definition) => Some(definition),
+            None => {
+                registered = self.manager.registered_table(&self.table);
+                registered.as_ref()
+            }
+        };
+        if let Some(definition) = definition {
+            validate_record(definition, &self.columns, is_update)?;
+        }
+
+        let live;
+        let definition = match definition {
+            Some(definition) => Some(definition),
+            None => {
+                live = self.manager.live_table(&self.table).await?;
+                live.as_ref()
+            }
+        };
+
+        let mut record: ColumnAndValue = self
+            .columns
+            .iter()
+            .map(|(column, value)| (column.clone(), Value::from(value.as_str())))
+            .collect();
+        if let Some(definition) = definition {
+            let generated = generated_values(
+                definition,
+                |column| self.columns.contains_key(column),
+                is_update,
+                self.manager.user(),
+            );
+            for (column, value) in generated {
+                record.insert(column, Value::from(value));
+            }
+        }
+
         match self.columns.get("internal_id") {
-            Some(id) => println!("updating record with ID: {} in {}", id, &self.table),
-            None => println!("Inserting new record into {}", &self.table),
+            Some(id) => {
+                record.remove("internal_id");
+                let mut query = QueryBuilder::new(vec![self.table.clone()]);
+                query.set_multiple(record).eq("internal_id", id.as_str());
+                self.manager.inner_ref().update(query).await?;
+            }
+            None => self.manager.inner_ref().insert(&self.table, record).await?,
         }
+
+        Ok(())
     }
 }
@@ -1,26 +1,83 @@
 use async_trait::async_trait;
-use sqlx::{any::AnyKind, MySql, Pool};
-use std::sync::Arc;
+use sqlx::any::AnyKind;
+use std::{sync::Arc, time::Duration};
 
-use super::{query::QueryBuilder, table::BaseTable};
+use super::{
+    query::QueryBuilder, query_cache::QueryCache, query_values::ColumnAndValue,
+    schema_diff::SchemaDiff, table::BaseTable,
+};
 
 #[async_trait]
-pub trait SchemaManagerTrait {
-    fn instance(db_pool: Arc<Pool<MySql>>) -> Self
-    where
-        Self: Sized;
-
+pub trait SchemaManagerTrait: Send + Sync {
     fn kind(&self) -> AnyKind;
 
-    // update an existing table
-    fn fetch_table_for_update(&self, name: &str) -> BaseTable;
+    // shared cache for queries that opt in with `QueryBuilder::cache`
+    fn set_query_cache(&mut self, cache: Arc<QueryCache>);
+
+    // send reads to the primary once this instance has written
+    fn set_read_your_writes(&mut self, enabled: bool);
+
+    // the live definition of a table, `None` when it does not exist
+    async fn fetch_table(&self, name: &str) -> anyhow::Result<Option<BaseTable>>;
+
+    // the name of every table in the database
+    async fn table_names(&self) -> anyhow::Result<Vec<String>>;
+
+    // update an existing table. A table that does not exist comes back empty
+    async fn fetch_table_for_update(&self, name: &str) -> anyhow::Result<BaseTable>;
 
     // commit schema changes
-    async fn commit(&self, table: BaseTable);
+    async fn commit(&self, table: BaseTable) -> anyhow::Result<()>;
+
+    // the DDL that commit would run for the table, without running it.
+    // `live` is the existing table, `None` when it is created
+    fn table_statements(&self, table: &BaseTable, live: Option<&BaseTable>) -> Vec<String>;
+
+    // run the changes of a diff against the live table
+    async fn apply_diff(&self, diff: &SchemaDiff) -> anyhow::Result<()>;
+
+    // drop the table when it exists
+    async fn drop_table(&self, name: &str) -> anyhow::Result<()>;
 
     fn query(&mut self, query_builder: QueryBuilder) -> &dyn SchemaManagerTrait;
 
-    async fn fetch_all_as_json(&self) -> Vec<serde_json::Value>;
+    // the rows of the active query
+    async fn try_fetch_all_as_json(&self) -> anyhow::Result<Vec<serde_json::Value>>;
+
+    // the rows of the query, leaving the active query as it is
+    async fn fetch(&self, query: QueryBuilder) -> anyhow::Result<Vec<serde_json::Value>>;
+
+    // the rows of the active query. A failed query is logged and gives no rows
+    async fn fetch_all_as_json(&self) -> Vec<serde_json::Value> {
+        self.try_fetch_all_as_json().await.unwrap_or_else(|e| {
+            log::error!("could not fetch the rows: {:#}", e);
+            Vec::new()
+        })
+    }
+
+    // insert a new record into the table
+    async fn insert(&self, table_name: &str, record: ColumnAndValue) -> anyhow::Result<()>;
+
+    // update the records matching the query with the query's `set` values
+    async fn update(&self, query: QueryBuilder) -> anyhow::Result<u64>;
+
+    // delete the records matching the query
+    async fn delete(&self, query: QueryBuilder) -> anyhow::Result<u64>;
+
+    // start a transaction on the primary. Every statement runs in it until
+    // it is committed or rolled back
+    async fn begin_transaction(&self) -> anyhow::Result<()>;
+
+    async fn commit_transaction(&self) -> anyhow::Result<()>;
+
+    async fn rollback_transaction(&self) -> anyhow::Result<()>;
+
+    // take a named lock shared by every connection to the database. Held
+    // until it is released or this instance is dropped. SQLite's locks only
+    // reach the connections of this process
+    async fn acquire_lock(&self, name: &str, timeout: Duration) -> anyhow::Result<()>;
+
+    async fn release_lock(&self, name: &str) -> anyhow::Result<()>;
 
     // checks if a table exist in the database
     async fn has_table(&self, name: &str) -> bool;
@@ -0,0 +1,383 @@
+use super::{
+    column::{BaseColumn, ColumnDefault, ColumnType, GeneratedStorage, RelationType},
+    foreign_key::{BaseForeignKey, ForeignKeyAction},
+    index::{BaseIndex, IndexKind, IndexOrder},
+    table::BaseTable,
+};
+
+/// Generates Rust source that recreates the tables with the `Manager` builder API.
+/// Tables are ordered so that referenced tables are created first
+pub fn generate_rust(tables: &[BaseTable]) -> String {
+    let tables = dependency_order(tables);
+
+    let mut source = String::from(
+        "// Generated from an existing database\n\
+         #![allow(unused_imports)]\n\n\
+         use dirtybase_db::base::{column::RelationType, foreign_key::ForeignKeyAction, manager::Manager};\n",
+    );
+
+    for table in &tables {
+        source.push('\n');
+        source.push_str(&table_to_rust(table));
+    }
+
+    source.push_str("\npub async fn setup_tables(manager: &Manager) -> anyhow::Result<()> {\n");
+    for table in &tables {
+        source.push_str(&format!(
+            "    {}(manager).await?;\n",
+            setup_function_name(&table.name)
+        ));
+    }
+    source.push_str("    Ok(())\n}\n");
+
+    source
+}
+
+/// The `setup_<table>_table` function for a single table
+pub fn table_to_rust(table: &BaseTable) -> String {
+    let mut source = format!(
+        "pub async fn {}(manager: &Manager) -> anyhow::Result<()> {{\n    manager\n        .create({:?}, |table| {{\n",
+        setup_function_name(&table.name),
+        table.name
+    );
+    push_lines(&mut source, &builder_lines(table), 12);
+    source.push_str("        })\n        .await\n}\n");
+    source
+}
+
+// One builder call per line. Modifiers continue on the following lines
+pub(crate) fn builder_lines(table: &BaseTable) -> Vec<String> {
+    let mut body = Vec::new();
+
+    let options = [
+        ("set_engine", &table.engine),
+        ("set_charset", &table.charset),
+        ("set_collation", &table.collation),
+        ("set_comment", &table.comment),
+    ];
+    for (setter, value) in options {
+        if let Some(value) = value {
+            body.push(format!("table.{}({:?});", setter, value));
+        }
+    }
+
+    for column in table.columns() {
+        body.push(format!("{};", column_to_rust(table, column)));
+    }
+
+    let auto_key: Vec<String> = table
+        .columns()
+        .iter()
+        .filter(|c| c.column_type == ColumnType::AutoIncrementId)
+        .map(|c| c.name.clone())
+        .collect();
+    let primary_key = table.primary_key_columns();
+    if primary_key != auto_key && !primary_key.is_empty() {
+        body.push(format!("table.primary_key(&{});", str_list(&primary_key)));
+    }
+
+    // indexes backing foreign keys are created by MySQL
+    let keys = table.foreign_key_constraints();
+    let key_names: Vec<&String> = keys.iter().filter_map(|k| k.name.as_ref()).collect();
+    for index in table
+        .indexes
+        .iter()
+        .filter(|i| !i.is_single_unique() && !key_names.contains(&&i.name))
+    {
+        body.push(format!("{};", index_to_rust(index)));
+    }
+
+    for check in &table.checks {
+        body.push(format!(
+            "table.check({:?}, {:?});",
+            check.name, check.expression
+        ));
+    }
+
+    for key in &table.foreign_keys {
+        body.push(format!("{};", foreign_key_to_rust(&table.name, key)));
+    }
+
+    body
+}
+
+// Continuation lines are indented one level deeper
+pub(crate) fn push_lines(source: &mut String, lines: &[String], indent: usize) {
+    for line in lines {
+        for (i, piece) in line.split('\n').enumerate() {
+            let indent = if i == 0 { indent } else { indent + 4 };
+            source.push_str(&format!("{}{}\n", " ".repeat(indent), piece));
+        }
+    }
+}
+
+fn column_to_rust(table: &BaseTable, column: &BaseColumn) -> String {
+    let name = format!("{:?}", column.name);
+    let mut builder = match &column.column_type {
+        ColumnType::AutoIncrementId if column.name == "id" => "table.id(None)".to_owned(),
+        ColumnType::AutoIncrementId => format!("table.id(Some({}))", name),
+        ColumnType::Binary(length) => format!("table.binary({}, {})", name, length),
+        ColumnType::Blob => format!("table.blob({})", name),
+        ColumnType::Boolean => format!("table.boolean({})", name),
+        ColumnType::Char(length) => format!("table.char({}, {})", name, length),
+        ColumnType::Date => format!("table.date({})", name),
+        ColumnType::Datetime => format!("table.datetime({})", name),
+        ColumnType::Decimal { precision, scale } => {
+            format!("table.decimal({}, {}, {})", name, precision, scale)
+        }
+        ColumnType::Enum(values) => format!("table.enumeration({}, &{})", name, str_list(values)),
+        ColumnType::File(relation_type) => {
+            format!(
+                "table.file({}, {})",
+                name,
+                relation_type_to_rust(relation_type)
+            )
+        }
+        ColumnType::Float => format!("table.float({})", name),
+        ColumnType::Integer => format!("table.integer({})", name),
+        ColumnType::Json => format!("table.json({})", name),
+        ColumnType::MediumInteger => format!("table.medium_integer({})", name),
+        ColumnType::Number => format!("table.number({})", name),
+        ColumnType::Relation {
+            relation_type,
+            table_name,
+        } => format!(
+            "table.relation({}, {}, {:?})",
+            name,
+            relation_type_to_rust(relation_type),
+            table_name
+        ),
+        ColumnType::Select(relation_type) => {
+            format!(
+                "table.select({}, {})",
+                name,
+                relation_type_to_rust(relation_type)
+            )
+        }
+        ColumnType::SmallInteger => format!("table.small_integer({})", name),
+        ColumnType::String(255) => format!("table.string({})", name),
+        ColumnType::String(length) => format!("table.sized_string({}, {})", name, length),
+        ColumnType::Text => format!("table.text({})", name),
+        ColumnType::Time => format!("table.time({})", name),
+        ColumnType::Timestamp => format!("table.timestamp({})", name),
+        ColumnType::TinyInteger => format!("table.tiny_integer({})", name),
+        ColumnType::Uuid => format!("table.uuid({})", name),
+    };
+
+    let mut modifiers = Vec::new();
+    if column.is_nullable == Some(true) {
+        modifiers.push(".set_is_nullable(true)".to_owned());
+    }
+    if column.is_unsigned {
+        modifiers.push(".set_is_unsigned(true)".to_owned());
+    }
+    if column.is_unique {
+        modifiers.push(".set_is_unique(true)".to_owned());
+    }
+    if let Some(default) = &column.default {
+        modifiers.push(match default {
+            ColumnDefault::Custom(value) => format!(".set_default({:?})", value),
+            ColumnDefault::EmptyString => ".default_is_empty_string()".to_owned(),
+            ColumnDefault::CreatedAt => ".default_is_created_at()".to_owned(),
+            ColumnDefault::UpdatedAt => ".default_is_updated_at()".to_owned(),
+            ColumnDefault::Zero => ".default_is_zero()".to_owned(),
+            ColumnDefault::EmptyObject => ".default_is_empty_object()".to_owned(),
+            ColumnDefault::EmptyArray => ".default_is_empty_array()".to_owned(),
+            ColumnDefault::Uuid => ".default_is_uuid()".to_owned(),
+            ColumnDefault::Ulid => ".default_is_ulid()".to_owned(),
+        });
+    }
+    // columns usually have the table's collation
+    if let Some(collation) = column
+        .collation
+        .as_ref()
+        .filter(|c| table.collation.as_ref() != Some(c))
+    {
+        modifiers.push(format!(".set_collation({:?})", collation));
+    }
+    if let Some(comment) = &column.comment {
+        modifiers.push(format!(".set_comment({:?})", comment));
+    }
+    if let Some(generated) = &column.generated {
+        modifiers.push(match generated.storage {
+            GeneratedStorage::Virtual => format!(".set_virtual_as({:?})", generated.expression),
+            GeneratedStorage::Stored => format!(".set_stored_as({:?})", generated.expression),
+        });
+    }
+    if let Some(check) = &column.check {
+        modifiers.push(format!(".set_check({:?})", check));
+    }
+    if let Some(key) = &column.relationship {
+        modifiers.push(format!(
+            ".references({:?}, {:?}, {})",
+            key.table(),
+            key.column(),
+            key.cascade_delete()
+        ));
+        if !matches!(
+            key.on_delete(),
+            ForeignKeyAction::Cascade | ForeignKeyAction::NoAction
+        ) {
+            modifiers.push(format!(
+                ".set_on_delete({})",
+                action_to_rust(key.on_delete())
+            ));
+        }
+        if key.on_update() != ForeignKeyAction::NoAction {
+            modifiers.push(format!(
+                ".set_on_update({})",
+                action_to_rust(key.on_update())
+            ));
+        }
+        // generated names are not repeated
+        let generated =
+            BaseForeignKey::generated_name(&table.name, std::slice::from_ref(&column.name));
+        if let Some(name) = key.name().filter(|name| **name != generated) {
+            modifiers.push(format!(".set_foreign_key_name({:?})", name));
+        }
+    }
+
+    if modifiers.len() > 1 {
+        builder.push_str(&format!("\n{}", modifiers.join("\n")));
+    } else {
+        builder.push_str(&modifiers.concat());
+    }
+    builder
+}
+
+fn index_to_rust(index: &BaseIndex) -> String {
+    let builder = match index.kind {
+        IndexKind::Index => "index",
+        IndexKind::Unique => "unique",
+        IndexKind::Fulltext => "fulltext",
+    };
+    let names: Vec<String> = index.columns.iter().map(|c| c.name.clone()).collect();
+    let mut source = format!("table.{}({:?}, &{})", builder, index.name, str_list(&names));
+
+    for column in &index.columns {
+        if let Some(length) = column.length {
+            source.push_str(&format!("\n.set_length({:?}, {})", column.name, length));
+        }
+        if column.order == IndexOrder::Desc {
+            source.push_str(&format!("\n.descending({:?})", column.name));
+        }
+    }
+    source
+}
+
+fn foreign_key_to_rust(table: &str, key: &BaseForeignKey) -> String {
+    let mut source = format!(
+        "table.foreign_key({:?}, &{}, {:?}, &{})",
+        key.name_in(table),
+        str_list(&key.columns),
+        key.table,
+        str_list(&key.references)
+    );
+    if key.on_delete != ForeignKeyAction::NoAction {
+        source.push_str(&format!(
+            "\n.set_on_delete({})",
+            action_to_rust(key.on_delete)
+        ));
+    }
+    if key.on_update != ForeignKeyAction::NoAction {
+        source.push_str(&format!(
+            "\n.set_on_update({})",
+            action_to_rust(key.on_update)
+        ));
+    }
+    source
+}
+
+fn action_to_rust(action: ForeignKeyAction) -> String {
+    format!("ForeignKeyAction::{:?}", action)
+}
+
+fn relation_type_to_rust(relation_type: &RelationType) -> String {
+    match relation_type {
+        RelationType::Single => "RelationType::Single".to_owned(),
+        RelationType::Multiple(limit) => format!("RelationType::Multiple({})", limit),
+    }
+}
+
+fn str_list(values: &[String]) -> String {
+    format!(
+        "[{}]",
+        values
+            .iter()
+            .map(|v| format!("{:?}", v))
+            .collect::<Vec<String>>()
+            .join(", ")
+    )
+}
+
+fn setup_function_name(table: &str) -> String {
+    format!(
+        "setup_{}_table",
+        table.trim_start_matches('_').to_lowercase()
+    )
+}
+
+// Referenced tables first. Tables in a reference cycle keep their name order
+pub(crate) fn dependency_order(tables: &[BaseTable]) -> Vec<&BaseTable> {
+    let mut remaining: Vec<&BaseTable> = tables.iter().collect();
+    remaining.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut ordered: Vec<&BaseTable> = Vec::new();
+    while !remaining.is_empty() {
+        let ready = remaining.iter().position(|table| {
+            table.foreign_key_constraints().iter().all(|key| {
+                key.table == table.name
+                    || ordered.iter().any(|t| t.name == key.table)
+                    || !remaining.iter().any(|t| t.name == key.table)
+            })
+        });
+        ordered.push(remaining.remove(ready.unwrap_or(0)));
+    }
+
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::column::ForeignKey;
+
+    #[test]
+    fn tables_become_builder_calls() {
+        let mut posts = BaseTable::new("posts");
+        posts.id(None);
+        posts.sized_string("title", 100).set_is_unique(true);
+        posts.text("body").set_is_nullable(true);
+        posts.ulid("author").relationship =
+            Some(ForeignKey::new("users", "id", true).with_name("posts_author_foreign"));
+        posts.index("posts_author_foreign", &["author"]);
+        posts
+            .index("posts_title_body", &["title", "body"])
+            .set_length("body", 20);
+
+        let mut users = BaseTable::new("users");
+        users.ulid("id");
+        users.primary_key(&["id"]);
+
+        let source = generate_rust(&[posts, users]);
+
+        assert!(source.contains(
+            "pub async fn setup_posts_table(manager: &Manager) -> anyhow::Result<()> {
+    manager
+        .create(\"posts\", |table| {
+            table.id(None);
+            table.sized_string(\"title\", 100).set_is_unique(true);
+            table.text(\"body\").set_is_nullable(true);
+            table.char(\"author\", 26).references(\"users\", \"id\", true);
+            table.index(\"posts_title_body\", &[\"title\", \"body\"])
+                .set_length(\"body\", 20);
+        })
+        .await
+}"
+        ));
+        assert!(source.contains("table.primary_key(&[\"id\"]);"));
+        assert!(source.contains(
+            "    setup_users_table(manager).await?;\n    setup_posts_table(manager).await?;"
+        ));
+    }
+}
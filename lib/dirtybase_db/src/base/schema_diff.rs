@@ -0,0 +1,649 @@
+use super::{
+    check::BaseCheck,
+    column::{BaseColumn, ColumnDefault, ColumnType},
+    foreign_key::BaseForeignKey,
+    index::BaseIndex,
+    table::BaseTable,
+};
+
+/// A single change needed to bring a live table in line with its definition
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaOperation {
+    DropForeignKey(String),
+    DropIndex(String),
+    DropCheck(String),
+    AddColumn(BaseColumn),
+    // the column's definition changed. `new_name` is set when it is renamed
+    AlterColumn(BaseColumn),
+    DropPrimaryKey,
+    AddPrimaryKey(Vec<String>),
+    AddUnique(String),
+    AddIndex(BaseIndex),
+    AddCheck(BaseCheck),
+    DropColumn(String),
+    AddForeignKey(BaseForeignKey),
+    SetEngine(String),
+    SetCharset(String),
+    SetCollation(String),
+    SetComment(String),
+    RenameTable(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaChange {
+    pub operation: SchemaOperation,
+    // the change can lose data
+    pub is_destructive: bool,
+}
+
+impl SchemaChange {
+    fn new(operation: SchemaOperation) -> Self {
+        Self {
+            operation,
+            is_destructive: false,
+        }
+    }
+
+    fn destructive(operation: SchemaOperation) -> Self {
+        Self {
+            operation,
+            is_destructive: true,
+        }
+    }
+}
+
+/// The ordered changes between a desired table and the live one.
+/// Foreign keys and indexes are dropped first, then columns are added or altered
+/// in definition order, indexes are added, then columns are dropped and new
+/// foreign keys added
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaDiff {
+    pub table: String,
+    pub changes: Vec<SchemaChange>,
+}
+
+impl SchemaDiff {
+    pub fn between(desired: &BaseTable, live: &BaseTable) -> Self {
+        // (name, is_destructive)
+        let mut drop_foreign_keys: Vec<(String, bool)> = desired
+            .dropped_foreign_keys
+            .iter()
+            .map(|name| (name.clone(), false))
+            .collect();
+        let mut drop_indexes = Vec::new();
+        let mut drop_primary_key = Vec::new();
+        let mut columns = Vec::new();
+        let mut add_indexes = Vec::new();
+        let mut drop_columns = Vec::new();
+        let mut add_foreign_keys = Vec::new();
+
+        for column in desired
+            .columns()
+            .iter()
+            .filter(|c| !c.has_pivot_table() && !desired.dropped_columns.contains(&c.name))
+        {
+            let existing = live.find_column(&column.name);
+            let target_name = column.new_name.as_ref().unwrap_or(&column.name);
+
+            match existing {
+                None => columns.push(SchemaChange::new(SchemaOperation::AddColumn(
+                    column.clone(),
+                ))),
+                Some(existing) => {
+                    if column.new_name.is_some()
+                        || column.after.is_some()
+                        || !is_same_definition(existing, column)
+                    {
+                        let loses_data = can_lose_data(existing, column);
+
+                        // redefining a column keeps the comment and collation it has
+                        let mut column = column.clone();
+                        column.comment = column.comment.or_else(|| existing.comment.clone());
+                        column.collation = column.collation.or_else(|| existing.collation.clone());
+                        let operation = SchemaOperation::AlterColumn(column);
+                        columns.push(if loses_data {
+                            SchemaChange::destructive(operation)
+                        } else {
+                            SchemaChange::new(operation)
+                        });
+                    }
+
+                    // a single column unique index is the same as a unique column
+                    let declared = desired
+                        .indexes
+                        .iter()
+                        .find(|i| i.is_unique_on(&column.name));
+                    let wants_unique = column.is_unique || declared.is_some();
+                    if wants_unique && !existing.is_unique {
+                        add_indexes.push(SchemaChange::new(match declared {
+                            Some(index) => SchemaOperation::AddIndex(index.clone()),
+                            None => SchemaOperation::AddUnique(target_name.clone()),
+                        }));
+                    } else if !wants_unique && existing.is_unique {
+                        if let Some(index) =
+                            live.indexes.iter().find(|i| i.is_unique_on(&column.name))
+                        {
+                            drop_indexes.push(index.name.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        // unique columns are handled above and the indexes backing foreign keys
+        // are managed by MySQL
+        let (mut desired_keys, live_keys) = (
+            desired.foreign_key_constraints(),
+            live.foreign_key_constraints(),
+        );
+        desired_keys.retain(|k| {
+            !k.columns
+                .iter()
+                .any(|c| desired.dropped_columns.contains(c))
+        });
+        let key_names: Vec<&String> = live_keys.iter().filter_map(|k| k.name.as_ref()).collect();
+        let is_table_level =
+            |index: &&BaseIndex| !index.is_single_unique() && !key_names.contains(&&index.name);
+
+        for index in desired.indexes.iter().filter(is_table_level) {
+            match live.indexes.iter().find(|i| i.name == index.name) {
+                Some(existing) if existing == index => (),
+                Some(existing) => {
+                    drop_indexes.push(existing.name.clone());
+                    add_indexes.push(SchemaChange::new(SchemaOperation::AddIndex(index.clone())));
+                }
+                None => {
+                    add_indexes.push(SchemaChange::new(SchemaOperation::AddIndex(index.clone())))
+                }
+            }
+        }
+
+        for index in live.indexes.iter() {
+            let is_undefined =
+                is_table_level(&index) && !desired.indexes.iter().any(|i| i.name == index.name);
+            if is_undefined || desired.dropped_indexes.contains(&index.name) {
+                drop_indexes.push(index.name.clone());
+            }
+        }
+
+        for key in &desired_keys {
+            match live_keys.iter().find(|k| is_same_constraint(key, k)) {
+                Some(existing) if existing.is_same_as(key) => (),
+                existing => {
+                    drop_foreign_keys.extend(
+                        existing
+                            .and_then(|k| k.name.clone())
+                            .map(|name| (name, false)),
+                    );
+                    add_foreign_keys.push(SchemaChange::new(SchemaOperation::AddForeignKey(
+                        key.clone(),
+                    )));
+                }
+            }
+        }
+
+        // live keys that are no longer defined. Those on dropped columns go with them
+        for key in live_keys
+            .iter()
+            .filter(|k| !desired_keys.iter().any(|d| is_same_constraint(d, k)))
+        {
+            let loses_column = key.columns.iter().any(|column| {
+                desired.find_column(column).is_none() || desired.dropped_columns.contains(column)
+            });
+            drop_foreign_keys.extend(key.name.clone().map(|name| (name, loses_column)));
+        }
+
+        let mut drop_checks = Vec::new();
+        let mut add_checks = Vec::new();
+        let mut desired_checks = desired.check_constraints();
+        desired_checks.retain(|check| {
+            !desired
+                .dropped_columns
+                .iter()
+                .any(|c| check.name == BaseCheck::generated_name(&desired.name, c))
+        });
+        let live_checks = live.check_constraints();
+        for check in &desired_checks {
+            match live_checks.iter().find(|c| c.name == check.name) {
+                Some(existing) if is_same_expression(&existing.expression, &check.expression) => {}
+                existing => {
+                    drop_checks.extend(existing.map(|c| c.name.clone()));
+                    add_checks.push(SchemaChange::new(SchemaOperation::AddCheck(check.clone())));
+                }
+            }
+        }
+        for check in live_checks.iter() {
+            let is_undefined = !desired_checks.iter().any(|c| c.name == check.name);
+            if is_undefined || desired.dropped_checks.contains(&check.name) {
+                drop_checks.push(check.name.clone());
+            }
+        }
+        drop_checks.dedup();
+
+        let mut primary_key = Vec::new();
+        let (desired_key, live_key) = (desired.primary_key_columns(), live.primary_key_columns());
+        if desired_key != live_key {
+            if !live_key.is_empty() {
+                drop_primary_key.push(SchemaChange::new(SchemaOperation::DropPrimaryKey));
+            }
+            if !desired_key.is_empty() {
+                primary_key.push(SchemaChange::new(SchemaOperation::AddPrimaryKey(
+                    desired_key,
+                )));
+            }
+        }
+
+        // live columns that are no longer defined, or were explicitly dropped
+        for column in live.columns() {
+            let is_defined = desired.find_column(&column.name).is_some()
+                && !desired.dropped_columns.contains(&column.name);
+            if is_defined {
+                continue;
+            }
+
+            drop_columns.push(SchemaChange::destructive(SchemaOperation::DropColumn(
+                column.name.clone(),
+            )));
+        }
+
+        let mut seen = Vec::new();
+        drop_foreign_keys.retain(|(name, _)| {
+            let keep = !seen.contains(name);
+            seen.push(name.clone());
+            keep
+        });
+        drop_indexes.dedup();
+
+        let mut changes: Vec<SchemaChange> = drop_foreign_keys
+            .into_iter()
+            .map(|(name, is_destructive)| SchemaChange {
+                operation: SchemaOperation::DropForeignKey(name),
+                is_destructive,
+            })
+            .collect();
+        changes.extend(
+            drop_indexes
+                .into_iter()
+                .map(|name| SchemaChange::new(SchemaOperation::DropIndex(name))),
+        );
+        changes.extend(
+            drop_checks
+                .into_iter()
+                .map(|name| SchemaChange::new(SchemaOperation::DropCheck(name))),
+        );
+        changes.extend(drop_primary_key);
+        changes.extend(columns);
+        changes.extend(primary_key);
+        changes.extend(add_indexes);
+        changes.extend(add_checks);
+        changes.extend(drop_columns);
+        changes.extend(add_foreign_keys);
+
+        // options that are not set keep their live value. The names of
+        // engines, character sets and collations are case insensitive
+        let options = [
+            (
+                &desired.engine,
+                &live.engine,
+                SchemaOperation::SetEngine as fn(_) -> _,
+            ),
+            (&desired.charset, &live.charset, SchemaOperation::SetCharset),
+            (
+                &desired.collation,
+                &live.collation,
+                SchemaOperation::SetCollation,
+            ),
+        ];
+        for (wanted, current, operation) in options {
+            if let Some(value) = wanted {
+                if !current
+                    .as_ref()
+                    .is_some_and(|c| c.eq_ignore_ascii_case(value))
+                {
+                    changes.push(SchemaChange::new(operation(value.clone())));
+                }
+            }
+        }
+        if let Some(comment) = &desired.comment {
+            if live.comment.as_ref() != Some(comment) {
+                changes.push(SchemaChange::new(SchemaOperation::SetComment(
+                    comment.clone(),
+                )));
+            }
+        }
+
+        if let Some(new_name) = &desired.new_name {
+            changes.push(SchemaChange::new(SchemaOperation::RenameTable(
+                new_name.clone(),
+            )));
+        }
+
+        Self {
+            table: live.name.clone(),
+            changes,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn has_destructive(&self) -> bool {
+        self.changes.iter().any(|c| c.is_destructive)
+    }
+
+    /// Splits the diff into the safe changes and the destructive ones
+    pub fn split_destructive(self) -> (SchemaDiff, Vec<SchemaChange>) {
+        let (destructive, safe) = self.changes.into_iter().partition(|c| c.is_destructive);
+        (
+            Self {
+                table: self.table,
+                changes: safe,
+            },
+            destructive,
+        )
+    }
+}
+
+// Named constraints are matched by name, the others by their columns
+fn is_same_constraint(desired: &BaseForeignKey, live: &BaseForeignKey) -> bool {
+    match &desired.name {
+        Some(name) => live.name.as_ref() == Some(name),
+        None => live.columns == desired.columns,
+    }
+}
+
+// Uniqueness and foreign keys are compared separately. The comment and
+// collation only when they are set
+fn is_same_definition(live: &BaseColumn, desired: &BaseColumn) -> bool {
+    live.column_type.storage_type() == desired.column_type.storage_type()
+        && live.is_unsigned == desired.is_unsigned
+        && live.is_nullable == desired.is_nullable
+        && database_default(live) == database_default(desired)
+        && (desired.comment.is_none() || desired.comment == live.comment)
+        && (desired.collation.is_none() || desired.collation == live.collation)
+        && match (&live.generated, &desired.generated) {
+            (Some(live), Some(desired)) => {
+                live.storage == desired.storage
+                    && is_same_expression(&live.expression, &desired.expression)
+            }
+            (live, desired) => live.is_none() && desired.is_none(),
+        }
+}
+
+fn database_default(column: &BaseColumn) -> Option<&ColumnDefault> {
+    column.default.as_ref().filter(|d| d.is_database_default())
+}
+
+// MySQL stores expressions reformatted: quoted, parenthesized and with
+// charset introducers on string literals
+fn is_same_expression(live: &str, desired: &str) -> bool {
+    normalized_expression(live) == normalized_expression(desired)
+}
+
+// String literals are kept as they are, only the rest is lowercased and
+// loses its whitespace. Inner parentheses can change the meaning, only
+// those around the whole expression are dropped
+fn normalized_expression(expression: &str) -> String {
+    let mut normalized = String::new();
+    let mut chars = expression.chars().peekable();
+    let mut previous = ' ';
+    let mut in_literal = false;
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            in_literal = !in_literal;
+        }
+        if in_literal || c == '\'' {
+            normalized.push(c);
+            previous = c;
+            continue;
+        }
+        // `_utf8mb4'value'` is `'value'`
+        if c == '_' && !(previous.is_alphanumeric() || previous == '_') {
+            let introducer: String = chars
+                .clone()
+                .take_while(|c| c.is_ascii_alphanumeric())
+                .collect();
+            let rest = chars.clone().nth(introducer.len());
+            if !introducer.is_empty() && rest == Some('\'') {
+                for _ in 0..introducer.len() {
+                    chars.next();
+                }
+                continue;
+            }
+        }
+        if c != '`' && !c.is_whitespace() {
+            normalized.extend(c.to_lowercase());
+        }
+        previous = c;
+    }
+
+    let mut expression = normalized.as_str();
+    while let Some(inner) = without_outer_parentheses(expression) {
+        expression = inner;
+    }
+    expression.to_owned()
+}
+
+// `(a + b)` is `a + b`, `(a) + (b)` has no outer parentheses
+fn without_outer_parentheses(expression: &str) -> Option<&str> {
+    let inner = expression.strip_prefix('(')?.strip_suffix(')')?;
+    let mut depth = 0;
+    let mut in_literal = false;
+    for c in inner.chars() {
+        match c {
+            '\'' => in_literal = !in_literal,
+            '(' if !in_literal => depth += 1,
+            ')' if !in_literal => {
+                if depth == 0 {
+                    return None;
+                }
+                depth -= 1;
+            }
+            _ => (),
+        }
+    }
+    Some(inner)
+}
+
+fn can_lose_data(live: &BaseColumn, desired: &BaseColumn) -> bool {
+    use ColumnType::*;
+
+    let (from, to) = (
+        live.column_type.storage_type(),
+        desired.column_type.storage_type(),
+    );
+    let type_loses_data = match (&from, &to) {
+        (from, to) if from == to => false,
+        (String(from), String(to)) | (Char(from), Char(to)) | (Char(from), String(to)) => to < from,
+        (Binary(from), Binary(to)) => to < from,
+        (String(_) | Char(_) | Enum(_), Text) | (Binary(_), Blob) => false,
+        // an enum can gain values
+        (Enum(from), Enum(to)) => from.iter().any(|value| !to.contains(value)),
+        (from, to) if from.is_numeric() || *from == Boolean => integer_rank(from)
+            .zip(integer_rank(to))
+            .map_or(!matches!(to, Number | Decimal { .. }), |(from, to)| {
+                to < from
+            }),
+        _ => true,
+    };
+
+    type_loses_data
+        || (live.is_unsigned != desired.is_unsigned && from.is_numeric())
+        || (live.is_nullable == Some(true) && desired.is_nullable == Some(false))
+}
+
+// Integer types from narrowest to widest
+fn integer_rank(column_type: &ColumnType) -> Option<u8> {
+    match column_type {
+        ColumnType::Boolean => Some(0),
+        ColumnType::TinyInteger => Some(1),
+        ColumnType::SmallInteger => Some(2),
+        ColumnType::MediumInteger => Some(3),
+        ColumnType::Integer | ColumnType::AutoIncrementId => Some(4),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::column::ForeignKey;
+
+    fn live_posts() -> BaseTable {
+        let mut live = BaseTable::new("posts");
+        live.set_is_new(false);
+        live.id(None);
+        live.sized_string("title", 100);
+        live.string("body").set_is_nullable(true);
+        live.ulid("author").relationship =
+            Some(ForeignKey::new("users", "id", true).with_name("posts_author_foreign"));
+        live
+    }
+
+    #[test]
+    fn identical_tables_have_no_changes() {
+        let live = live_posts();
+        assert!(SchemaDiff::between(&live.clone(), &live).is_empty());
+    }
+
+    #[test]
+    fn changes_are_ordered_and_flagged() {
+        let mut desired = BaseTable::new("posts");
+        desired.id(None);
+        desired.string("title");
+        desired.text("body").set_is_nullable(false);
+        desired.boolean("published");
+        desired
+            .ulid("author")
+            .references_without_cascade_delete("users", "id");
+
+        let diff = SchemaDiff::between(&desired, &live_posts());
+        let operations: Vec<(&SchemaOperation, bool)> = diff
+            .changes
+            .iter()
+            .map(|c| (&c.operation, c.is_destructive))
+            .collect();
+
+        assert_eq!(
+            operations[0],
+            (
+                &SchemaOperation::DropForeignKey("posts_author_foreign".to_owned()),
+                false
+            )
+        );
+        assert!(
+            matches!(operations[1], (SchemaOperation::AlterColumn(c), false) if c.name == "title")
+        );
+        assert!(
+            matches!(operations[2], (SchemaOperation::AlterColumn(c), true) if c.name == "body")
+        );
+        assert!(
+            matches!(operations[3], (SchemaOperation::AddColumn(c), false) if c.name == "published")
+        );
+        assert!(matches!(
+            operations[4],
+            (SchemaOperation::AddForeignKey(key), false) if key.columns == ["author"]
+        ));
+        assert_eq!(operations.len(), 5);
+    }
+
+    #[test]
+    fn indexes_are_added_replaced_and_dropped() {
+        let mut live = live_posts();
+        live.index("posts_title_body", &["title", "body"]);
+        live.index("posts_old", &["body"]);
+        live.index("posts_author_foreign", &["author"]);
+
+        let mut desired = live_posts();
+        desired
+            .index("posts_title_body", &["title", "body"])
+            .descending("body");
+        desired.fulltext("posts_search", &["title", "body"]);
+        desired.unique("posts_title_unique", &["title"]);
+
+        let operations: Vec<SchemaOperation> = SchemaDiff::between(&desired, &live)
+            .changes
+            .into_iter()
+            .map(|c| c.operation)
+            .collect();
+
+        assert_eq!(
+            operations,
+            vec![
+                SchemaOperation::DropIndex("posts_title_body".to_owned()),
+                SchemaOperation::DropIndex("posts_old".to_owned()),
+                SchemaOperation::AddIndex(desired.indexes[2].clone()),
+                SchemaOperation::AddIndex(desired.indexes[0].clone()),
+                SchemaOperation::AddIndex(desired.indexes[1].clone()),
+            ]
+        );
+    }
+
+    #[test]
+    fn expressions_are_compared_as_mysql_stores_them() {
+        assert!(is_same_expression("(`price` >= 0)", "price >= 0"));
+        assert!(is_same_expression(
+            "json_unquote(json_extract(`meta`,_utf8mb4'$.size'))",
+            "JSON_UNQUOTE(JSON_EXTRACT(meta, '$.size'))"
+        ));
+        assert!(!is_same_expression("(`price` > 0)", "price >= 0"));
+        assert!(is_same_expression("((a + b))", "a+b"));
+        assert!(!is_same_expression("(a + b) * c", "a + b * c"));
+        assert!(!is_same_expression("(a) + (b)", "a) + (b"));
+        assert!(!is_same_expression("`status` = 'A'", "status = 'a'"));
+        assert!(!is_same_expression("name = 'a b'", "name = 'ab'"));
+    }
+
+    #[test]
+    fn narrowing_types_is_destructive() {
+        let column = |column_type: ColumnType| BaseColumn::new("a", column_type);
+
+        assert!(!can_lose_data(
+            &column(ColumnType::SmallInteger),
+            &column(ColumnType::Integer)
+        ));
+        assert!(can_lose_data(
+            &column(ColumnType::Integer),
+            &column(ColumnType::TinyInteger)
+        ));
+        assert!(!can_lose_data(
+            &column(ColumnType::Float),
+            &column(ColumnType::Number)
+        ));
+        assert!(can_lose_data(
+            &column(ColumnType::Number),
+            &column(ColumnType::Integer)
+        ));
+        assert!(!can_lose_data(
+            &column(ColumnType::Char(36)),
+            &column(ColumnType::Uuid)
+        ));
+        assert!(can_lose_data(
+            &column(ColumnType::Enum(vec!["a".to_owned(), "b".to_owned()])),
+            &column(ColumnType::Enum(vec!["a".to_owned()]))
+        ));
+    }
+
+    #[test]
+    fn undefined_columns_are_dropped() {
+        let mut desired = live_posts();
+        desired.drop_column("body");
+        desired.columns.retain(|c| c.name != "author");
+
+        let diff = SchemaDiff::between(&desired, &live_posts());
+        assert!(diff.has_destructive());
+
+        let (safe, destructive) = diff.split_destructive();
+        assert!(safe.is_empty());
+        assert_eq!(
+            destructive[0].operation,
+            SchemaOperation::DropForeignKey("posts_author_foreign".to_owned())
+        );
+        assert_eq!(
+            destructive[1].operation,
+            SchemaOperation::DropColumn("body".to_owned())
+        );
+        assert_eq!(destructive.len(), 3);
+    }
+}
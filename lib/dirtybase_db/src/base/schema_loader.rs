@@ -0,0 +1,260 @@
+use super::{
+    helper::{is_safe_expression, is_valid_name},
+    manager::Manager,
+    schema_diff::SchemaChange,
+    table::BaseTable,
+};
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaFormat {
+    Json,
+    Yaml,
+}
+
+impl SchemaFormat {
+    /// The format of a schema file, `None` for files that are not schemas
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+}
+
+// A schema file holds a single table or a list of tables
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SchemaFile {
+    Many(Vec<BaseTable>),
+    One(Box<BaseTable>),
+}
+
+/// Parses the tables defined in a schema file's contents
+pub fn parse_schema(contents: &str, format: SchemaFormat) -> anyhow::Result<Vec<BaseTable>> {
+    let file: SchemaFile = match format {
+        SchemaFormat::Json => serde_json::from_str(contents)?,
+        SchemaFormat::Yaml => serde_yaml::from_str(contents)?,
+    };
+
+    let tables = match file {
+        SchemaFile::Many(tables) => tables,
+        SchemaFile::One(table) => vec![*table],
+    };
+
+    for table in &tables {
+        validate(table)?;
+    }
+
+    Ok(tables)
+}
+
+/// Loads every JSON and YAML schema file in the directory, in file name order
+pub fn load_schema_dir(dir: impl AsRef<Path>) -> anyhow::Result<Vec<BaseTable>> {
+    let mut files: Vec<_> = fs::read_dir(dir.as_ref())?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter_map(|path| SchemaFormat::from_path(&path).map(|format| (path, format)))
+        .collect();
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut tables = Vec::new();
+    for (path, format) in files {
+        let contents = fs::read_to_string(&path)?;
+        tables.extend(
+            parse_schema(&contents, format)
+                .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?,
+        );
+    }
+
+    Ok(tables)
+}
+
+/// Creates or converges every table defined in the directory.
+/// Destructive changes are skipped and returned
+pub async fn apply_schema_dir(
+    manager: &Manager,
+    dir: impl AsRef<Path>,
+) -> anyhow::Result<Vec<SchemaChange>> {
+    let mut skipped = Vec::new();
+    for table in load_schema_dir(dir)? {
+        skipped.extend(manager.converge_table(table, false).await?);
+    }
+
+    Ok(skipped)
+}
+
+// Every name the table's DDL refers to must be an identifier and
+// every expression must be free of statements and comments
+fn validate(table: &BaseTable) -> anyhow::Result<()> {
+    let mut names: Vec<&str> = vec![&table.name];
+    names.extend(
+        [
+            &table.new_name,
+            &table.engine,
+            &table.charset,
+            &table.collation,
+        ]
+        .into_iter()
+        .filter_map(|name| name.as_deref()),
+    );
+    names.extend(table.primary_key.iter().flatten().map(|name| name.as_str()));
+    for column in &table.columns {
+        names.push(&column.name);
+        names.extend(column.referenced_names());
+    }
+    for index in &table.indexes {
+        names.push(&index.name);
+        names.extend(index.columns.iter().map(|c| c.name.as_str()));
+    }
+    for key in &table.foreign_keys {
+        names.extend(key.name.as_deref());
+        names.push(&key.table);
+        names.extend(
+            key.columns
+                .iter()
+                .chain(&key.references)
+                .map(|c| c.as_str()),
+        );
+    }
+    names.extend(table.checks.iter().map(|check| check.name.as_str()));
+
+    if let Some(name) = names.into_iter().find(|name| !is_valid_name(name)) {
+        anyhow::bail!("invalid name `{}` in table `{}`", name, table.name);
+    }
+
+    let expressions = table
+        .columns
+        .iter()
+        .flat_map(|c| c.generated.iter().map(|g| &g.expression).chain(&c.check))
+        .chain(table.checks.iter().map(|check| &check.expression));
+    for expression in expressions {
+        if !is_safe_expression(expression) {
+            anyhow::bail!(
+                "unsafe expression `{}` in table `{}`",
+                expression,
+                table.name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::column::{ColumnDefault, ColumnType, RelationType};
+
+    #[test]
+    fn yaml_schema_is_parsed() {
+        let yaml = "
+name: posts
+columns:
+  - name: id
+    type: auto_increment_id
+  - name: title
+    type:
+      string: 100
+    is_unique: true
+  - name: body
+    type: text
+    is_nullable: true
+  - name: status
+    type:
+      enum: [draft, published]
+    default:
+      custom: draft
+  - name: author
+    type:
+      relation:
+        relation_type: single
+        table_name: users
+    relationship:
+      table: users
+      column: id
+      on_delete: cascade
+indexes:
+  - name: posts_status_title
+    kind: index
+    columns:
+      - name: status
+      - name: title
+        length: 10
+        order: desc
+";
+        let tables = parse_schema(yaml, SchemaFormat::Yaml).unwrap();
+        let table = &tables[0];
+
+        assert!(table.is_new());
+        assert_eq!(table.columns.len(), 5);
+        assert_eq!(table.columns[1].column_type, ColumnType::String(100));
+        assert_eq!(table.columns[0].is_nullable, Some(false));
+        assert_eq!(table.columns[2].is_nullable, Some(true));
+        assert_eq!(
+            table.columns[3].default,
+            Some(ColumnDefault::Custom("draft".to_owned()))
+        );
+        assert!(table.columns[4]
+            .relationship
+            .as_ref()
+            .unwrap()
+            .cascade_delete());
+        assert_eq!(table.indexes[0].columns[1].length, Some(10));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let mut table = BaseTable::new("tags");
+        table.id_set();
+        table.string("name");
+        table.relation("posts", RelationType::Multiple(0), "posts");
+        table.unique("tags_name", &["name"]);
+
+        let json = serde_json::to_string(&vec![table.clone()]).unwrap();
+        let tables = parse_schema(&json, SchemaFormat::Json).unwrap();
+
+        assert_eq!(tables[0].columns, table.columns);
+        assert_eq!(tables[0].indexes, table.indexes);
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        let json = r#"{"name": "posts; drop", "columns": []}"#;
+        assert!(parse_schema(json, SchemaFormat::Json).is_err());
+    }
+
+    #[test]
+    fn foreign_keys_collations_and_expressions_are_checked() {
+        let column = |extra: &str| {
+            format!(
+                r#"{{"name": "posts", "columns": [{{"name": "author", "type": "integer"{}}}]}}"#,
+                extra
+            )
+        };
+
+        for extra in [
+            r#", "relationship": {"table": "users` (id); DROP TABLE x; --", "column": "id"}"#,
+            r#", "relationship": {"table": "users", "column": "id)"}"#,
+            r#", "collation": "utf8mb4_bin; DROP TABLE x""#,
+            r#", "check": "author > 0); DROP TABLE x; --""#,
+            r#", "generated": {"expression": "(SELECT 1)", "storage": "virtual"}"#,
+        ] {
+            assert!(
+                parse_schema(&column(extra), SchemaFormat::Json).is_err(),
+                "{}",
+                extra
+            );
+        }
+
+        let valid = column(
+            r#", "relationship": {"table": "users", "column": "id"}, "check": "author > 0""#,
+        );
+        assert!(parse_schema(&valid, SchemaFormat::Json).is_ok());
+
+        let checked = r#"{"name": "posts", "columns": [],
+            "checks": [{"name": "posts_check", "expression": "1; DROP TABLE x"}]}"#;
+        assert!(parse_schema(checked, SchemaFormat::Json).is_err());
+    }
+}
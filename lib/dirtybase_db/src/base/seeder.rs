@@ -0,0 +1,123 @@
+use super::{manager::Manager, query::QueryBuilder, table::BaseTable};
+use async_trait::async_trait;
+
+pub const SEEDER_TABLE: &str = "_core_seeder";
+
+/// Fills the database with data, usually through factories. Seeders run
+/// in the order they are registered and each one runs once
+#[async_trait(?Send)]
+pub trait Seeder {
+    // unique, recorded in the seeder table once it ran
+    fn name(&self) -> &str;
+
+    async fn run(&self, manager: &Manager) -> anyhow::Result<()>;
+}
+
+/// The known seeders, in the order they run
+#[derive(Default)]
+pub struct SeederRegistry {
+    seeders: Vec<Box<dyn Seeder>>,
+}
+
+impl SeederRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registering a name twice is an error
+    pub fn register(&mut self, seeder: Box<dyn Seeder>) -> anyhow::Result<&mut Self> {
+        if self.find(seeder.name()).is_some() {
+            anyhow::bail!("seeder `{}` is already registered", seeder.name());
+        }
+        self.seeders.push(seeder);
+        Ok(self)
+    }
+
+    pub fn find(&self, name: &str) -> Option<&dyn Seeder> {
+        self.seeders
+            .iter()
+            .find(|s| s.name() == name)
+            .map(|s| s.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.seeders.iter().map(|s| s.name()).collect()
+    }
+}
+
+/// The table that records the seeders that ran
+pub fn seeder_table() -> BaseTable {
+    let mut table = BaseTable::new(SEEDER_TABLE);
+    table.id(None);
+    // seeder name
+    table.string("name").set_is_unique(true);
+    table.timestamps();
+    table
+}
+
+/// Runs the registry's seeders
+pub struct SeederRunner<'a> {
+    manager: &'a Manager,
+    registry: &'a SeederRegistry,
+}
+
+impl<'a> SeederRunner<'a> {
+    pub fn new(manager: &'a Manager, registry: &'a SeederRegistry) -> Self {
+        Self { manager, registry }
+    }
+
+    /// The names of the seeders that already ran. Read from the primary,
+    /// a lagging replica would have them run again
+    pub async fn ran(&self) -> anyhow::Result<Vec<String>> {
+        let mut query = QueryBuilder::new(vec![SEEDER_TABLE.to_owned()]);
+        query.select("name").on_primary();
+
+        let rows = self.manager.fetch(query).await?;
+        Ok(rows
+            .iter()
+            .filter_map(|row| row.get("name").and_then(|n| n.as_str()))
+            .map(|name| name.to_owned())
+            .collect())
+    }
+
+    /// Runs the seeders that did not run yet, each in a transaction.
+    /// With `force` every seeder runs again. Returns their names
+    pub async fn run(&self, force: bool) -> anyhow::Result<Vec<String>> {
+        let ran = self.ran().await?;
+        let manager = self.manager;
+
+        let mut names = Vec::new();
+        for name in self.registry.names() {
+            let has_run = ran.iter().any(|r| r == name);
+            if has_run && !force {
+                continue;
+            }
+
+            let seeder = self.registry.find(name).unwrap();
+            manager
+                .in_transaction(async {
+                    seeder.run(manager).await?;
+                    if has_run {
+                        return Ok(());
+                    }
+                    manager
+                        .insert(SEEDER_TABLE)
+                        .set("name", name.to_owned())
+                        .save()
+                        .await
+                })
+                .await
+                .map_err(|e| e.context(format!("seeder `{}` failed", name)))?;
+            names.push(name.to_owned());
+        }
+
+        Ok(names)
+    }
+
+    /// Forgets that the seeder ran, so that the next run includes it
+    pub async fn reset(&self, name: &str) -> anyhow::Result<u64> {
+        let mut query = QueryBuilder::new(vec![SEEDER_TABLE.to_owned()]);
+        query.eq("name", name);
+        self.manager.inner_ref().delete(query).await
+    }
+}
@@ -1,23 +1,82 @@
 use super::{
+    check::BaseCheck,
     column::{BaseColumn, ColumnType, RelationType},
+    foreign_key::BaseForeignKey,
+    index::{BaseIndex, IndexKind},
+    pivot::PivotTable,
     user_table::user_table_name,
 };
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
-#[derive(Debug)]
+/// The user that created the record, added by `blame`
+pub const CREATOR_COLUMN: &str = "creator";
+/// The user that last changed the record, added by `blame`
+pub const EDITOR_COLUMN: &str = "editor";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BaseTable {
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub new_name: Option<String>,
     pub columns: Vec<BaseColumn>,
+    // defaults to the auto increment column when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary_key: Option<Vec<String>>,
+    // every secondary index. Single column unique indexes also
+    // mark their column as unique
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub indexes: Vec<BaseIndex>,
+    // foreign keys that are not a single column's relationship
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub foreign_keys: Vec<BaseForeignKey>,
+    // checks that are not on a single column
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<BaseCheck>,
+    // the table options. The driver's defaults are used for those not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub charset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dropped_columns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dropped_foreign_keys: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dropped_indexes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dropped_checks: Vec<String>,
+    #[serde(skip, default = "is_new")]
     pub is_new: bool,
 }
 
+// a loaded definition is committed as a new table unless it already exists
+fn is_new() -> bool {
+    true
+}
+
 impl BaseTable {
     pub fn new(name: &str) -> Self {
         Self {
             name: name.to_owned(),
             new_name: None,
             columns: Vec::new(),
+            primary_key: None,
+            indexes: Vec::new(),
+            foreign_keys: Vec::new(),
+            checks: Vec::new(),
+            engine: None,
+            charset: None,
+            collation: None,
+            comment: None,
+            dropped_columns: Vec::new(),
+            dropped_foreign_keys: Vec::new(),
+            dropped_indexes: Vec::new(),
+            dropped_checks: Vec::new(),
             is_new: true,
         }
     }
@@ -28,22 +87,228 @@ impl BaseTable {
         self
     }
 
+    /// Drop an existing column
+    pub fn drop_column(&mut self, name: &str) -> &mut Self {
+        self.dropped_columns.push(name.to_owned());
+        self
+    }
+
+    /// Drop an existing foreign key constraint by its name
+    pub fn drop_foreign_key(&mut self, name: &str) -> &mut Self {
+        self.foreign_keys
+            .retain(|key| key.name.as_deref() != Some(name));
+        for column in self.columns.iter_mut() {
+            if column.relationship.as_ref().and_then(|k| k.name()) == Some(&name.to_owned()) {
+                column.relationship = None;
+            }
+        }
+        self.dropped_foreign_keys.push(name.to_owned());
+        self
+    }
+
+    /// Sets the primary key. Use more than one column for a composite key
+    pub fn primary_key(&mut self, columns: &[&str]) -> &mut Self {
+        self.primary_key = Some(columns.iter().map(|c| c.to_string()).collect());
+        self
+    }
+
+    /// The columns of the primary key, the auto increment column when none was set
+    pub fn primary_key_columns(&self) -> Vec<String> {
+        match &self.primary_key {
+            Some(columns) => columns.clone(),
+            None => self
+                .columns
+                .iter()
+                .filter(|c| c.column_type == ColumnType::AutoIncrementId)
+                .map(|c| c.name.clone())
+                .collect(),
+        }
+    }
+
+    /// Drop an existing index by its name
+    pub fn drop_index(&mut self, name: &str) -> &mut Self {
+        self.indexes.retain(|index| index.name != name);
+        self.dropped_indexes.push(name.to_owned());
+        self
+    }
+
+    /// A named index on one or more columns
+    pub fn index(&mut self, name: &str, columns: &[&str]) -> &mut BaseIndex {
+        self.add_index(BaseIndex::new(name, IndexKind::Index, columns))
+    }
+
+    /// A named unique constraint on one or more columns
+    pub fn unique(&mut self, name: &str, columns: &[&str]) -> &mut BaseIndex {
+        self.add_index(BaseIndex::new(name, IndexKind::Unique, columns))
+    }
+
+    pub fn fulltext(&mut self, name: &str, columns: &[&str]) -> &mut BaseIndex {
+        self.add_index(BaseIndex::new(name, IndexKind::Fulltext, columns))
+    }
+
+    // redefining an existing index replaces it
+    fn add_index(&mut self, index: BaseIndex) -> &mut BaseIndex {
+        match self.indexes.iter().position(|i| i.name == index.name) {
+            Some(position) => {
+                self.indexes[position] = index;
+                &mut self.indexes[position]
+            }
+            None => {
+                self.indexes.push(index);
+                self.indexes.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// A named foreign key on one or more columns. Redefining an existing
+    /// constraint replaces it
+    pub fn foreign_key(
+        &mut self,
+        name: &str,
+        columns: &[&str],
+        table: &str,
+        references: &[&str],
+    ) -> &mut BaseForeignKey {
+        let mut key = BaseForeignKey::new(columns, table, references);
+        key.set_name(name);
+
+        match self
+            .foreign_keys
+            .iter()
+            .position(|k| k.name.as_deref() == Some(name))
+        {
+            Some(position) => {
+                self.foreign_keys[position] = key;
+                &mut self.foreign_keys[position]
+            }
+            None => {
+                self.foreign_keys.push(key);
+                self.foreign_keys.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Every foreign key of the table, the columns' relationships first
+    pub fn foreign_key_constraints(&self) -> Vec<BaseForeignKey> {
+        self.columns
+            .iter()
+            .filter(|c| !c.has_pivot_table())
+            .filter_map(|c| {
+                c.relationship
+                    .as_ref()
+                    .map(|k| k.constraint(c.new_name.as_ref().unwrap_or(&c.name)))
+            })
+            .chain(self.foreign_keys.iter().cloned())
+            .collect()
+    }
+
+    /// A named CHECK constraint, such as `ends_at > starts_at`.
+    /// Redefining an existing check replaces it
+    pub fn check(&mut self, name: &str, expression: &str) -> &mut Self {
+        let check = BaseCheck::new(name, expression);
+        match self.checks.iter().position(|c| c.name == name) {
+            Some(position) => self.checks[position] = check,
+            None => self.checks.push(check),
+        }
+        self
+    }
+
+    /// Drop an existing check constraint by its name
+    pub fn drop_check(&mut self, name: &str) -> &mut Self {
+        self.checks.retain(|check| check.name != name);
+        self.dropped_checks.push(name.to_owned());
+        self
+    }
+
+    /// Every check of the table, the columns' checks first
+    pub fn check_constraints(&self) -> Vec<BaseCheck> {
+        self.columns
+            .iter()
+            .filter_map(|c| {
+                c.check.as_ref().map(|expression| {
+                    BaseCheck::new(&BaseCheck::generated_name(&self.name, &c.name), expression)
+                })
+            })
+            .chain(self.checks.iter().cloned())
+            .collect()
+    }
+
+    pub fn set_engine(&mut self, engine: &str) -> &mut Self {
+        self.engine = Some(engine.to_owned());
+        self
+    }
+
+    /// The default character set of the table's columns
+    pub fn set_charset(&mut self, charset: &str) -> &mut Self {
+        self.charset = Some(charset.to_owned());
+        self
+    }
+
+    /// The default collation of the table's columns
+    pub fn set_collation(&mut self, collation: &str) -> &mut Self {
+        self.collation = Some(collation.to_owned());
+        self
+    }
+
+    pub fn set_comment(&mut self, comment: &str) -> &mut Self {
+        self.comment = Some(comment.to_owned());
+        self
+    }
+
     pub fn set_is_new(&mut self, new: bool) -> &mut Self {
         self.is_new = new;
         self
     }
 
+    /// Databases do not store that the `id` of an `id_set` is a ULID.
+    /// Drivers call this on the tables they read back
+    pub fn detect_id_set(&mut self) {
+        let has_internal_id = self
+            .columns
+            .iter()
+            .any(|c| c.name == "internal_id" && c.column_type == ColumnType::AutoIncrementId);
+        if let Some(id) = self.columns.iter_mut().find(|c| c.name == "id") {
+            if has_internal_id && id.column_type == ColumnType::Char(26) && id.default.is_none() {
+                id.default_is_ulid();
+            }
+        }
+    }
+
     pub fn column(
         &mut self,
         name: &'static str,
         callback: impl FnOnce(&mut BaseColumn),
     ) -> &mut BaseColumn {
         let mut column = BaseColumn::new(name, ColumnType::String(255));
-
         callback(&mut column);
-        self.columns.push(column);
+        self.add_column(column)
+    }
+
+    /// Adds a column built elsewhere, such as a collection's field
+    pub fn add_column(&mut self, column: BaseColumn) -> &mut BaseColumn {
+        // redefining an existing column replaces it in place
+        match self.columns.iter().position(|c| c.name == column.name) {
+            Some(index) => {
+                self.columns[index] = column;
+                &mut self.columns[index]
+            }
+            None => {
+                self.columns.push(column);
+                self.columns.last_mut().unwrap()
+            }
+        }
+    }
 
-        self.columns.last_mut().unwrap()
+    pub fn binary(&mut self, name: &'static str, length: usize) -> &mut BaseColumn {
+        self.column(name, |column| {
+            column.set_type(ColumnType::Binary(length));
+        })
+    }
+
+    pub fn blob(&mut self, name: &'static str) -> &mut BaseColumn {
+        self.column(name, |column| {
+            column.set_type(ColumnType::Blob);
+        })
     }
 
     pub fn boolean(&mut self, name: &'static str) -> &mut BaseColumn {
@@ -64,6 +329,32 @@ impl BaseTable {
         })
     }
 
+    pub fn datetime(&mut self, name: &'static str) -> &mut BaseColumn {
+        self.column(name, |column| {
+            column.set_type(ColumnType::Datetime);
+        })
+    }
+
+    pub fn decimal(
+        &mut self,
+        name: &'static str,
+        precision: usize,
+        scale: usize,
+    ) -> &mut BaseColumn {
+        self.column(name, |column| {
+            column.set_type(ColumnType::Decimal { precision, scale });
+        })
+    }
+
+    /// A column limited to one of the values
+    pub fn enumeration(&mut self, name: &'static str, values: &[&str]) -> &mut BaseColumn {
+        self.column(name, |column| {
+            column.set_type(ColumnType::Enum(
+                values.iter().map(|v| v.to_string()).collect(),
+            ));
+        })
+    }
+
     pub fn file(&mut self, name: &'static str, relation_type: RelationType) -> &mut BaseColumn {
         self.column(name, |column| {
             column.set_type(ColumnType::File(relation_type));
@@ -88,6 +379,12 @@ impl BaseTable {
         })
     }
 
+    pub fn medium_integer(&mut self, name: &'static str) -> &mut BaseColumn {
+        self.column(name, |column| {
+            column.set_type(ColumnType::MediumInteger);
+        })
+    }
+
     pub fn number(&mut self, name: &'static str) -> &mut BaseColumn {
         self.column(name, |column| {
             column.set_type(ColumnType::Number);
@@ -113,6 +410,13 @@ impl BaseTable {
             column.set_type(ColumnType::Select(relation_type));
         })
     }
+
+    pub fn small_integer(&mut self, name: &'static str) -> &mut BaseColumn {
+        self.column(name, |column| {
+            column.set_type(ColumnType::SmallInteger);
+        })
+    }
+
     pub fn string(&mut self, name: &'static str) -> &mut BaseColumn {
         self.column(name, |column| {
             column.set_type(ColumnType::String(255));
@@ -127,7 +431,25 @@ impl BaseTable {
 
     pub fn text(&mut self, name: &'static str) -> &mut BaseColumn {
         self.column(name, |column| {
-            column.set_type(ColumnType::String(512));
+            column.set_type(ColumnType::Text);
+        })
+    }
+
+    pub fn time(&mut self, name: &'static str) -> &mut BaseColumn {
+        self.column(name, |column| {
+            column.set_type(ColumnType::Time);
+        })
+    }
+
+    pub fn timestamp(&mut self, name: &'static str) -> &mut BaseColumn {
+        self.column(name, |column| {
+            column.set_type(ColumnType::Timestamp);
+        })
+    }
+
+    pub fn tiny_integer(&mut self, name: &'static str) -> &mut BaseColumn {
+        self.column(name, |column| {
+            column.set_type(ColumnType::TinyInteger);
         })
     }
 
@@ -143,7 +465,10 @@ impl BaseTable {
 
     pub fn id_set(&mut self) {
         self.id(Some("internal_id"));
-        self.ulid("id").set_is_unique(true).set_is_nullable(false);
+        self.ulid("id")
+            .set_is_unique(true)
+            .set_is_nullable(false)
+            .default_is_ulid();
     }
 
     pub fn id(&mut self, name: Option<&'static str>) -> &mut BaseColumn {
@@ -153,13 +478,13 @@ impl BaseTable {
     }
 
     pub fn created_at(&mut self) -> &mut BaseColumn {
-        self.date("created_at")
+        self.datetime("created_at")
             .set_is_nullable(false)
             .default_is_created_at()
     }
 
     pub fn updated_at(&mut self) -> &mut BaseColumn {
-        self.date("updated_at")
+        self.datetime("updated_at")
             .set_is_nullable(false)
             .default_is_updated_at()
     }
@@ -172,18 +497,18 @@ impl BaseTable {
     pub fn blame(&mut self) {
         let user_table_name = &user_table_name();
         let mut _creator = self
-            .ulid("creator")
+            .ulid(CREATOR_COLUMN)
             .set_is_nullable(false)
             .references_without_cascade_delete(user_table_name, "id");
 
         let mut _editor = self
-            .ulid("editor")
+            .ulid(EDITOR_COLUMN)
             .set_is_nullable(true)
             .references_without_cascade_delete(user_table_name, "id");
     }
 
     pub fn soft_deletable(&mut self) -> &mut BaseColumn {
-        self.date("deleted_at").set_is_nullable(true)
+        self.datetime("deleted_at").set_is_nullable(true)
     }
 
     pub fn is_new(&self) -> bool {
@@ -193,4 +518,16 @@ impl BaseTable {
     pub fn columns(&self) -> &Vec<BaseColumn> {
         &self.columns
     }
+
+    pub fn find_column(&self, name: &str) -> Option<&BaseColumn> {
+        self.columns.iter().find(|c| c.name == name)
+    }
+
+    /// The pivot tables required by this table's multiple relations
+    pub fn pivot_tables(&self) -> Vec<PivotTable> {
+        self.columns
+            .iter()
+            .filter_map(|column| PivotTable::from_column(&self.name, column))
+            .collect()
+    }
 }
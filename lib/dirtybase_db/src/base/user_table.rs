@@ -1,3 +1,5 @@
+use super::{schema_diff::SchemaChange, table::BaseTable};
+
 pub struct User {
     // internal_id: u64,
     // id: String
@@ -14,20 +16,22 @@ pub fn user_table_name() -> String {
 // We need to have this table in the orm lib as
 // the "own" fields are assuming there is a user
 // table
-pub async fn setup_users_table(manager: &super::manager::Manager) {
-    let name = user_table_name();
-    if !manager.has_table(&name).await {
-        manager
-            .create(&name, |table| {
-                table.id_set();
-                table.string("username");
-                table.string("email");
-                // password, open ID, third party, magic link?
-                // the user could be using openID to login !?!?!
-                table.string("password").set_is_nullable(true);
-                table.timestamps();
-                table.soft_deletable();
-            })
-            .await;
-    }
+pub fn users_table() -> BaseTable {
+    let mut table = BaseTable::new(USER_TABLE);
+    table.id_set();
+    table.string("username");
+    table.string("email");
+    // password, open ID, third party, magic link?
+    // the user could be using openID to login !?!?!
+    table.string("password").set_is_nullable(true);
+    table.timestamps();
+    table.soft_deletable();
+    table
+}
+
+// Returns the destructive changes that were skipped
+pub async fn setup_users_table(
+    manager: &super::manager::Manager,
+) -> anyhow::Result<Vec<SchemaChange>> {
+    manager.converge_table(users_table(), false).await
 }
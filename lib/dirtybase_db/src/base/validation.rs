@@ -0,0 +1,402 @@
+use super::table::BaseTable;
+use anyhow::anyhow;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::HashMap, fmt, sync::Arc};
+
+/// A rule a column's value must satisfy before it is written
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationRule {
+    Required,
+    MinLength(usize),
+    MaxLength(usize),
+    Min(f64),
+    Max(f64),
+    // a regular expression the whole value must match
+    Pattern(Pattern),
+    Email,
+    Url,
+    OneOf(Vec<String>),
+    // for JSON columns
+    JsonSchema(JsonSchema),
+}
+
+impl ValidationRule {
+    /// A `Pattern` rule, fails when the pattern does not compile
+    pub fn pattern(pattern: &str) -> anyhow::Result<Self> {
+        Ok(Self::Pattern(Pattern::new(pattern)?))
+    }
+
+    /// A `JsonSchema` rule, fails when the schema does not compile
+    pub fn json_schema(schema: serde_json::Value) -> anyhow::Result<Self> {
+        Ok(Self::JsonSchema(JsonSchema::new(schema)?))
+    }
+
+    /// The rule's name in field errors
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Required => "required",
+            Self::MinLength(_) => "min_length",
+            Self::MaxLength(_) => "max_length",
+            Self::Min(_) => "min",
+            Self::Max(_) => "max",
+            Self::Pattern(_) => "pattern",
+            Self::Email => "email",
+            Self::Url => "url",
+            Self::OneOf(_) => "one_of",
+            Self::JsonSchema(_) => "json_schema",
+        }
+    }
+
+    /// Why the value breaks the rule, `None` when it does not.
+    /// Empty values only break `Required`
+    pub fn check(&self, value: &str) -> Option<String> {
+        if value.is_empty() {
+            return match self {
+                Self::Required => Some("is required".to_owned()),
+                _ => None,
+            };
+        }
+
+        match self {
+            Self::Required => None,
+            Self::MinLength(length) => (value.chars().count() < *length)
+                .then(|| format!("must be at least {} characters", length)),
+            Self::MaxLength(length) => (value.chars().count() > *length)
+                .then(|| format!("must be at most {} characters", length)),
+            Self::Min(min) => match value.parse::<f64>() {
+                Ok(number) if number >= *min => None,
+                Ok(_) => Some(format!("must be at least {}", min)),
+                Err(_) => Some("must be a number".to_owned()),
+            },
+            Self::Max(max) => match value.parse::<f64>() {
+                Ok(number) if number <= *max => None,
+                Ok(_) => Some(format!("must be at most {}", max)),
+                Err(_) => Some("must be a number".to_owned()),
+            },
+            Self::Pattern(pattern) => (!pattern.regex.is_match(value))
+                .then(|| format!("must match `{}`", pattern.as_str())),
+            Self::Email => (!is_email(value)).then(|| "must be an email address".to_owned()),
+            Self::Url => (!is_url(value)).then(|| "must be a URL".to_owned()),
+            Self::OneOf(values) => (!values.iter().any(|v| v == value))
+                .then(|| format!("must be one of {}", values.join(", "))),
+            Self::JsonSchema(schema) => schema.check(value),
+        }
+    }
+}
+
+/// A regular expression compiled when the rule is declared. Serialized as
+/// the expression itself
+#[derive(Clone)]
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> anyhow::Result<Self> {
+        let regex = Regex::new(&format!("^(?:{})$", pattern))
+            .map_err(|e| anyhow!("invalid pattern `{}`: {}", pattern, e))?;
+        Ok(Self {
+            source: pattern.to_owned(),
+            regex,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Pattern").field(&self.source).finish()
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.source.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Self::new(&source).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A JSON schema compiled when the rule is declared. Serialized as the
+/// schema itself
+#[derive(Clone)]
+pub struct JsonSchema {
+    schema: serde_json::Value,
+    compiled: Arc<jsonschema::JSONSchema>,
+}
+
+impl JsonSchema {
+    pub fn new(schema: serde_json::Value) -> anyhow::Result<Self> {
+        let compiled = jsonschema::JSONSchema::compile(&schema)
+            .map_err(|e| anyhow!("invalid JSON schema: {}", e))?;
+        Ok(Self {
+            schema,
+            compiled: Arc::new(compiled),
+        })
+    }
+
+    pub fn schema(&self) -> &serde_json::Value {
+        &self.schema
+    }
+
+    fn check(&self, value: &str) -> Option<String> {
+        let instance: serde_json::Value = match serde_json::from_str(value) {
+            Ok(instance) => instance,
+            Err(_) => return Some("must be valid JSON".to_owned()),
+        };
+
+        let result = self.compiled.validate(&instance);
+        result.err().map(|errors| {
+            errors
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        })
+    }
+}
+
+impl fmt::Debug for JsonSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("JsonSchema").field(&self.schema).finish()
+    }
+}
+
+impl PartialEq for JsonSchema {
+    fn eq(&self, other: &Self) -> bool {
+        self.schema == other.schema
+    }
+}
+
+impl Serialize for JsonSchema {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.schema.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonSchema {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let schema = serde_json::Value::deserialize(deserializer)?;
+        Self::new(schema).map_err(serde::de::Error::custom)
+    }
+}
+
+fn is_email(value: &str) -> bool {
+    match value.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && domain.split('.').all(|part| !part.is_empty())
+                && !value.chars().any(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+fn is_url(value: &str) -> bool {
+    match value.split_once("://") {
+        Some((scheme, rest)) => {
+            matches!(scheme.to_lowercase().as_str(), "http" | "https")
+                && !rest.is_empty()
+                && !rest.starts_with('/')
+                && !value.chars().any(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+/// A field that failed one of its column's rules
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub rule: String,
+    pub message: String,
+}
+
+/// Every field error of a record. Returned inside `anyhow::Error` by the
+/// writes, downcast it to respond with the errors
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// The errors grouped by field
+    pub fn by_field(&self) -> HashMap<&str, Vec<&FieldError>> {
+        let mut grouped: HashMap<&str, Vec<&FieldError>> = HashMap::new();
+        for error in &self.errors {
+            grouped.entry(error.field.as_str()).or_default().push(error);
+        }
+        grouped
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors: Vec<String> = self
+            .errors
+            .iter()
+            .map(|e| format!("`{}` {}", e.field, e.message))
+            .collect();
+        write!(f, "validation failed: {}", errors.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+/// Checks the record's values against the rules of the table's columns.
+/// An update only checks the columns it sets
+pub fn validate_record(
+    table: &BaseTable,
+    record: &HashMap<String, String>,
+    is_update: bool,
+) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::default();
+
+    for column in table.columns() {
+        let value = match record.get(&column.name) {
+            Some(value) => value.as_str(),
+            None if is_update => continue,
+            None => "",
+        };
+
+        for rule in &column.rules {
+            if let Some(message) = rule.check(value) {
+                errors.errors.push(FieldError {
+                    field: column.name.clone(),
+                    rule: rule.code().to_owned(),
+                    message,
+                });
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn articles() -> BaseTable {
+        let mut table = BaseTable::new("articles");
+        table
+            .string("title")
+            .add_rule(ValidationRule::Required)
+            .add_rule(ValidationRule::MaxLength(5));
+        table.string("email").add_rule(ValidationRule::Email);
+        table
+            .integer("rating")
+            .add_rule(ValidationRule::Min(1.0))
+            .add_rule(ValidationRule::Max(5.0));
+        table
+            .string("slug")
+            .add_rule(ValidationRule::pattern("[a-z-]+").unwrap());
+        table.json("meta").add_rule(
+            ValidationRule::json_schema(json!({
+                "type": "object",
+                "required": ["size"],
+            }))
+            .unwrap(),
+        );
+        table
+    }
+
+    fn record(values: &[(&str, &str)]) -> HashMap<String, String> {
+        values
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn invalid_fields_are_reported() {
+        let errors = validate_record(
+            &articles(),
+            &record(&[
+                ("email", "not an email"),
+                ("rating", "9"),
+                ("slug", "Hello World"),
+                ("meta", r#"{"width": 3}"#),
+            ]),
+            false,
+        )
+        .unwrap_err();
+
+        let rules: Vec<(&str, &str)> = errors
+            .errors
+            .iter()
+            .map(|e| (e.field.as_str(), e.rule.as_str()))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                ("title", "required"),
+                ("email", "email"),
+                ("rating", "max"),
+                ("slug", "pattern"),
+                ("meta", "json_schema"),
+            ]
+        );
+    }
+
+    #[test]
+    fn valid_records_and_partial_updates_pass() {
+        assert!(validate_record(
+            &articles(),
+            &record(&[
+                ("title", "Hi"),
+                ("email", "a@b.co"),
+                ("rating", "3"),
+                ("slug", "hi-there"),
+                ("meta", r#"{"size": 3}"#),
+            ]),
+            false,
+        )
+        .is_ok());
+        assert!(validate_record(&articles(), &record(&[("rating", "2")]), true).is_ok());
+        assert!(ValidationRule::Url.check("https://example.com/a").is_none());
+        assert!(ValidationRule::Url.check("example.com").is_some());
+    }
+
+    #[test]
+    fn invalid_rules_fail_when_declared() {
+        assert!(ValidationRule::pattern("[a-z").is_err());
+        assert!(ValidationRule::json_schema(json!({ "type": 5 })).is_err());
+
+        let loaded: ValidationRule =
+            serde_json::from_value(json!({ "pattern": "[a-z-]+" })).unwrap();
+        assert_eq!(loaded, ValidationRule::pattern("[a-z-]+").unwrap());
+        assert_eq!(
+            serde_json::to_value(&loaded).unwrap(),
+            json!({ "pattern": "[a-z-]+" })
+        );
+        assert!(serde_json::from_value::<ValidationRule>(json!({ "pattern": "[a-z" })).is_err());
+    }
+}
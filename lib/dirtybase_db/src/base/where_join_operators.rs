@@ -1,14 +1,30 @@
 use super::query_conditions::Condition;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub enum WhereClause {
+    Condition(Condition),
+    Group(Vec<WhereJoinOperator>),
+}
+
+impl From<Condition> for WhereClause {
+    fn from(value: Condition) -> Self {
+        Self::Condition(value)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum WhereJoinOperator {
-    None(Condition),
-    And(Condition),
-    Or(Condition),
+    None(WhereClause),
+    And(WhereClause),
+    Or(WhereClause),
 }
 
 impl WhereJoinOperator {
     pub fn as_clause(&self, existing_wheres: &str, condition: &str) -> String {
+        if existing_wheres.trim().is_empty() {
+            return condition.to_owned();
+        }
+
         let join = match &self {
             Self::And(_) => "AND",
             Self::Or(_) => "OR",
@@ -18,7 +34,13 @@ impl WhereJoinOperator {
         format!("{} {} {}", existing_wheres, join, condition)
     }
 
-    pub fn condition(&self) -> &Condition {
+    pub fn clause(&self) -> &WhereClause {
+        match self {
+            Self::And(c) | Self::Or(c) | Self::None(c) => c,
+        }
+    }
+
+    pub fn into_clause(self) -> WhereClause {
         match self {
             Self::And(c) | Self::Or(c) | Self::None(c) => c,
         }
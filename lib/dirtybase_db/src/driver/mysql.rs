@@ -1 +1,2 @@
+pub mod commit_table;
 pub mod mysql_schema_manager;
@@ -1,24 +1,529 @@
+use crate::base::{
+    check::BaseCheck,
+    column::{
+        BaseColumn, ColumnDefault, ColumnType, ForeignKey, GeneratedColumn, GeneratedStorage,
+    },
+    foreign_key::{BaseForeignKey, ForeignKeyAction},
+    index::{BaseIndex, IndexColumn, IndexKind, IndexOrder},
+    table::BaseTable,
+};
+use sqlx::{mysql::MySqlRow, MySql, Pool, Row};
+
+// INFORMATION_SCHEMA columns are cast to CHAR as some servers return them as binary
+const COLUMNS_QUERY: &str = "SELECT CAST(COLUMN_NAME AS CHAR) AS name,
+CAST(COLUMN_TYPE AS CHAR) AS column_type,
+CAST(IS_NULLABLE AS CHAR) AS is_nullable,
+CAST(COLUMN_DEFAULT AS CHAR) AS column_default,
+CAST(EXTRA AS CHAR) AS extra,
+CAST(COLLATION_NAME AS CHAR) AS collation,
+CAST(COLUMN_COMMENT AS CHAR) AS comment,
+CAST(GENERATION_EXPRESSION AS CHAR) AS generation_expression
+FROM INFORMATION_SCHEMA.COLUMNS
+WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ?
+ORDER BY ORDINAL_POSITION";
+
+const INDEXES_QUERY: &str = "SELECT CAST(INDEX_NAME AS CHAR) AS name,
+CAST(COLUMN_NAME AS CHAR) AS column_name,
+CAST(NON_UNIQUE AS CHAR) AS non_unique,
+CAST(INDEX_TYPE AS CHAR) AS index_type,
+CAST(SUB_PART AS CHAR) AS sub_part,
+CAST(COLLATION AS CHAR) AS collation
+FROM INFORMATION_SCHEMA.STATISTICS
+WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ?
+ORDER BY INDEX_NAME, SEQ_IN_INDEX";
+
+const FOREIGN_KEYS_QUERY: &str = "SELECT CAST(k.CONSTRAINT_NAME AS CHAR) AS name,
+CAST(k.COLUMN_NAME AS CHAR) AS column_name,
+CAST(k.REFERENCED_TABLE_NAME AS CHAR) AS referenced_table,
+CAST(k.REFERENCED_COLUMN_NAME AS CHAR) AS referenced_column,
+CAST(r.DELETE_RULE AS CHAR) AS delete_rule,
+CAST(r.UPDATE_RULE AS CHAR) AS update_rule
+FROM INFORMATION_SCHEMA.KEY_COLUMN_USAGE k
+JOIN INFORMATION_SCHEMA.REFERENTIAL_CONSTRAINTS r
+ON r.CONSTRAINT_SCHEMA = k.CONSTRAINT_SCHEMA
+AND r.CONSTRAINT_NAME = k.CONSTRAINT_NAME
+AND r.TABLE_NAME = k.TABLE_NAME
+WHERE k.TABLE_SCHEMA = DATABASE() AND k.TABLE_NAME = ?
+AND k.REFERENCED_TABLE_NAME IS NOT NULL
+ORDER BY k.CONSTRAINT_NAME, k.ORDINAL_POSITION";
+
+const CHECKS_QUERY: &str = "SELECT CAST(c.CONSTRAINT_NAME AS CHAR) AS name,
+CAST(c.CHECK_CLAUSE AS CHAR) AS expression
+FROM INFORMATION_SCHEMA.TABLE_CONSTRAINTS t
+JOIN INFORMATION_SCHEMA.CHECK_CONSTRAINTS c
+ON c.CONSTRAINT_SCHEMA = t.CONSTRAINT_SCHEMA
+AND c.CONSTRAINT_NAME = t.CONSTRAINT_NAME
+WHERE t.TABLE_SCHEMA = DATABASE() AND t.TABLE_NAME = ?
+AND t.CONSTRAINT_TYPE = 'CHECK'
+ORDER BY c.CONSTRAINT_NAME";
+
+const TABLE_OPTIONS_QUERY: &str = "SELECT CAST(t.ENGINE AS CHAR) AS engine,
+CAST(c.CHARACTER_SET_NAME AS CHAR) AS charset,
+CAST(t.TABLE_COLLATION AS CHAR) AS collation,
+CAST(t.TABLE_COMMENT AS CHAR) AS comment
+FROM INFORMATION_SCHEMA.TABLES t
+LEFT JOIN INFORMATION_SCHEMA.COLLATIONS c ON c.COLLATION_NAME = t.TABLE_COLLATION
+WHERE t.TABLE_SCHEMA = DATABASE() AND t.TABLE_NAME = ?";
+
+const TABLES_QUERY: &str = "SELECT CAST(TABLE_NAME AS CHAR) AS name
+FROM INFORMATION_SCHEMA.TABLES
+WHERE TABLE_SCHEMA = DATABASE() AND TABLE_TYPE = 'BASE TABLE'
+ORDER BY TABLE_NAME";
+
+/// Reads the live definition of a table from INFORMATION_SCHEMA
 pub(crate) struct MysqlTableManager<'a> {
-    manager: &'a MySqlSchemaManager,
-    table: BaseTable,
+    db_pool: &'a Pool<MySql>,
 }
 
-impl MysqlTableManager<'a> {
-    pub fn new(table: BaseTable, manager: &'a MySqlSchemaManager) -> Self {
-        Self { manager, table }
+impl<'a> MysqlTableManager<'a> {
+    pub fn new(db_pool: &'a Pool<MySql>) -> Self {
+        Self { db_pool }
     }
 
-    pub fn commit(&self) {
-        let query = "SELECT *
-FROM INFORMATION_SCHEMA.COLUMNS
-WHERE table_name = ?";
-        let mut rows = sqlx::query(query)
-            .bind(&table.name)
-            .fetch(self.manager.db_pool.as_ref());
+    /// The name of every table in the current database
+    pub async fn table_names(&self) -> anyhow::Result<Vec<String>> {
+        Ok(sqlx::query(TABLES_QUERY)
+            .fetch_all(self.db_pool)
+            .await?
+            .iter()
+            .map(|row| text(row, "name"))
+            .collect())
+    }
+
+    /// The table as it exists in the database, `None` when there is no such table
+    pub async fn fetch_table(&self, name: &str) -> anyhow::Result<Option<BaseTable>> {
+        let columns = self.fetch_rows(COLUMNS_QUERY, name).await?;
+        if columns.is_empty() {
+            return Ok(None);
+        }
+
+        let mut table = BaseTable::new(name);
+        table.set_is_new(false);
+
+        for row in &columns {
+            let mut column = column_from_row(
+                &text(row, "name"),
+                &text(row, "column_type"),
+                &text(row, "is_nullable"),
+                row.try_get::<Option<String>, _>("column_default")?
+                    .as_deref(),
+                &text(row, "extra"),
+            )?;
+            column.collation = optional_text(row, "collation");
+            column.comment = optional_text(row, "comment");
+            column.generated = generated_from(
+                &text(row, "extra"),
+                optional_text(row, "generation_expression"),
+            );
+            table.columns.push(column);
+        }
+
+        // servers before MySQL 8.0.16 have no CHECK_CONSTRAINTS table
+        for row in self
+            .fetch_rows(CHECKS_QUERY, name)
+            .await
+            .unwrap_or_default()
+        {
+            table.checks.push(BaseCheck::new(
+                &text(&row, "name"),
+                &text(&row, "expression"),
+            ));
+        }
+
+        if let Some(row) = self.fetch_rows(TABLE_OPTIONS_QUERY, name).await?.first() {
+            table.engine = optional_text(row, "engine");
+            table.charset = optional_text(row, "charset");
+            table.collation = optional_text(row, "collation");
+            table.comment = optional_text(row, "comment");
+        }
+
+        for row in self.fetch_rows(INDEXES_QUERY, name).await? {
+            let index_name = text(&row, "name");
+            if index_name == "PRIMARY" {
+                table
+                    .primary_key
+                    .get_or_insert_with(Vec::new)
+                    .push(text(&row, "column_name"));
+                continue;
+            }
+
+            let column = IndexColumn {
+                name: text(&row, "column_name"),
+                length: text(&row, "sub_part").parse().ok(),
+                order: if text(&row, "collation") == "D" {
+                    IndexOrder::Desc
+                } else {
+                    IndexOrder::Asc
+                },
+            };
+            match table.indexes.iter_mut().find(|i| i.name == index_name) {
+                Some(index) => index.columns.push(column),
+                None => {
+                    let kind = if text(&row, "index_type") == "FULLTEXT" {
+                        IndexKind::Fulltext
+                    } else if text(&row, "non_unique") == "0" {
+                        IndexKind::Unique
+                    } else {
+                        IndexKind::Index
+                    };
+                    let mut index = BaseIndex::new(&index_name, kind, &[]);
+                    index.columns.push(column);
+                    table.indexes.push(index);
+                }
+            }
+        }
+
+        let mut foreign_keys: Vec<(String, Vec<MySqlRow>)> = Vec::new();
+        for row in self.fetch_rows(FOREIGN_KEYS_QUERY, name).await? {
+            let constraint = text(&row, "name");
+            match foreign_keys
+                .iter_mut()
+                .find(|(name, _)| *name == constraint)
+            {
+                Some((_, rows)) => rows.push(row),
+                None => foreign_keys.push((constraint, vec![row])),
+            }
+        }
+
+        apply_indexes(&mut table);
+
+        // single column constraints map onto the column's relationship
+        for (constraint, rows) in foreign_keys {
+            let row = &rows[0];
+            let on_delete = foreign_key_action_from(&text(row, "delete_rule"));
+            let on_update = foreign_key_action_from(&text(row, "update_rule"));
+            let column_name = text(row, "column_name");
+
+            match table.columns.iter_mut().find(|c| c.name == column_name) {
+                Some(column) if rows.len() == 1 => {
+                    column.relationship = Some(
+                        ForeignKey::new(
+                            &text(row, "referenced_table"),
+                            &text(row, "referenced_column"),
+                            false,
+                        )
+                        .with_name(&constraint)
+                        .with_on_delete(on_delete)
+                        .with_on_update(on_update),
+                    );
+                }
+                _ => table.foreign_keys.push(BaseForeignKey {
+                    name: Some(constraint),
+                    columns: rows.iter().map(|r| text(r, "column_name")).collect(),
+                    table: text(row, "referenced_table"),
+                    references: rows.iter().map(|r| text(r, "referenced_column")).collect(),
+                    on_delete,
+                    on_update,
+                }),
+            }
+        }
+        table.detect_id_set();
+
+        Ok(Some(table))
+    }
+
+    async fn fetch_rows(&self, query: &str, table: &str) -> anyhow::Result<Vec<MySqlRow>> {
+        Ok(sqlx::query(query)
+            .bind(table)
+            .fetch_all(self.db_pool)
+            .await?)
+    }
+}
+
+fn text(row: &MySqlRow, column: &str) -> String {
+    row.try_get::<Option<String>, _>(column)
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+// `EXTRA` is `VIRTUAL GENERATED` or `STORED GENERATED` for generated columns.
+// Expression defaults are `DEFAULT_GENERATED`
+fn generated_from(extra: &str, expression: Option<String>) -> Option<GeneratedColumn> {
+    let extra = extra.to_uppercase();
+    let storage = if extra.contains("VIRTUAL GENERATED") {
+        GeneratedStorage::Virtual
+    } else if extra.contains("STORED GENERATED") {
+        GeneratedStorage::Stored
+    } else {
+        return None;
+    };
+
+    expression.map(|expression| GeneratedColumn {
+        expression,
+        storage,
+    })
+}
+
+// MySQL reports missing comments as empty strings
+fn optional_text(row: &MySqlRow, column: &str) -> Option<String> {
+    Some(text(row, column)).filter(|value| !value.is_empty())
+}
+
+// Single column unique indexes are the column's `is_unique` flag
+fn apply_indexes(table: &mut BaseTable) {
+    for column in table.columns.iter_mut() {
+        column.is_unique = table.indexes.iter().any(|i| i.is_unique_on(&column.name));
+    }
+}
 
-        while let Some(row) = rows.try_next().await.expect("could not get all countries") {
-            let name: String = row.try_get("COLUMN_NAME").unwrap();
-            dbg!(name);
+fn foreign_key_action_from(rule: &str) -> ForeignKeyAction {
+    match rule.to_uppercase().as_str() {
+        "CASCADE" => ForeignKeyAction::Cascade,
+        "SET NULL" => ForeignKeyAction::SetNull,
+        "RESTRICT" => ForeignKeyAction::Restrict,
+        _ => ForeignKeyAction::NoAction,
+    }
+}
+
+pub(crate) fn column_from_row(
+    name: &str,
+    column_type: &str,
+    is_nullable: &str,
+    default: Option<&str>,
+    extra: &str,
+) -> anyhow::Result<BaseColumn> {
+    let mapped = column_type_from(column_type, extra)
+        .map_err(|e| anyhow::anyhow!("column `{}`: {}", name, e))?;
+    let mut column = BaseColumn::new(name, mapped);
+    column.is_unsigned = column.column_type != ColumnType::AutoIncrementId
+        && column_type.to_lowercase().contains("unsigned");
+    column.is_nullable = Some(is_nullable.eq_ignore_ascii_case("yes"));
+    column.default = column_default_from(default, extra);
+    Ok(column)
+}
+
+/// Maps a MySQL `COLUMN_TYPE`, such as `varchar(255)` or `bigint(20) unsigned`,
+/// onto its column type. Types that no column type renders back as,
+/// such as `int`, `year` or `set`, are errors rather than approximations
+pub(crate) fn column_type_from(column_type: &str, extra: &str) -> anyhow::Result<ColumnType> {
+    let lower = column_type.to_lowercase();
+    let base = lower
+        .split(|c: char| c == '(' || c.is_whitespace())
+        .next()
+        .unwrap_or_default();
+    let arguments: Vec<usize> = lower
+        .split_once('(')
+        .and_then(|(_, rest)| rest.split_once(')'))
+        .map(|(arguments, _)| {
+            arguments
+                .split(',')
+                .filter_map(|a| a.trim().parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    let length = arguments.first().copied();
+
+    if extra.to_lowercase().contains("auto_increment") {
+        // SQLite only auto increments `integer` columns
+        return match base {
+            "bigint" | "integer" => Ok(ColumnType::AutoIncrementId),
+            _ => anyhow::bail!("unsupported auto increment type `{}`", column_type),
+        };
+    }
+
+    Ok(match base {
+        "tinyint" if length == Some(1) => ColumnType::Boolean,
+        "bool" | "boolean" => ColumnType::Boolean,
+        "tinyint" => ColumnType::TinyInteger,
+        "smallint" => ColumnType::SmallInteger,
+        "mediumint" => ColumnType::MediumInteger,
+        "bigint" => ColumnType::Integer,
+        "char" => ColumnType::Char(length.unwrap_or(1)),
+        "varchar" => ColumnType::String(length.unwrap_or(255)),
+        "date" => ColumnType::Date,
+        "datetime" => ColumnType::Datetime,
+        "time" => ColumnType::Time,
+        "timestamp" => ColumnType::Timestamp,
+        "float" => ColumnType::Float,
+        "double" | "real" => ColumnType::Number,
+        "decimal" | "numeric" => ColumnType::Decimal {
+            precision: length.unwrap_or(10),
+            scale: arguments.get(1).copied().unwrap_or(0),
+        },
+        "json" => ColumnType::Json,
+        "tinytext" | "text" | "mediumtext" | "longtext" => ColumnType::Text,
+        "binary" | "varbinary" => ColumnType::Binary(length.unwrap_or(1)),
+        "tinyblob" | "blob" | "mediumblob" | "longblob" => ColumnType::Blob,
+        "enum" => ColumnType::Enum(enum_values(column_type)),
+        "uuid" => ColumnType::Uuid,
+        _ => anyhow::bail!("unsupported column type `{}`", column_type),
+    })
+}
+
+// The values of `enum('a','b')`, quotes are doubled when escaped
+fn enum_values(column_type: &str) -> Vec<String> {
+    let inner = column_type
+        .split_once('(')
+        .and_then(|(_, rest)| rest.rsplit_once(')'))
+        .map(|(inner, _)| inner)
+        .unwrap_or_default();
+
+    let mut values = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' if in_quotes && chars.peek() == Some(&'\'') => {
+                current.push('\'');
+                chars.next();
+            }
+            '\'' if in_quotes => {
+                values.push(std::mem::take(&mut current));
+                in_quotes = false;
+            }
+            '\'' => in_quotes = true,
+            _ if in_quotes => current.push(c),
+            _ => (),
         }
     }
+    values
+}
+
+/// Maps a `COLUMN_DEFAULT` onto a column default. MariaDB quotes literal
+/// defaults and reports a missing default as `NULL`, MySQL does neither
+pub(crate) fn column_default_from(default: Option<&str>, extra: &str) -> Option<ColumnDefault> {
+    let default = default?;
+    if default == "NULL" {
+        return None;
+    }
+
+    // expression defaults are reported as `_utf8mb4\'[]\'` or `('[]')`
+    let default = default.trim_start_matches("_utf8mb4").replace("\\'", "'");
+    let default = default
+        .strip_prefix('(')
+        .and_then(|d| d.strip_suffix(')'))
+        .unwrap_or(&default);
+    let value = default
+        .strip_prefix('\'')
+        .and_then(|d| d.strip_suffix('\''))
+        .unwrap_or(default);
+    let lower = value.to_lowercase();
+
+    Some(if lower == "uuid()" {
+        ColumnDefault::Uuid
+    } else if lower.starts_with("current_timestamp") || lower == "now()" {
+        if extra.to_lowercase().contains("on update") {
+            ColumnDefault::UpdatedAt
+        } else {
+            ColumnDefault::CreatedAt
+        }
+    } else {
+        match value {
+            "" => ColumnDefault::EmptyString,
+            "0" => ColumnDefault::Zero,
+            "{}" => ColumnDefault::EmptyObject,
+            "[]" => ColumnDefault::EmptyArray,
+            _ => ColumnDefault::Custom(value.to_owned()),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn column_types_are_mapped() {
+        assert_eq!(
+            column_type_from("bigint(20) unsigned", "auto_increment").unwrap(),
+            ColumnType::AutoIncrementId
+        );
+        assert_eq!(
+            column_type_from("tinyint(1)", "").unwrap(),
+            ColumnType::Boolean
+        );
+        assert_eq!(
+            column_type_from("char(26)", "").unwrap(),
+            ColumnType::Char(26)
+        );
+        assert_eq!(
+            column_type_from("varchar(512)", "").unwrap(),
+            ColumnType::String(512)
+        );
+        assert_eq!(column_type_from("bigint", "").unwrap(), ColumnType::Integer);
+        assert_eq!(
+            column_type_from("decimal(10,2)", "").unwrap(),
+            ColumnType::Decimal {
+                precision: 10,
+                scale: 2
+            }
+        );
+        assert_eq!(column_type_from("longtext", "").unwrap(), ColumnType::Text);
+        assert_eq!(
+            column_type_from("smallint(6)", "").unwrap(),
+            ColumnType::SmallInteger
+        );
+        assert_eq!(
+            column_type_from("varbinary(16)", "").unwrap(),
+            ColumnType::Binary(16)
+        );
+        assert_eq!(
+            column_type_from("enum('draft','it''s')", "").unwrap(),
+            ColumnType::Enum(vec!["draft".to_owned(), "it's".to_owned()])
+        );
+
+        let column = column_from_row("views", "bigint(20) unsigned", "NO", None, "").unwrap();
+        assert!(column.is_unsigned);
+    }
+
+    #[test]
+    fn lossy_column_types_are_rejected() {
+        for column_type in ["int(11)", "year", "set('a','b')", "bit(1)", "geometry"] {
+            assert!(
+                column_type_from(column_type, "").is_err(),
+                "{}",
+                column_type
+            );
+        }
+        assert!(column_type_from("int(10) unsigned", "auto_increment").is_err());
+        assert!(column_from_row("point", "geometry", "NO", None, "").is_err());
+    }
+
+    #[test]
+    fn column_defaults_are_mapped() {
+        assert_eq!(column_default_from(None, ""), None);
+        assert_eq!(column_default_from(Some("NULL"), ""), None);
+        assert_eq!(
+            column_default_from(Some("current_timestamp()"), ""),
+            Some(ColumnDefault::CreatedAt)
+        );
+        assert_eq!(
+            column_default_from(
+                Some("CURRENT_TIMESTAMP"),
+                "DEFAULT_GENERATED on update CURRENT_TIMESTAMP"
+            ),
+            Some(ColumnDefault::UpdatedAt)
+        );
+        assert_eq!(
+            column_default_from(Some("'0'"), ""),
+            Some(ColumnDefault::Zero)
+        );
+        assert_eq!(
+            column_default_from(Some("'draft'"), ""),
+            Some(ColumnDefault::Custom("draft".to_owned()))
+        );
+    }
+
+    #[test]
+    fn single_column_unique_indexes_mark_the_column() {
+        let mut table = BaseTable::new("users");
+        table
+            .columns
+            .push(column_from_row("id", "char(26)", "NO", None, "").unwrap());
+        table
+            .columns
+            .push(column_from_row("email", "varchar(255)", "YES", None, "").unwrap());
+        table.unique("id", &["id"]);
+        table.unique("email_name", &["email", "name"]);
+        table
+            .unique("email_prefix", &["email"])
+            .set_length("email", 10);
+        apply_indexes(&mut table);
+
+        assert!(table.columns[0].is_unique);
+        assert!(!table.columns[1].is_unique);
+        assert_eq!(table.columns[1].is_nullable, Some(true));
+    }
 }
@@ -1,195 +1,720 @@
+use super::commit_table::MysqlTableManager;
 use crate::base::{
-    column::{BaseColumn, ColumnDefault, ColumnType},
-    helper::generate_ulid,
+    check::BaseCheck,
+    column::{BaseColumn, ColumnDefault, ColumnType, GeneratedStorage},
+    foreign_key::{BaseForeignKey, ForeignKeyAction},
+    helper::binary_to_json,
+    index::{BaseIndex, IndexKind, IndexOrder},
+    pool_set::PoolSet,
     query::QueryBuilder,
+    query_cache::QueryCache,
     query_conditions::Condition,
     query_operators::Operator,
-    query_values::Value,
+    query_values::{ColumnAndValue, Value},
     schema::SchemaManagerTrait,
+    schema_diff::{SchemaDiff, SchemaOperation},
     table::BaseTable,
+    where_join_operators::{WhereClause, WhereJoinOperator},
 };
 use async_trait::async_trait;
-use futures::stream::TryStreamExt;
-use sqlx::{any::AnyKind, mysql::MySqlRow, types::chrono, Column, MySql, Pool, Row};
-use std::sync::Arc;
+use futures::lock::Mutex;
+use sqlx::{
+    any::AnyKind,
+    mysql::{MySqlConnection, MySqlRow},
+    types::chrono,
+    Column, Connection, MySql, Pool, Row, Transaction,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+const DEFAULT_ENGINE: &str = "InnoDB";
+const DEFAULT_CHARSET: &str = "utf8mb4";
+const DEFAULT_COLLATION: &str = "utf8mb4_unicode_ci";
 
 struct ActiveQuery {
     statement: String,
     params: Vec<String>,
+    tables: Vec<String>,
+    cache_ttl: Option<Duration>,
+    on_primary: bool,
 }
 pub struct MySqlSchemaManager {
-    db_pool: Arc<Pool<MySql>>,
+    db_pools: Arc<PoolSet<MySql>>,
+    db_pool: Pool<MySql>,
     active_query: Option<ActiveQuery>,
+    query_cache: Option<Arc<QueryCache>>,
+    transaction: Mutex<Option<Transaction<'static, MySql>>>,
+    // the tables written in the transaction, invalidated when it ends
+    written_tables: Mutex<Vec<String>>,
+    // advisory locks belong to the connection that took them
+    locks: Mutex<HashMap<String, MySqlConnection>>,
+    read_your_writes: bool,
+    has_written: AtomicBool,
 }
 
 impl MySqlSchemaManager {
-    pub fn new(db_pool: Arc<Pool<MySql>>) -> Self {
+    pub fn new(db_pools: Arc<PoolSet<MySql>>) -> Self {
         Self {
-            db_pool,
+            db_pool: db_pools.primary().clone(),
+            db_pools,
             active_query: None,
+            query_cache: None,
+            transaction: Mutex::new(None),
+            written_tables: Mutex::new(Vec::new()),
+            locks: Mutex::new(HashMap::new()),
+            read_your_writes: false,
+            has_written: AtomicBool::new(false),
         }
     }
 }
 
 #[async_trait]
 impl SchemaManagerTrait for MySqlSchemaManager {
-    fn instance(db_pool: Arc<Pool<MySql>>) -> Self
-    where
-        Self: Sized,
-    {
-        Self::new(db_pool)
-    }
-
     fn kind(&self) -> AnyKind {
         AnyKind::MySql
         // self.db_pool.any_kind()
     }
 
-    fn fetch_table_for_update(&self, name: &str) -> BaseTable {
-        BaseTable::new(name)
+    fn set_query_cache(&mut self, cache: Arc<QueryCache>) {
+        self.query_cache = Some(cache);
+    }
+
+    fn set_read_your_writes(&mut self, enabled: bool) {
+        self.read_your_writes = enabled;
+    }
+
+    async fn fetch_table(&self, name: &str) -> anyhow::Result<Option<BaseTable>> {
+        MysqlTableManager::new(&self.db_pool)
+            .fetch_table(name)
+            .await
+    }
+
+    async fn table_names(&self) -> anyhow::Result<Vec<String>> {
+        MysqlTableManager::new(&self.db_pool).table_names().await
+    }
+
+    async fn fetch_table_for_update(&self, name: &str) -> anyhow::Result<BaseTable> {
+        Ok(match self.fetch_table(name).await? {
+            Some(mut table) => {
+                // unique columns already carry these
+                table.indexes.retain(|index| !index.is_single_unique());
+                table
+            }
+            None => BaseTable::new(name),
+        })
     }
     async fn has_table(&self, name: &str) -> bool {
-        let query = "SELECT table_name FROM INFORMATION_SCHEMA.TABLES WHERE table_name = ?";
+        let query = "SELECT table_name FROM INFORMATION_SCHEMA.TABLES \
+            WHERE table_schema = DATABASE() AND table_name = ?";
 
         let result = sqlx::query(query)
             .bind(name)
             .map(|_row| true)
-            .fetch_one(self.db_pool.as_ref())
+            .fetch_one(&self.db_pool)
             .await;
 
         result.unwrap_or(false)
     }
 
-    async fn commit(&self, table: BaseTable) {
+    async fn commit(&self, table: BaseTable) -> anyhow::Result<()> {
+        self.invalidate_cache(&table.name);
         self.do_commit(table).await
     }
 
+    fn table_statements(&self, table: &BaseTable, live: Option<&BaseTable>) -> Vec<String> {
+        match live {
+            Some(live) => self.diff_statements(&SchemaDiff::between(table, live)),
+            None => vec![self.create_table_statement(table)],
+        }
+    }
+
+    async fn apply_diff(&self, diff: &SchemaDiff) -> anyhow::Result<()> {
+        self.invalidate_cache(&diff.table);
+        for statement in self.diff_statements(diff) {
+            sqlx::query(&statement).execute(&self.db_pool).await?;
+        }
+        Ok(())
+    }
+
+    async fn drop_table(&self, name: &str) -> anyhow::Result<()> {
+        self.invalidate_cache(name);
+        sqlx::query(&format!("DROP TABLE IF EXISTS `{}`", name))
+            .execute(&self.db_pool)
+            .await?;
+        Ok(())
+    }
+
     fn query(&mut self, query: QueryBuilder) -> &dyn SchemaManagerTrait
     where
         Self: Sized,
     {
+        self.active_query = Some(self.active_query_from(&query));
+        self
+    }
+
+    async fn try_fetch_all_as_json(&self) -> anyhow::Result<Vec<serde_json::Value>> {
+        match &self.active_query {
+            Some(active_query) => self.fetch_active_query(active_query).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn fetch(&self, query: QueryBuilder) -> anyhow::Result<Vec<serde_json::Value>> {
+        self.fetch_active_query(&self.active_query_from(&query))
+            .await
+    }
+
+    async fn insert(&self, table_name: &str, record: ColumnAndValue) -> anyhow::Result<()> {
+        let mut columns = Vec::new();
+        let mut placeholders = Vec::new();
         let mut params = Vec::new();
-        let statement = self.build_query(&query, &mut params);
 
-        self.active_query = Some(ActiveQuery { statement, params });
+        for (column, value) in record {
+            columns.push(format!("`{}`", column));
+            placeholders.push(self.value_placeholder(&value));
+            value.to_param(&mut params);
+        }
 
-        self
+        let statement = format!(
+            "INSERT INTO `{}` ({}) VALUES ({})",
+            table_name,
+            columns.join(","),
+            placeholders.join(",")
+        );
+
+        self.execute(&statement, &params).await?;
+        self.invalidate_written(&[table_name.to_owned()]).await;
+        Ok(())
     }
 
-    async fn fetch_all_as_json(&self) -> Vec<serde_json::Value> {
-        let mut results = Vec::new();
-        match &self.active_query {
-            Some(active_query) => {
-                let mut query = sqlx::query(&active_query.statement);
-                for p in &active_query.params {
-                    query = query.bind::<&str>(p);
-                }
+    async fn update(&self, query: QueryBuilder) -> anyhow::Result<u64> {
+        let mut params = Vec::new();
+        let mut sets = Vec::new();
 
-                let mut rows = query.fetch(self.db_pool.as_ref());
-                while let Some(row) = rows.try_next().await.ok().unwrap_or_default() {
-                    results.push(self.row_to_json(&row));
-                }
+        if let Some(columns) = query.set_columns() {
+            for (column, value) in columns {
+                sets.push(format!("`{}` = {}", column, self.value_placeholder(value)));
+                value.to_param(&mut params);
             }
-            None => (),
         }
 
-        results
+        if sets.is_empty() {
+            return Ok(0);
+        }
+
+        let statement = format!(
+            "UPDATE {} SET {} {}",
+            query.tables().join(","),
+            sets.join(","),
+            self.build_where_clauses(&query, &mut params)
+        );
+
+        let affected = self.execute(&statement, &params).await?;
+        self.invalidate_written(query.tables()).await;
+        Ok(affected)
+    }
+
+    async fn delete(&self, query: QueryBuilder) -> anyhow::Result<u64> {
+        let mut params = Vec::new();
+        let statement = format!(
+            "DELETE FROM {} {}",
+            query.tables().join(","),
+            self.build_where_clauses(&query, &mut params)
+        );
+
+        let affected = self.execute(&statement, &params).await?;
+        self.invalidate_written(query.tables()).await;
+        Ok(affected)
+    }
+
+    async fn begin_transaction(&self) -> anyhow::Result<()> {
+        let mut transaction = self.transaction.lock().await;
+        if transaction.is_some() {
+            anyhow::bail!("a transaction is already in progress");
+        }
+
+        *transaction = Some(self.db_pool.begin().await?);
+        Ok(())
+    }
+
+    async fn commit_transaction(&self) -> anyhow::Result<()> {
+        let result = match self.transaction.lock().await.take() {
+            Some(transaction) => transaction.commit().await,
+            None => anyhow::bail!("there is no transaction to commit"),
+        };
+        self.invalidate_transaction_writes().await;
+        Ok(result?)
+    }
+
+    async fn rollback_transaction(&self) -> anyhow::Result<()> {
+        let result = match self.transaction.lock().await.take() {
+            Some(transaction) => transaction.rollback().await,
+            None => anyhow::bail!("there is no transaction to roll back"),
+        };
+        self.invalidate_transaction_writes().await;
+        Ok(result?)
+    }
+
+    async fn acquire_lock(&self, name: &str, timeout: Duration) -> anyhow::Result<()> {
+        if self.locks.lock().await.contains_key(name) {
+            anyhow::bail!("the lock `{}` is already held", name);
+        }
+
+        // detached so that a connection still holding the lock closes
+        // instead of going back to the pool. `locks` is not held while
+        // waiting, other locks can be taken and released meanwhile
+        let mut connection = self.db_pool.acquire().await?.detach();
+        let acquired: Option<i64> = sqlx::query_scalar("SELECT GET_LOCK(?, ?)")
+            .bind(name)
+            .bind(timeout.as_secs())
+            .fetch_one(&mut connection)
+            .await?;
+
+        if acquired != Some(1) {
+            connection.close().await?;
+            anyhow::bail!(
+                "could not acquire the lock `{}` within {} seconds",
+                name,
+                timeout.as_secs()
+            )
+        }
+
+        let mut locks = self.locks.lock().await;
+        if locks.contains_key(name) {
+            drop(locks);
+            sqlx::query("SELECT RELEASE_LOCK(?)")
+                .bind(name)
+                .execute(&mut connection)
+                .await?;
+            connection.close().await?;
+            anyhow::bail!("the lock `{}` is already held", name);
+        }
+        locks.insert(name.to_owned(), connection);
+        Ok(())
+    }
+
+    async fn release_lock(&self, name: &str) -> anyhow::Result<()> {
+        let mut connection = match self.locks.lock().await.remove(name) {
+            Some(connection) => connection,
+            None => anyhow::bail!("the lock `{}` is not held", name),
+        };
+
+        sqlx::query("SELECT RELEASE_LOCK(?)")
+            .bind(name)
+            .execute(&mut connection)
+            .await?;
+        connection.close().await?;
+        Ok(())
     }
 }
 
 impl MySqlSchemaManager {
-    async fn do_commit(&self, table: BaseTable) {
+    /// Compiles a select query into its statement and params
+    pub fn to_sql(&self, query: &QueryBuilder) -> (String, Vec<String>) {
+        let mut params = Vec::new();
+        let statement = self.build_query(query, &mut params);
+        (statement, params)
+    }
+
+    async fn execute(&self, statement: &str, params: &[String]) -> anyhow::Result<u64> {
+        let mut query = sqlx::query(statement);
+        for p in params {
+            query = query.bind::<&str>(p);
+        }
+
+        self.has_written.store(true, Ordering::Relaxed);
+        let result = match self.transaction.lock().await.as_mut() {
+            Some(transaction) => query.execute(transaction).await?,
+            None => query.execute(&self.db_pool).await?,
+        };
+
+        Ok(result.rows_affected())
+    }
+
+    // Replicas serve reads unless the query asks for the primary, or
+    // this instance has written and should read its own writes
+    fn read_pool(&self, on_primary: bool) -> &Pool<MySql> {
+        if on_primary || (self.read_your_writes && self.has_written.load(Ordering::Relaxed)) {
+            &self.db_pool
+        } else {
+            self.db_pools.reader()
+        }
+    }
+
+    fn active_query_from(&self, query: &QueryBuilder) -> ActiveQuery {
+        let mut params = Vec::new();
+        let statement = self.build_query(query, &mut params);
+
+        ActiveQuery {
+            statement,
+            params,
+            tables: query.all_tables(),
+            cache_ttl: query.cache_ttl(),
+            on_primary: query.is_on_primary(),
+        }
+    }
+
+    async fn fetch_active_query(
+        &self,
+        active_query: &ActiveQuery,
+    ) -> anyhow::Result<Vec<serde_json::Value>> {
+        let mut results = Vec::new();
+        let in_transaction = self.transaction.lock().await.is_some();
+        let cache = match (&self.query_cache, active_query.cache_ttl) {
+            (Some(cache), Some(ttl)) if !in_transaction => Some((
+                cache,
+                ttl,
+                QueryCache::key(&active_query.statement, &active_query.params),
+                cache.generation(),
+            )),
+            _ => None,
+        };
+
+        if let Some((cache, _, key, _)) = &cache {
+            if let Some(rows) = cache.get(key) {
+                return Ok(rows);
+            }
+        }
+
+        let mut query = sqlx::query(&active_query.statement);
+        for p in &active_query.params {
+            query = query.bind::<&str>(p);
+        }
+
+        // rows from a replica that missed a recent write would stay cached
+        let on_primary = active_query.on_primary
+            || cache
+                .as_ref()
+                .is_some_and(|(cache, ..)| cache.is_recently_written(&active_query.tables));
+
+        let rows = match self.transaction.lock().await.as_mut() {
+            Some(transaction) => query.fetch_all(transaction).await?,
+            None => query.fetch_all(self.read_pool(on_primary)).await?,
+        };
+
+        for row in rows {
+            results.push(self.row_to_json(&row));
+        }
+
+        if let Some((cache, ttl, key, generation)) = cache {
+            cache.put(
+                &key,
+                active_query.tables.clone(),
+                results.clone(),
+                ttl,
+                generation,
+            );
+        }
+
+        Ok(results)
+    }
+
+    fn invalidate_cache(&self, table: &str) {
+        if let Some(cache) = &self.query_cache {
+            cache.invalidate_table(table);
+        }
+    }
+
+    // Other connections only see a transaction's writes once it commits.
+    // Invalidating earlier would let them cache the old rows again
+    async fn invalidate_written(&self, tables: &[String]) {
+        if self.transaction.lock().await.is_some() {
+            self.written_tables
+                .lock()
+                .await
+                .extend(tables.iter().cloned());
+        } else {
+            tables.iter().for_each(|t| self.invalidate_cache(t));
+        }
+    }
+
+    async fn invalidate_transaction_writes(&self) {
+        let tables = std::mem::take(&mut *self.written_tables.lock().await);
+        tables.iter().for_each(|t| self.invalidate_cache(t));
+    }
+
+    fn value_placeholder(&self, value: &Value) -> String {
+        match value {
+            Value::Null => "NULL".to_owned(),
+            _ => "?".to_owned(),
+        }
+    }
+
+    async fn do_commit(&self, table: BaseTable) -> anyhow::Result<()> {
         if table.is_new() {
-            println!("create new table");
             self.create_table(table).await
         } else {
-            self.create_table(table).await
+            let live = self.fetch_table_for_update(&table.name).await?;
+            for statement in self.diff_statements(&SchemaDiff::between(&table, &live)) {
+                self.run_schema_statement(&statement).await?;
+            }
+            Ok(())
         }
     }
 
-    async fn create_table(&self, table: BaseTable) {
-        let columns: Vec<String> = table
+    async fn create_table(&self, table: BaseTable) -> anyhow::Result<()> {
+        let query = self.create_table_statement(&table);
+        self.run_schema_statement(&query).await
+    }
+
+    async fn run_schema_statement(&self, query: &str) -> anyhow::Result<()> {
+        log::debug!("{}", query);
+        sqlx::query(query).execute(&self.db_pool).await?;
+        Ok(())
+    }
+
+    fn create_table_statement(&self, table: &BaseTable) -> String {
+        let mut entries: Vec<String> = table
             .columns()
             .iter()
+            .filter(|column| !column.has_pivot_table())
             .map(|column| self.create_column(column))
             .collect();
 
-        let query = format!(
-            "CREATE TABLE `{}` (\n{}\n) ENGINE='InnoDB';",
-            &table.name,
-            columns.join(",\n")
+        let primary_key = table.primary_key_columns();
+        if !primary_key.is_empty() {
+            entries.push(format!("PRIMARY KEY ({})", quoted_names(&primary_key)));
+        }
+
+        entries.extend(
+            table
+                .indexes
+                .iter()
+                .map(|index| self.index_definition(index)),
+        );
+
+        entries.extend(
+            table
+                .check_constraints()
+                .iter()
+                .map(|check| self.check_definition(check)),
         );
 
-        let result = sqlx::query(&query).execute(self.db_pool.as_ref()).await;
+        entries.extend(
+            table
+                .foreign_key_constraints()
+                .iter()
+                .map(|key| self.foreign_key_definition(&table.name, key)),
+        );
 
-        match result {
-            Ok(x) => {
-                println!("----------------------- ok result -------------");
-                dbg!(x);
-                println!("----------------------- ok result -------------");
-            }
-            Err(e) => {
-                println!("----------------------- error result -------------");
-                dbg!(e.to_string());
-                println!("----------------------- error result -------------");
-            }
+        format!(
+            "CREATE TABLE `{}` (\n{}\n) {};",
+            &table.name,
+            entries.join(",\n"),
+            self.table_options(table)
+        )
+    }
+
+    // The character set and collation default together, setting either
+    // leaves the other to MySQL
+    fn table_options(&self, table: &BaseTable) -> String {
+        let mut options = vec![format!(
+            "ENGINE={}",
+            quoted_value(table.engine.as_deref().unwrap_or(DEFAULT_ENGINE))
+        )];
+
+        let (charset, collation) = match (&table.charset, &table.collation) {
+            (None, None) => (Some(DEFAULT_CHARSET), Some(DEFAULT_COLLATION)),
+            (charset, collation) => (charset.as_deref(), collation.as_deref()),
+        };
+        if let Some(charset) = charset {
+            options.push(format!("DEFAULT CHARSET={}", quoted_value(charset)));
+        }
+        if let Some(collation) = collation {
+            options.push(format!("COLLATE={}", quoted_value(collation)));
+        }
+        if let Some(comment) = &table.comment {
+            options.push(format!("COMMENT={}", quoted_value(comment)));
         }
 
-        if self.has_table("_core_users").await {
-            let new_user_query = "INSERT INTO `_core_users` (`id`, `username`, `email`)
-VALUES (?, ?, ?);";
+        options.join(" ")
+    }
 
-            let id = generate_ulid();
+    // Foreign keys are dropped in their own statement as MySQL does not
+    // allow dropping and adding a constraint in the same ALTER
+    fn diff_statements(&self, diff: &SchemaDiff) -> Vec<String> {
+        let mut statements = Vec::new();
+        let name = &diff.table;
+        let mut drops = Vec::new();
+        let mut changes = Vec::new();
 
-            let result = sqlx::query(new_user_query)
-                .bind(&id)
-                .bind("first_user")
-                .bind(34)
-                .execute(self.db_pool.as_ref())
-                .await;
-            match result {
-                Ok(_) => {
-                    println!("user was created")
+        for change in &diff.changes {
+            match &change.operation {
+                SchemaOperation::DropForeignKey(key) => {
+                    drops.push(format!("DROP FOREIGN KEY `{}`", key))
+                }
+                SchemaOperation::DropIndex(index) => {
+                    changes.push(format!("DROP INDEX `{}`", index))
+                }
+                SchemaOperation::DropCheck(check) => {
+                    changes.push(format!("DROP CHECK `{}`", check))
+                }
+                SchemaOperation::AddColumn(column) => {
+                    changes.push(
+                        self.with_after(
+                            format!("ADD COLUMN {}", self.create_column(column)),
+                            column,
+                        ),
+                    );
+                }
+                SchemaOperation::AlterColumn(column) => {
+                    let definition = match &column.new_name {
+                        Some(new_name) => format!(
+                            "CHANGE COLUMN `{}` {}",
+                            &column.name,
+                            self.column_definition(new_name, column)
+                        ),
+                        None => format!(
+                            "MODIFY COLUMN {}",
+                            self.column_definition(&column.name, column)
+                        ),
+                    };
+                    changes.push(self.with_after(definition, column));
+                }
+                SchemaOperation::DropPrimaryKey => changes.push("DROP PRIMARY KEY".to_owned()),
+                SchemaOperation::AddPrimaryKey(columns) => {
+                    changes.push(format!("ADD PRIMARY KEY ({})", quoted_names(columns)))
+                }
+                SchemaOperation::AddUnique(column) => {
+                    changes.push(format!("ADD UNIQUE (`{}`)", column))
+                }
+                SchemaOperation::AddIndex(index) => {
+                    changes.push(format!("ADD {}", self.index_definition(index)))
                 }
-                Err(e) => {
-                    dbg!(e.to_string());
+                SchemaOperation::AddCheck(check) => {
+                    changes.push(format!("ADD {}", self.check_definition(check)))
+                }
+                SchemaOperation::DropColumn(column) => {
+                    changes.push(format!("DROP COLUMN `{}`", column))
+                }
+                SchemaOperation::AddForeignKey(key) => {
+                    changes.push(format!("ADD {}", self.foreign_key_definition(name, key)))
+                }
+                SchemaOperation::SetEngine(engine) => {
+                    changes.push(format!("ENGINE={}", quoted_value(engine)))
+                }
+                SchemaOperation::SetCharset(charset) => {
+                    changes.push(format!("DEFAULT CHARSET={}", quoted_value(charset)))
+                }
+                SchemaOperation::SetCollation(collation) => {
+                    changes.push(format!("COLLATE={}", quoted_value(collation)))
+                }
+                SchemaOperation::SetComment(comment) => {
+                    changes.push(format!("COMMENT={}", quoted_value(comment)))
+                }
+                SchemaOperation::RenameTable(new_name) => {
+                    changes.push(format!("RENAME TO `{}`", new_name))
                 }
             }
         }
 
-        println!("update existing table");
-        dbg!(query);
+        if !drops.is_empty() {
+            statements.push(format!("ALTER TABLE `{}` {};", name, drops.join(", ")));
+        }
+
+        if !changes.is_empty() {
+            statements.push(format!("ALTER TABLE `{}` {};", name, changes.join(", ")));
+        }
+
+        statements
+    }
+
+    fn index_definition(&self, index: &BaseIndex) -> String {
+        let kind = match index.kind {
+            IndexKind::Index => "INDEX",
+            IndexKind::Unique => "UNIQUE INDEX",
+            IndexKind::Fulltext => "FULLTEXT INDEX",
+        };
+        let columns: Vec<String> = index
+            .columns
+            .iter()
+            .map(|column| {
+                let mut entry = format!("`{}`", column.name);
+                if let Some(length) = column.length {
+                    entry.push_str(&format!("({})", length));
+                }
+                if column.order == IndexOrder::Desc {
+                    entry.push_str(" DESC");
+                }
+                entry
+            })
+            .collect();
+
+        format!("{} `{}` ({})", kind, index.name, columns.join(", "))
+    }
+
+    fn with_after(&self, mut definition: String, column: &BaseColumn) -> String {
+        if let Some(after) = &column.after {
+            definition.push_str(&format!(" AFTER `{}`", after));
+        }
+        definition
+    }
+
+    fn foreign_key_definition(&self, table: &str, key: &BaseForeignKey) -> String {
+        let mut clause = format!(
+            "CONSTRAINT `{}` FOREIGN KEY ({}) REFERENCES `{}` ({})",
+            key.name_in(table),
+            quoted_names(&key.columns),
+            &key.table,
+            quoted_names(&key.references)
+        );
+        if key.on_delete != ForeignKeyAction::NoAction {
+            clause.push_str(&format!(
+                " ON DELETE {}",
+                foreign_key_action_sql(key.on_delete)
+            ));
+        }
+        if key.on_update != ForeignKeyAction::NoAction {
+            clause.push_str(&format!(
+                " ON UPDATE {}",
+                foreign_key_action_sql(key.on_update)
+            ));
+        }
+        clause
+    }
+
+    fn check_definition(&self, check: &BaseCheck) -> String {
+        format!("CONSTRAINT `{}` CHECK ({})", check.name, check.expression)
     }
 
     fn create_column(&self, column: &BaseColumn) -> String {
-        let mut entry = format!("`{}`", &column.name);
+        let mut definition = self.column_definition(&column.name, column);
+        if column.is_unique {
+            definition.push_str(" UNIQUE");
+        }
+        definition
+    }
+
+    fn column_definition(&self, name: &str, column: &BaseColumn) -> String {
+        let mut entry = format!("`{}`", name);
         let mut the_type = " ".to_owned();
 
         // column type
-        match column.column_type {
-            ColumnType::AutoIncrementId => {
-                the_type.push_str("bigint(20) unsigned AUTO_INCREMENT PRIMARY KEY")
-            }
-            ColumnType::Boolean => the_type.push_str("tinyint(1)"),
-            ColumnType::Char(length) => {
-                the_type.push_str(&format!("char({}) COLLATE 'utf8mb4_unicode_ci'", length))
-            }
-            ColumnType::Date => the_type.push_str("datetime"),
-            // ColumnType::File() shouldn't be here
-            // ColumnType::Float not sure
-            ColumnType::Integer => the_type.push_str("bigint(20)"),
-            ColumnType::Json => the_type.push_str("json"),
-            ColumnType::Number => the_type.push_str("double"),
-            // ColumnType::Relation { relation_type, table_name }
-            // ColumnType::Select()
-            ColumnType::String(length) => {
-                let q = format!("varchar({}) COLLATE 'utf8mb4_unicode_ci'", length);
-                the_type.push_str(q.as_str());
-            }
-            ColumnType::Text => the_type.push_str("longtext"),
-            ColumnType::Uuid => the_type.push_str("uuid"),
-            _ => the_type.push_str("varchar(255)"),
-        };
+        the_type.push_str(&column_type_sql(&column.column_type));
+        if column.is_unsigned && column.column_type.is_numeric() {
+            the_type.push_str(" unsigned");
+        }
+        if let Some(collation) = &column.collation {
+            the_type.push_str(&format!(" COLLATE {}", quoted_value(collation)));
+        }
+        if let Some(generated) = &column.generated {
+            the_type.push_str(&format!(
+                " GENERATED ALWAYS AS ({}) {}",
+                generated.expression,
+                match generated.storage {
+                    GeneratedStorage::Virtual => "VIRTUAL",
+                    GeneratedStorage::Stored => "STORED",
+                }
+            ));
+        } else if column.column_type == ColumnType::AutoIncrementId {
+            the_type.push_str(" AUTO_INCREMENT");
+        }
 
         // column is nullable
         if let Some(nullable) = column.is_nullable {
@@ -200,40 +725,31 @@ VALUES (?, ?, ?);";
             }
         }
 
-        // column is unique
-        if column.is_unique {
-            the_type.push_str(" UNIQUE");
-        }
-
-        // column default
-        if let Some(default) = &column.default {
-            the_type.push_str(" DEFAULT ");
-            match default {
-                ColumnDefault::CreatedAt => the_type.push_str("now()"),
-                ColumnDefault::Custom(d) => the_type.push_str(&format!("'{}'", d)),
-                ColumnDefault::EmptyArray => the_type.push_str("[]"),
-                ColumnDefault::EmptyObject => the_type.push_str("{}"),
-                ColumnDefault::EmptyString => the_type.push_str(""),
-                ColumnDefault::Uuid => the_type.push_str("SYS_GUID()"),
-                ColumnDefault::Ulid => (),
-                ColumnDefault::UpdatedAt => {
-                    the_type.push_str("current_timestamp() ON UPDATE CURRENT_TIMESTAMP")
-                }
-                ColumnDefault::Zero => the_type.push('0'),
-            };
+        // column default. MySQL has no ULID function and generated
+        // columns have none
+        let default = match column
+            .default
+            .as_ref()
+            .filter(|_| column.generated.is_none())
+        {
+            Some(ColumnDefault::CreatedAt) => Some("now()".to_owned()),
+            Some(ColumnDefault::Custom(d)) => Some(quoted_value(d)),
+            Some(ColumnDefault::EmptyArray) => Some("('[]')".to_owned()),
+            Some(ColumnDefault::EmptyObject) => Some("('{}')".to_owned()),
+            Some(ColumnDefault::EmptyString) => Some("''".to_owned()),
+            Some(ColumnDefault::Uuid) => Some("(uuid())".to_owned()),
+            Some(ColumnDefault::Ulid) | None => None,
+            Some(ColumnDefault::UpdatedAt) => {
+                Some("current_timestamp() ON UPDATE CURRENT_TIMESTAMP".to_owned())
+            }
+            Some(ColumnDefault::Zero) => Some("0".to_owned()),
+        };
+        if let Some(default) = default {
+            the_type.push_str(&format!(" DEFAULT {}", default));
         }
 
-        // column relationship
-        if let Some(relationship) = &column.relationship {
-            the_type.push_str(&format!(
-                ", FOREIGN KEY (`{}`) REFERENCES `{}` (`{}`)",
-                &column.name,
-                &relationship.table(),
-                &relationship.column()
-            ));
-            if relationship.cascade_delete() {
-                the_type.push_str(" ON DELETE CASCADE");
-            }
+        if let Some(comment) = &column.comment {
+            the_type.push_str(&format!(" COMMENT {}", quoted_value(comment)));
         }
 
         entry.push_str(&the_type);
@@ -288,70 +804,84 @@ VALUES (?, ?, ?);";
         // wheres
         sql = format!("{} {}", sql, self.build_where_clauses(query, params));
 
+        if query.is_for_update() {
+            sql.push_str(" FOR UPDATE");
+        }
+
         sql
     }
 
     fn build_where_clauses(&self, query: &QueryBuilder, params: &mut Vec<String>) -> String {
-        let mut wheres = "".to_owned();
-        for where_join in query.where_clauses() {
-            wheres = where_join.as_clause(
-                &wheres,
-                &self.transform_condition(where_join.condition(), params),
-            );
+        let wheres = self.build_clauses(query.where_clauses(), params);
+
+        if wheres.is_empty() {
+            wheres
+        } else {
+            format!("WHERE {}", wheres)
         }
+    }
 
-        if !wheres.is_empty() {
-            wheres = format!("WHERE {}", wheres);
+    fn build_clauses(&self, clauses: &[WhereJoinOperator], params: &mut Vec<String>) -> String {
+        let mut wheres = "".to_owned();
+        for where_join in clauses {
+            let condition = match where_join.clause() {
+                WhereClause::Condition(condition) => self.transform_condition(condition, params),
+                WhereClause::Group(group) => format!("({})", self.build_clauses(group, params)),
+            };
+            wheres = where_join.as_clause(&wheres, &condition);
         }
 
         wheres
     }
 
     fn transform_condition(&self, condition: &Condition, params: &mut Vec<String>) -> String {
-        self.transform_value(condition.value(), params);
-
-        let placeholder =
-            if *condition.operator() == Operator::In || *condition.operator() == Operator::NotIn {
-                let length = match &condition.value() {
-                    Value::I64s(v) => v.len(),
-                    Value::U64s(v) => v.len(),
-                    Value::Strings(v) => v.len(),
-                    _ => 1,
-                };
-
-                let mut placeholder = Vec::new();
-                placeholder.resize(length, "?");
-                placeholder.join(",")
-            } else {
-                "?".to_owned()
-            };
+        let is_list =
+            *condition.operator() == Operator::In || *condition.operator() == Operator::NotIn;
+
+        let placeholder = match condition.value() {
+            Value::SubQuery(q) if is_list => self.build_query(q, params),
+            Value::SubQuery(q) => format!("({})", self.build_query(q, params)),
+            value => {
+                value.to_param(params);
+                if is_list {
+                    let length = match value {
+                        Value::I64s(v) => v.len(),
+                        Value::U64s(v) => v.len(),
+                        Value::F64s(v) => v.len(),
+                        Value::Strings(v) => v.len(),
+                        _ => 1,
+                    };
+
+                    let mut placeholder = Vec::new();
+                    placeholder.resize(length, "?");
+                    placeholder.join(",")
+                } else {
+                    "?".to_owned()
+                }
+            }
+        };
 
         condition
             .operator()
             .as_clause(condition.column(), &placeholder)
     }
 
-    fn transform_value(&self, value: &Value, params: &mut Vec<String>) {
-        match value {
-            Value::SubQuery(q) => {
-                self.build_query(q, params);
-            }
-            _ => value.to_param(params),
-        }
-    }
-
     fn row_to_json(&self, row: &MySqlRow) -> serde_json::Value {
         let mut this_row = serde_json::Map::new();
 
-        // types are from : https://docs.rs/sqlx/latest/sqlx/mysql/types/index.html
         for col in row.columns() {
             let name = col.name().to_owned();
-            match col.type_info().to_string().as_str() {
-                "BOOLEAN" | "TINYINT(1)" => {
+            let type_name = col.type_info().to_string();
+            let Some(json_type) = JsonType::of(&type_name) else {
+                log::warn!("column type not mapped: {:?}", col.type_info());
+                continue;
+            };
+            match json_type {
+                JsonType::Boolean => {
                     let v: bool = row.get(col.name());
                     this_row.insert(name, serde_json::Value::Bool(v));
                 }
-                "TINYINT" => {
+                JsonType::TinyInt => {
                     let v = row.try_get::<i8, &str>(col.name());
                     if let Ok(v) = v {
                         this_row
@@ -363,7 +893,7 @@ VALUES (?, ?, ?);";
                         );
                     }
                 }
-                "SMALLINT" => {
+                JsonType::SmallInt => {
                     let v = row.try_get::<i16, &str>(col.name());
                     if let Ok(v) = v {
                         this_row
@@ -375,7 +905,11 @@ VALUES (?, ?, ?);";
                         );
                     }
                 }
-                "INT" => {
+                JsonType::MediumInt => {
+                    let v = row.try_get::<i32, &str>(col.name()).unwrap_or_default();
+                    this_row.insert(name, serde_json::Value::Number(serde_json::Number::from(v)));
+                }
+                JsonType::Int => {
                     let v = row.try_get::<i32, &str>(col.name());
                     if let Ok(v) = v {
                         this_row
@@ -387,7 +921,7 @@ VALUES (?, ?, ?);";
                         );
                     }
                 }
-                "BIGINT" => {
+                JsonType::BigInt => {
                     let v = row.try_get::<i64, &str>(col.name());
                     if let Ok(v) = v {
                         this_row
@@ -399,7 +933,7 @@ VALUES (?, ?, ?);";
                         );
                     }
                 }
-                "TINYINT UNSIGNED" => {
+                JsonType::UnsignedTinyInt => {
                     let v = row.try_get::<u8, &str>(col.name());
                     if let Ok(v) = v {
                         this_row
@@ -411,7 +945,7 @@ VALUES (?, ?, ?);";
                         );
                     }
                 }
-                "SMALLINT UNSIGNED" => {
+                JsonType::UnsignedSmallInt => {
                     let v = row.try_get::<u16, &str>(col.name());
                     if let Ok(v) = v {
                         this_row
@@ -423,7 +957,11 @@ VALUES (?, ?, ?);";
                         );
                     }
                 }
-                "INT UNSIGNED" => {
+                JsonType::UnsignedMediumInt => {
+                    let v = row.try_get::<u32, &str>(col.name()).unwrap_or_default();
+                    this_row.insert(name, serde_json::Value::Number(serde_json::Number::from(v)));
+                }
+                JsonType::UnsignedInt => {
                     let v = row.try_get::<u32, &str>(col.name());
                     if let Ok(v) = v {
                         this_row
@@ -435,7 +973,7 @@ VALUES (?, ?, ?);";
                         );
                     }
                 }
-                "BIGINT UNSIGNED" => {
+                JsonType::UnsignedBigInt => {
                     let v = row.try_get::<u64, &str>(col.name());
                     if let Ok(v) = v {
                         this_row.insert(
@@ -449,7 +987,7 @@ VALUES (?, ?, ?);";
                         );
                     }
                 }
-                "DOUBLE" | "FLOAT" => {
+                JsonType::Float => {
                     let v = row.try_get::<f64, &str>(col.name());
                     if let Ok(v) = v {
                         this_row.insert(
@@ -465,14 +1003,22 @@ VALUES (?, ?, ?);";
                         );
                     }
                 }
-                "CHAR" | "VARCHAR" | "TEXT" => {
+                // sent as text, a float would lose its precision
+                JsonType::Decimal => {
+                    if let Ok(v) = row.try_get_unchecked::<String, &str>(col.name()) {
+                        this_row.insert(name, serde_json::Value::String(v));
+                    } else {
+                        this_row.insert(name, serde_json::Value::Null);
+                    }
+                }
+                JsonType::String => {
                     if let Ok(v) = row.try_get::<String, &str>(col.name()) {
                         this_row.insert(name, serde_json::Value::String(v));
                     } else {
                         this_row.insert(name, serde_json::Value::Null);
                     }
                 }
-                "TIMESTAMP" => {
+                JsonType::Timestamp => {
                     let v = row.try_get::<chrono::DateTime<chrono::Utc>, &str>(col.name());
                     if let Ok(v) = v {
                         this_row.insert(name, serde_json::Value::String(v.to_string()));
@@ -480,7 +1026,7 @@ VALUES (?, ?, ?);";
                         this_row.insert(name, serde_json::Value::Null);
                     }
                 }
-                "DATE" => {
+                JsonType::Date => {
                     let v = row.try_get::<chrono::NaiveDate, &str>(col.name());
                     if let Ok(v) = v {
                         this_row.insert(name, serde_json::Value::String(v.to_string()));
@@ -488,7 +1034,7 @@ VALUES (?, ?, ?);";
                         this_row.insert(name, serde_json::Value::Null);
                     }
                 }
-                "TIME" => {
+                JsonType::Time => {
                     let v = row.try_get::<chrono::NaiveTime, &str>(col.name());
                     if let Ok(v) = v {
                         this_row.insert(name, serde_json::Value::String(v.to_string()));
@@ -496,7 +1042,7 @@ VALUES (?, ?, ?);";
                         this_row.insert(name, serde_json::Value::Null);
                     }
                 }
-                "DATETIME" => {
+                JsonType::Datetime => {
                     let v = row.try_get::<chrono::NaiveDateTime, &str>(col.name());
 
                     if let Ok(v) = v {
@@ -508,11 +1054,13 @@ VALUES (?, ?, ?);";
                         this_row.insert(col.name().to_owned(), serde_json::Value::Null);
                     }
                 }
-                "VARBINARY" | "BINARY" | "BLOB" => {
-                    // TODO find a mean to represent binary
+                JsonType::Json => {
+                    let v = row.try_get::<serde_json::Value, &str>(col.name());
+                    this_row.insert(name, v.unwrap_or(serde_json::Value::Null));
                 }
-                _ => {
-                    dbg!("not mapped {:#?}", col.type_info());
+                JsonType::Binary => {
+                    let v = row.try_get::<Vec<u8>, &str>(col.name());
+                    this_row.insert(name, v.map(binary_to_json).unwrap_or_default());
                 }
             }
         }
@@ -520,3 +1068,432 @@ VALUES (?, ?, ?);";
         serde_json::Value::Object(this_row)
     }
 }
+
+// How a column is read into JSON, by the name sqlx gives its type
+#[derive(Debug, PartialEq)]
+enum JsonType {
+    Boolean,
+    TinyInt,
+    SmallInt,
+    MediumInt,
+    Int,
+    BigInt,
+    UnsignedTinyInt,
+    UnsignedSmallInt,
+    UnsignedMediumInt,
+    UnsignedInt,
+    UnsignedBigInt,
+    Float,
+    Decimal,
+    String,
+    Timestamp,
+    Date,
+    Time,
+    Datetime,
+    Json,
+    Binary,
+}
+
+impl JsonType {
+    // types are from : https://docs.rs/sqlx/latest/sqlx/mysql/types/index.html
+    fn of(type_name: &str) -> Option<Self> {
+        Some(match type_name {
+            "BOOLEAN" | "TINYINT(1)" => Self::Boolean,
+            "TINYINT" => Self::TinyInt,
+            "SMALLINT" => Self::SmallInt,
+            "MEDIUMINT" => Self::MediumInt,
+            "INT" => Self::Int,
+            "BIGINT" => Self::BigInt,
+            "TINYINT UNSIGNED" => Self::UnsignedTinyInt,
+            "SMALLINT UNSIGNED" => Self::UnsignedSmallInt,
+            "MEDIUMINT UNSIGNED" => Self::UnsignedMediumInt,
+            "INT UNSIGNED" => Self::UnsignedInt,
+            "BIGINT UNSIGNED" => Self::UnsignedBigInt,
+            "DOUBLE" | "FLOAT" => Self::Float,
+            "DECIMAL" => Self::Decimal,
+            "CHAR" | "VARCHAR" | "TEXT" | "ENUM" => Self::String,
+            "TIMESTAMP" => Self::Timestamp,
+            "DATE" => Self::Date,
+            "TIME" => Self::Time,
+            "DATETIME" => Self::Datetime,
+            "JSON" => Self::Json,
+            "VARBINARY" | "BINARY" | "BLOB" => Self::Binary,
+            _ => return None,
+        })
+    }
+}
+
+// The MySQL type of a column, without its attributes
+fn column_type_sql(column_type: &ColumnType) -> String {
+    match column_type.storage_type() {
+        ColumnType::AutoIncrementId => "bigint(20) unsigned".to_owned(),
+        ColumnType::Binary(length) => format!("varbinary({})", length),
+        ColumnType::Blob => "longblob".to_owned(),
+        ColumnType::Boolean => "tinyint(1)".to_owned(),
+        ColumnType::Char(length) => format!("char({})", length),
+        ColumnType::Date => "date".to_owned(),
+        ColumnType::Datetime => "datetime".to_owned(),
+        ColumnType::Decimal { precision, scale } => format!("decimal({},{})", precision, scale),
+        ColumnType::Enum(values) => format!(
+            "enum({})",
+            values
+                .iter()
+                .map(|v| quoted_value(v))
+                .collect::<Vec<String>>()
+                .join(",")
+        ),
+        ColumnType::Float => "float".to_owned(),
+        ColumnType::Integer => "bigint(20)".to_owned(),
+        ColumnType::Json => "json".to_owned(),
+        ColumnType::MediumInteger => "mediumint".to_owned(),
+        ColumnType::Number => "double".to_owned(),
+        ColumnType::SmallInteger => "smallint".to_owned(),
+        ColumnType::String(length) => format!("varchar({})", length),
+        ColumnType::Text => "longtext".to_owned(),
+        ColumnType::Time => "time".to_owned(),
+        ColumnType::Timestamp => "timestamp".to_owned(),
+        ColumnType::TinyInteger => "tinyint".to_owned(),
+        ColumnType::Relation { .. } => {
+            unreachable!("multiple relations are stored in a pivot table")
+        }
+        // storage_type maps these onto the types above
+        ColumnType::File(_) | ColumnType::Select(_) | ColumnType::Uuid => {
+            unreachable!("not a storage type")
+        }
+    }
+}
+
+fn foreign_key_action_sql(action: ForeignKeyAction) -> &'static str {
+    match action {
+        ForeignKeyAction::Cascade => "CASCADE",
+        ForeignKeyAction::SetNull => "SET NULL",
+        ForeignKeyAction::Restrict => "RESTRICT",
+        ForeignKeyAction::NoAction => "NO ACTION",
+    }
+}
+
+fn quoted_value(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "''"))
+}
+
+fn quoted_names(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("`{}`", name))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::base::{
+        column::{ForeignKey, RelationType},
+        pivot::PivotTable,
+    };
+    use sqlx::mysql::MySqlPoolOptions;
+
+    // A manager whose pool never connects. Good enough for SQL generation
+    pub(crate) fn offline_manager() -> MySqlSchemaManager {
+        let pool = MySqlPoolOptions::new()
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_lazy("mysql://root@localhost/dirtybase")
+            .unwrap();
+        MySqlSchemaManager::new(Arc::new(PoolSet::new(pool)))
+    }
+
+    #[test]
+    fn locked_reads_are_for_update() {
+        let mut query = QueryBuilder::new(vec!["users".to_owned()]);
+        query.select("id").eq("id", "a").lock_for_update();
+
+        assert!(query.is_on_primary());
+        assert_eq!(
+            offline_manager().to_sql(&query).0,
+            "SELECT id FROM users WHERE id = ? FOR UPDATE"
+        );
+    }
+
+    #[test]
+    fn grouped_conditions_are_wrapped() {
+        let mut query = QueryBuilder::new(vec!["users".to_owned()]);
+        query.eq("age", 5).or_where_group(|group| {
+            group.eq("name", "x").and_is_in("role", vec!["a", "b"]);
+        });
+
+        let (sql, params) = offline_manager().to_sql(&query);
+        assert_eq!(
+            sql,
+            "SELECT users.* FROM users WHERE age = ? OR (name = ? AND role IN (?,?))"
+        );
+        assert_eq!(params, vec!["5", "x", "a", "b"]);
+    }
+
+    #[test]
+    fn create_table_renders_foreign_keys_as_table_constraints() {
+        let mut table = BaseTable::new("posts");
+        table.id(None);
+        table
+            .ulid("author")
+            .references_with_cascade_delete("users", "id");
+        table
+            .index("posts_author_id", &["author", "id"])
+            .set_length("author", 8)
+            .descending("id");
+
+        assert_eq!(
+            offline_manager().create_table_statement(&table),
+            "CREATE TABLE `posts` (
+`id` bigint(20) unsigned AUTO_INCREMENT NOT NULL,
+`author` char(26) NOT NULL,
+PRIMARY KEY (`id`),
+INDEX `posts_author_id` (`author`(8), `id` DESC),
+CONSTRAINT `posts_author_foreign` FOREIGN KEY (`author`) REFERENCES `users` (`id`) ON DELETE CASCADE
+) ENGINE='InnoDB' DEFAULT CHARSET='utf8mb4' COLLATE='utf8mb4_unicode_ci';"
+        );
+    }
+
+    #[test]
+    fn foreign_keys_have_actions_and_names() {
+        let mut table = BaseTable::new("line_items");
+        table.integer("order_id");
+        table.integer("order_version");
+        table
+            .ulid("product")
+            .set_is_nullable(true)
+            .references_without_cascade_delete("products", "id")
+            .set_on_delete(ForeignKeyAction::SetNull)
+            .set_on_update(ForeignKeyAction::Cascade)
+            .set_foreign_key_name("line_items_product");
+        table
+            .foreign_key(
+                "line_items_order",
+                &["order_id", "order_version"],
+                "orders",
+                &["id", "version"],
+            )
+            .set_on_delete(ForeignKeyAction::Restrict);
+
+        let statement = offline_manager().create_table_statement(&table);
+        assert!(statement.contains(
+            "CONSTRAINT `line_items_product` FOREIGN KEY (`product`) REFERENCES `products` (`id`) ON DELETE SET NULL ON UPDATE CASCADE,\n\
+CONSTRAINT `line_items_order` FOREIGN KEY (`order_id`, `order_version`) REFERENCES `orders` (`id`, `version`) ON DELETE RESTRICT\n"
+        ));
+
+        let mut live = table.clone();
+        live.set_is_new(false);
+        live.foreign_keys[0].set_on_delete(ForeignKeyAction::Cascade);
+        table.drop_foreign_key("line_items_product");
+        assert_eq!(
+            offline_manager().diff_statements(&SchemaDiff::between(&table, &live)),
+            vec![
+                "ALTER TABLE `line_items` DROP FOREIGN KEY `line_items_product`, DROP FOREIGN KEY `line_items_order`;",
+                "ALTER TABLE `line_items` ADD CONSTRAINT `line_items_order` FOREIGN KEY (`order_id`, `order_version`) REFERENCES `orders` (`id`, `version`) ON DELETE RESTRICT;",
+            ]
+        );
+    }
+
+    #[test]
+    fn column_types_render_mysql_types() {
+        let mut table = BaseTable::new("things");
+        table.float("a");
+        table.decimal("b", 8, 2).set_is_unsigned(true);
+        table.file("c", RelationType::Single);
+        table.select("d", RelationType::Multiple(0));
+        table.relation("e", RelationType::Single, "users");
+        table.uuid("f").default_is_uuid();
+        table.text("g");
+        table
+            .enumeration("h", &["draft", "it's"])
+            .set_default("draft");
+        table.small_integer("i").set_is_unsigned(true);
+        table.timestamp("j");
+        table.binary("k", 16);
+        table.json("l").default_is_empty_array();
+        table.ulid("m").default_is_ulid();
+        table
+            .string("n")
+            .set_collation("utf8mb4_bin")
+            .set_comment("Nick's name");
+        table.date("o");
+        table.datetime("p");
+        table.medium_integer("q");
+
+        let manager = offline_manager();
+        let definitions: Vec<String> = table
+            .columns()
+            .iter()
+            .map(|column| manager.create_column(column))
+            .collect();
+
+        assert_eq!(
+            definitions,
+            vec![
+                "`a` float NOT NULL",
+                "`b` decimal(8,2) unsigned NOT NULL",
+                "`c` char(26) NOT NULL",
+                "`d` json NOT NULL",
+                "`e` char(26) NOT NULL",
+                "`f` char(36) NOT NULL DEFAULT (uuid())",
+                "`g` longtext NOT NULL",
+                "`h` enum('draft','it''s') NOT NULL DEFAULT 'draft'",
+                "`i` smallint unsigned NOT NULL",
+                "`j` timestamp NOT NULL",
+                "`k` varbinary(16) NOT NULL",
+                "`l` json NOT NULL DEFAULT ('[]')",
+                "`m` char(26) NOT NULL",
+                "`n` varchar(255) COLLATE 'utf8mb4_bin' NOT NULL COMMENT 'Nick''s name'",
+                "`o` date NOT NULL",
+                "`p` datetime NOT NULL",
+                "`q` mediumint NOT NULL",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_column_type_is_read_into_json() {
+        assert_eq!(JsonType::of("DECIMAL"), Some(JsonType::Decimal));
+        assert_eq!(JsonType::of("MEDIUMINT"), Some(JsonType::MediumInt));
+        assert_eq!(
+            JsonType::of("MEDIUMINT UNSIGNED"),
+            Some(JsonType::UnsignedMediumInt)
+        );
+        assert_eq!(JsonType::of("ENUM"), Some(JsonType::String));
+        assert_eq!(JsonType::of("DATE"), Some(JsonType::Date));
+        assert_eq!(JsonType::of("GEOMETRY"), None);
+    }
+
+    #[test]
+    fn table_options_are_rendered_and_altered() {
+        let mut table = BaseTable::new("logs");
+        table.text("message").set_comment("Message");
+        table.set_engine("MyISAM").set_charset("latin1");
+
+        assert!(offline_manager()
+            .create_table_statement(&table)
+            .ends_with(") ENGINE='MyISAM' DEFAULT CHARSET='latin1';"));
+
+        let mut live = table.clone();
+        live.set_is_new(false);
+        live.set_engine("InnoDB").set_collation("latin1_swedish_ci");
+        live.columns[0].comment = Some("Message".to_owned());
+        table.set_comment("Request logs");
+        table.text("message");
+
+        assert_eq!(
+            offline_manager().diff_statements(&SchemaDiff::between(&table, &live)),
+            vec!["ALTER TABLE `logs` ENGINE='MyISAM', COMMENT='Request logs';"]
+        );
+    }
+
+    #[test]
+    fn checks_and_generated_columns() {
+        let mut table = BaseTable::new("products");
+        table.json("meta");
+        table
+            .sized_string("sku", 32)
+            .set_is_nullable(true)
+            .set_virtual_as("meta->>'$.sku'");
+        table
+            .decimal("price", 10, 2)
+            .default_is_zero()
+            .set_check("price >= 0");
+        table.check(
+            "products_sku_or_meta",
+            "sku is not null or meta is not null",
+        );
+        table.index("products_sku", &["sku"]);
+
+        let statement = offline_manager().create_table_statement(&table);
+        assert!(statement.contains(
+            "`sku` varchar(32) GENERATED ALWAYS AS (meta->>'$.sku') VIRTUAL NULL,\n\
+`price` decimal(10,2) NOT NULL DEFAULT 0,\n\
+INDEX `products_sku` (`sku`),\n\
+CONSTRAINT `products_price_check` CHECK (price >= 0),\n\
+CONSTRAINT `products_sku_or_meta` CHECK (sku is not null or meta is not null)\n"
+        ));
+
+        // as introspected from MySQL
+        let mut live = table.clone();
+        live.set_is_new(false);
+        live.columns[2].check = None;
+        live.checks = vec![
+            BaseCheck::new("products_price_check", "(`price` >= 0)"),
+            BaseCheck::new("products_old", "(`price` < 1000)"),
+        ];
+        table.columns[1].set_stored_as("meta->>'$.sku'");
+
+        assert_eq!(
+            offline_manager().diff_statements(&SchemaDiff::between(&table, &live)),
+            vec![
+                "ALTER TABLE `products` DROP CHECK `products_old`, \
+MODIFY COLUMN `sku` varchar(32) GENERATED ALWAYS AS (meta->>'$.sku') STORED NULL, \
+ADD CONSTRAINT `products_sku_or_meta` CHECK (sku is not null or meta is not null);"
+            ]
+        );
+    }
+
+    #[test]
+    fn pivot_tables_have_a_composite_primary_key() {
+        let mut table = BaseTable::new("_core_role_user");
+        PivotTable::new("_core_role_user", "_app_core_role", "_core_users", 0).build(&mut table);
+
+        let statement = offline_manager().create_table_statement(&table);
+        assert!(statement.contains("PRIMARY KEY (`owner_id`, `related_id`)"));
+
+        let mut live = table.clone();
+        live.primary_key(&["owner_id"]);
+        assert_eq!(
+            offline_manager().diff_statements(&SchemaDiff::between(&table, &live)),
+            vec!["ALTER TABLE `_core_role_user` DROP PRIMARY KEY, ADD PRIMARY KEY (`owner_id`, `related_id`);"]
+        );
+    }
+
+    #[test]
+    fn alter_table_adds_modifies_renames_and_drops() {
+        let mut live = BaseTable::new("posts");
+        live.set_is_new(false);
+        live.string("title");
+        live.integer("views");
+        live.string("slug").set_is_unique(true);
+        live.unique("slug", &["slug"]);
+        live.ulid("author").relationship =
+            Some(ForeignKey::new("users", "id", true).with_name("posts_author_foreign"));
+        live.ulid("legacy").relationship =
+            Some(ForeignKey::new("users", "id", false).with_name("posts_legacy_foreign"));
+
+        // unique columns carry their own index, as in fetch_table_for_update
+        let mut table = live.clone();
+        table.indexes.clear();
+        table.string("title").rename("headline");
+        table.integer("views").set_after("headline");
+        table.boolean("published");
+        table
+            .ulid("editor")
+            .set_is_nullable(true)
+            .references_without_cascade_delete("users", "id");
+        table.string("slug");
+        table.ulid("author");
+        table.drop_column("legacy");
+        table.rename("articles");
+
+        let statements = offline_manager().diff_statements(&SchemaDiff::between(&table, &live));
+
+        assert_eq!(
+            statements,
+            vec![
+                "ALTER TABLE `posts` DROP FOREIGN KEY `posts_author_foreign`, DROP FOREIGN KEY `posts_legacy_foreign`;".to_owned(),
+                "ALTER TABLE `posts` DROP INDEX `slug`, \
+CHANGE COLUMN `title` `headline` varchar(255) NOT NULL, \
+MODIFY COLUMN `views` bigint(20) NOT NULL AFTER `headline`, \
+ADD COLUMN `published` tinyint(1) NOT NULL, \
+ADD COLUMN `editor` char(26) NULL, \
+DROP COLUMN `legacy`, \
+ADD CONSTRAINT `posts_editor_foreign` FOREIGN KEY (`editor`) REFERENCES `users` (`id`), \
+RENAME TO `articles`;"
+                    .to_owned()
+            ]
+        );
+    }
+}
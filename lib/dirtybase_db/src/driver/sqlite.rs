@@ -0,0 +1,2 @@
+pub mod commit_table;
+pub mod sqlite_schema_manager;
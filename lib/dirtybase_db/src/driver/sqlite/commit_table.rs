@@ -0,0 +1,506 @@
+use crate::{
+    base::{
+        check::BaseCheck,
+        column::{
+            BaseColumn, ColumnDefault, ColumnType, ForeignKey, GeneratedColumn, GeneratedStorage,
+        },
+        foreign_key::{BaseForeignKey, ForeignKeyAction},
+        index::{BaseIndex, IndexColumn, IndexKind, IndexOrder},
+        table::BaseTable,
+    },
+    driver::mysql::commit_table::column_type_from,
+};
+use sqlx::{sqlite::SqliteRow, Row, SqliteConnection};
+
+const COLUMNS_QUERY: &str = "SELECT name, type, \"notnull\" AS not_null, dflt_value, pk, hidden
+FROM pragma_table_xinfo(?)
+ORDER BY cid";
+
+const INDEXES_QUERY: &str = "SELECT name, \"unique\" AS is_unique, origin
+FROM pragma_index_list(?)
+ORDER BY name";
+
+const INDEX_COLUMNS_QUERY: &str = "SELECT name, \"desc\" AS is_desc
+FROM pragma_index_xinfo(?)
+WHERE key = 1
+ORDER BY seqno";
+
+const FOREIGN_KEYS_QUERY: &str = "SELECT id, \"table\" AS referenced_table,
+\"from\" AS column_name, \"to\" AS referenced_column, on_update, on_delete
+FROM pragma_foreign_key_list(?)
+ORDER BY id, seq";
+
+const DEFINITION_QUERY: &str = "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?";
+
+const TRIGGERS_QUERY: &str =
+    "SELECT name FROM sqlite_master WHERE type = 'trigger' AND tbl_name = ?";
+
+const TABLES_QUERY: &str = "SELECT name FROM sqlite_master
+WHERE type = 'table' AND name NOT LIKE 'sqlite_%'
+ORDER BY name";
+
+/// Reads the live definition of a table from the pragmas and the
+/// `CREATE TABLE` statement SQLite keeps in `sqlite_master`
+pub(crate) struct SqliteTableManager<'a> {
+    connection: &'a mut SqliteConnection,
+}
+
+impl<'a> SqliteTableManager<'a> {
+    pub fn new(connection: &'a mut SqliteConnection) -> Self {
+        Self { connection }
+    }
+
+    /// The name of every table in the database
+    pub async fn table_names(&mut self) -> anyhow::Result<Vec<String>> {
+        Ok(sqlx::query(TABLES_QUERY)
+            .fetch_all(&mut *self.connection)
+            .await?
+            .iter()
+            .map(|row| text(row, "name"))
+            .collect())
+    }
+
+    /// The table as it exists in the database, `None` when there is no such table
+    pub async fn fetch_table(&mut self, name: &str) -> anyhow::Result<Option<BaseTable>> {
+        let definition = match self.fetch_rows(DEFINITION_QUERY, name).await?.first() {
+            Some(row) => text(row, "sql"),
+            None => return Ok(None),
+        };
+        let triggers: Vec<String> = self
+            .fetch_rows(TRIGGERS_QUERY, name)
+            .await?
+            .iter()
+            .map(|row| text(row, "name"))
+            .collect();
+
+        let mut table = BaseTable::new(name);
+        table.set_is_new(false);
+        table.comment = table_comment(&definition);
+
+        let mut primary_key = Vec::new();
+        for row in self.fetch_rows(COLUMNS_QUERY, name).await? {
+            let column_name = text(&row, "name");
+            let line = column_line(&definition, &column_name).unwrap_or_default();
+            let on_update = triggers.contains(&updated_at_trigger(name, &column_name));
+
+            let mut column = column_from_row(
+                &column_name,
+                &text(&row, "type"),
+                number(&row, "not_null") == 0,
+                row.try_get::<Option<String>, _>("dflt_value")?.as_deref(),
+                line,
+                on_update,
+            )?;
+            column.generated = match number(&row, "hidden") {
+                2 => generated_from(line, GeneratedStorage::Virtual),
+                3 => generated_from(line, GeneratedStorage::Stored),
+                _ => None,
+            };
+
+            let position = number(&row, "pk");
+            if position > 0 {
+                primary_key.push((position, column_name));
+            }
+            table.columns.push(column);
+        }
+
+        primary_key.sort();
+        if !primary_key.is_empty() {
+            table.primary_key = Some(primary_key.into_iter().map(|(_, name)| name).collect());
+        }
+
+        for (name, expression) in named_constraints(&definition, "CHECK") {
+            table.checks.push(BaseCheck::new(&name, &expression));
+        }
+
+        for row in self.fetch_rows(INDEXES_QUERY, name).await? {
+            let origin = text(&row, "origin");
+            if origin == "pk" {
+                continue;
+            }
+
+            let mut columns = Vec::new();
+            for column in self
+                .fetch_rows(INDEX_COLUMNS_QUERY, &text(&row, "name"))
+                .await?
+            {
+                columns.push(IndexColumn {
+                    name: text(&column, "name"),
+                    length: None,
+                    order: if number(&column, "is_desc") == 1 {
+                        IndexOrder::Desc
+                    } else {
+                        IndexOrder::Asc
+                    },
+                });
+            }
+
+            // UNIQUE columns get an automatic index, named after the column
+            // as MySQL does
+            let index_name = match (origin.as_str(), columns.as_slice()) {
+                ("u", [column]) => column.name.clone(),
+                _ => text(&row, "name"),
+            };
+            let kind = if number(&row, "is_unique") == 1 {
+                IndexKind::Unique
+            } else {
+                IndexKind::Index
+            };
+            let mut index = BaseIndex::new(&index_name, kind, &[]);
+            index.columns = columns;
+            table.indexes.push(index);
+        }
+
+        for column in table.columns.iter_mut() {
+            column.is_unique = table.indexes.iter().any(|i| i.is_unique_on(&column.name));
+        }
+
+        // SQLite does not report constraint names, they are read from the definition
+        let names = named_constraints(&definition, "FOREIGN KEY");
+        let mut foreign_keys: Vec<(i64, Vec<SqliteRow>)> = Vec::new();
+        for row in self.fetch_rows(FOREIGN_KEYS_QUERY, name).await? {
+            let id = number(&row, "id");
+            match foreign_keys.iter_mut().find(|(key, _)| *key == id) {
+                Some((_, rows)) => rows.push(row),
+                None => foreign_keys.push((id, vec![row])),
+            }
+        }
+
+        // single column constraints map onto the column's relationship
+        for (_, rows) in foreign_keys {
+            let row = &rows[0];
+            let on_delete = foreign_key_action_from(&text(row, "on_delete"));
+            let on_update = foreign_key_action_from(&text(row, "on_update"));
+            let columns: Vec<String> = rows.iter().map(|r| text(r, "column_name")).collect();
+            let constraint = names
+                .iter()
+                .find(|(_, body)| quoted_list(body) == columns)
+                .map(|(name, _)| name.clone());
+
+            match table.columns.iter_mut().find(|c| c.name == columns[0]) {
+                Some(column) if rows.len() == 1 => {
+                    let mut key = ForeignKey::new(
+                        &text(row, "referenced_table"),
+                        &text(row, "referenced_column"),
+                        false,
+                    )
+                    .with_on_delete(on_delete)
+                    .with_on_update(on_update);
+                    if let Some(constraint) = &constraint {
+                        key = key.with_name(constraint);
+                    }
+                    column.relationship = Some(key);
+                }
+                _ => table.foreign_keys.push(BaseForeignKey {
+                    name: constraint,
+                    columns,
+                    table: text(row, "referenced_table"),
+                    references: rows.iter().map(|r| text(r, "referenced_column")).collect(),
+                    on_delete,
+                    on_update,
+                }),
+            }
+        }
+        table.detect_id_set();
+
+        Ok(Some(table))
+    }
+
+    async fn fetch_rows(&mut self, query: &str, table: &str) -> anyhow::Result<Vec<SqliteRow>> {
+        Ok(sqlx::query(query)
+            .bind(table)
+            .fetch_all(&mut *self.connection)
+            .await?)
+    }
+}
+
+/// The trigger that sets an updated at column when its row changes
+pub(crate) fn updated_at_trigger(table: &str, column: &str) -> String {
+    format!("{}_{}_on_update", table, column)
+}
+
+fn text(row: &SqliteRow, column: &str) -> String {
+    row.try_get::<Option<String>, _>(column)
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+fn number(row: &SqliteRow, column: &str) -> i64 {
+    row.try_get::<Option<i64>, _>(column)
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
+pub(crate) fn column_from_row(
+    name: &str,
+    declared_type: &str,
+    is_nullable: bool,
+    default: Option<&str>,
+    line: &str,
+    on_update: bool,
+) -> anyhow::Result<BaseColumn> {
+    // the declared types are MySQL's, they map back the same way
+    let lower = declared_type.to_lowercase();
+    let (is_unsigned, declared_type) = match lower.strip_prefix("unsigned ") {
+        Some(declared_type) => (true, declared_type),
+        None => (false, lower.as_str()),
+    };
+    let extra = if line.contains(" AUTOINCREMENT") {
+        "auto_increment"
+    } else {
+        ""
+    };
+
+    let mapped = column_type_from(declared_type, extra)
+        .map_err(|e| anyhow::anyhow!("column `{}`: {}", name, e))?;
+    let mut column = BaseColumn::new(name, mapped);
+    if let Some(values) = enum_values(line, name) {
+        column.column_type = ColumnType::Enum(values);
+    }
+    column.is_unsigned = is_unsigned && column.column_type != ColumnType::AutoIncrementId;
+    column.is_nullable = Some(is_nullable);
+    column.default = column_default_from(default, on_update);
+    column.comment = trailing_comment(line);
+    Ok(column)
+}
+
+/// Maps a column's `dflt_value`, the default expression as it was written.
+/// Updated at columns have the same default as created at ones and a trigger
+pub(crate) fn column_default_from(default: Option<&str>, on_update: bool) -> Option<ColumnDefault> {
+    let default = default?.trim();
+    let lower = default.to_lowercase();
+    if lower == "null" {
+        return None;
+    }
+    if lower.contains("randomblob(") {
+        return Some(ColumnDefault::Uuid);
+    }
+    if lower == "current_timestamp" {
+        return Some(if on_update {
+            ColumnDefault::UpdatedAt
+        } else {
+            ColumnDefault::CreatedAt
+        });
+    }
+
+    let value = match default
+        .strip_prefix('\'')
+        .and_then(|d| d.strip_suffix('\''))
+    {
+        Some(value) => value.replace("''", "'"),
+        None => default.to_owned(),
+    };
+    Some(match value.as_str() {
+        "" => ColumnDefault::EmptyString,
+        "0" => ColumnDefault::Zero,
+        "{}" => ColumnDefault::EmptyObject,
+        "[]" => ColumnDefault::EmptyArray,
+        _ => ColumnDefault::Custom(value),
+    })
+}
+
+fn foreign_key_action_from(rule: &str) -> ForeignKeyAction {
+    match rule.to_uppercase().as_str() {
+        "CASCADE" => ForeignKeyAction::Cascade,
+        "SET NULL" => ForeignKeyAction::SetNull,
+        "RESTRICT" => ForeignKeyAction::Restrict,
+        _ => ForeignKeyAction::NoAction,
+    }
+}
+
+// The definitions written by the driver have one entry per line
+fn column_line<'a>(definition: &'a str, column: &str) -> Option<&'a str> {
+    let prefix = format!("\"{}\" ", column.replace('"', "\"\""));
+    definition
+        .lines()
+        .map(|line| line.trim())
+        .find(|line| line.starts_with(&prefix))
+}
+
+// `CREATE TABLE "logs" ( /* comment */`
+fn table_comment(definition: &str) -> Option<String> {
+    definition.lines().next().and_then(trailing_comment)
+}
+
+fn trailing_comment(line: &str) -> Option<String> {
+    let line = line.trim_end_matches(',').trim_end();
+    let comment = line.strip_suffix(" */")?;
+    let start = comment.rfind("/* ")?;
+    Some(comment[start + 3..].to_owned())
+}
+
+fn generated_from(line: &str, storage: GeneratedStorage) -> Option<GeneratedColumn> {
+    let (_, rest) = line.split_once(" GENERATED ALWAYS AS (")?;
+    enclosed(rest).map(|expression| GeneratedColumn {
+        expression: expression.to_owned(),
+        storage,
+    })
+}
+
+// Enumerations are text columns checked against their values
+fn enum_values(line: &str, column: &str) -> Option<Vec<String>> {
+    let check = format!(" CHECK (\"{}\" IN (", column.replace('"', "\"\""));
+    let (_, rest) = line.split_once(&check)?;
+    enclosed(rest).map(quoted_list)
+}
+
+/// `CONSTRAINT "<name>" <kind> (<body>)` entries of a definition, such as
+/// the checks and foreign keys
+fn named_constraints(definition: &str, kind: &str) -> Vec<(String, String)> {
+    definition
+        .lines()
+        .filter_map(|line| {
+            let rest = line.trim().strip_prefix("CONSTRAINT \"")?;
+            let end = closing_quote(rest, '"')?;
+            let name = rest[..end].replace("\"\"", "\"");
+            let body = rest[end + 1..]
+                .trim_start()
+                .strip_prefix(kind)?
+                .trim_start()
+                .strip_prefix('(')?;
+            Some((name, enclosed(body)?.to_owned()))
+        })
+        .collect()
+}
+
+// The text up to the parenthesis that closes the one before it.
+// Quoted parentheses are skipped
+fn enclosed(text: &str) -> Option<&str> {
+    let mut depth = 0;
+    let mut quote = None;
+    for (position, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') if depth == 0 => return Some(&text[..position]),
+            (None, ')') => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+// Doubled quotes are escaped ones
+fn closing_quote(text: &str, quote: char) -> Option<usize> {
+    let mut chars = text.char_indices().peekable();
+    while let Some((position, c)) = chars.next() {
+        if c == quote {
+            if chars.peek().map(|(_, c)| *c) == Some(quote) {
+                chars.next();
+            } else {
+                return Some(position);
+            }
+        }
+    }
+    None
+}
+
+// The values of `"a", "b"` or `'a','b'`
+fn quoted_list(list: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut rest = list.trim();
+    while let Some(quote) = rest.chars().next().filter(|c| *c == '\'' || *c == '"') {
+        let inner = &rest[1..];
+        let end = match closing_quote(inner, quote) {
+            Some(end) => end,
+            None => break,
+        };
+        let doubled = format!("{}{}", quote, quote);
+        values.push(inner[..end].replace(&doubled, &quote.to_string()));
+        rest = inner[end + 1..]
+            .trim_start()
+            .trim_start_matches(',')
+            .trim_start();
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFINITION: &str = "CREATE TABLE \"posts\" ( /* Blog posts */
+\"id\" integer PRIMARY KEY AUTOINCREMENT NOT NULL,
+\"status\" text NOT NULL DEFAULT 'draft' CHECK (\"status\" IN ('draft','it''s (new)')) /* Status */,
+\"sku\" varchar(32) GENERATED ALWAYS AS (json_extract(meta, '$.sku')) VIRTUAL NULL,
+CONSTRAINT \"posts_price_check\" CHECK (price >= (0)),
+CONSTRAINT \"posts_order\" FOREIGN KEY (\"order_id\", \"order_version\") REFERENCES \"orders\" (\"id\", \"version\")
+)";
+
+    #[test]
+    fn definitions_are_read_back() {
+        assert_eq!(table_comment(DEFINITION), Some("Blog posts".to_owned()));
+
+        let status = column_from_row(
+            "status",
+            "text",
+            false,
+            Some("'draft'"),
+            column_line(DEFINITION, "status").unwrap(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            status.column_type,
+            ColumnType::Enum(vec!["draft".to_owned(), "it's (new)".to_owned()])
+        );
+        assert_eq!(status.comment, Some("Status".to_owned()));
+        assert_eq!(
+            status.default,
+            Some(ColumnDefault::Custom("draft".to_owned()))
+        );
+
+        let id = column_line(DEFINITION, "id").unwrap();
+        assert_eq!(
+            column_from_row("id", "integer", false, None, id, false)
+                .unwrap()
+                .column_type,
+            ColumnType::AutoIncrementId
+        );
+        assert_eq!(
+            generated_from(
+                column_line(DEFINITION, "sku").unwrap(),
+                GeneratedStorage::Virtual
+            )
+            .unwrap()
+            .expression,
+            "json_extract(meta, '$.sku')"
+        );
+
+        assert_eq!(
+            named_constraints(DEFINITION, "CHECK"),
+            vec![("posts_price_check".to_owned(), "price >= (0)".to_owned())]
+        );
+        let keys = named_constraints(DEFINITION, "FOREIGN KEY");
+        assert_eq!(keys[0].0, "posts_order");
+        assert_eq!(quoted_list(&keys[0].1), vec!["order_id", "order_version"]);
+    }
+
+    #[test]
+    fn column_types_and_defaults_are_mapped() {
+        let column =
+            column_from_row("views", "unsigned bigint", true, Some("0"), "", false).unwrap();
+        assert_eq!(column.column_type, ColumnType::Integer);
+        assert!(column.is_unsigned);
+        assert_eq!(column.is_nullable, Some(true));
+        assert_eq!(column.default, Some(ColumnDefault::Zero));
+
+        assert_eq!(column_default_from(Some("NULL"), false), None);
+        assert_eq!(
+            column_default_from(Some("CURRENT_TIMESTAMP"), true),
+            Some(ColumnDefault::UpdatedAt)
+        );
+        assert_eq!(
+            column_default_from(Some("'[]'"), false),
+            Some(ColumnDefault::EmptyArray)
+        );
+        assert_eq!(
+            column_default_from(Some("(lower(hex(randomblob(4))))"), false),
+            Some(ColumnDefault::Uuid)
+        );
+    }
+}
@@ -0,0 +1,1912 @@
+use super::commit_table::{updated_at_trigger, SqliteTableManager};
+use crate::base::{
+    check::BaseCheck,
+    column::{BaseColumn, ColumnDefault, ColumnType, GeneratedStorage},
+    foreign_key::{BaseForeignKey, ForeignKeyAction},
+    helper::binary_to_json,
+    index::{BaseIndex, IndexKind, IndexOrder},
+    pool_set::PoolSet,
+    query::QueryBuilder,
+    query_cache::QueryCache,
+    query_conditions::Condition,
+    query_operators::Operator,
+    query_values::{ColumnAndValue, Value},
+    schema::SchemaManagerTrait,
+    schema_diff::{SchemaDiff, SchemaOperation},
+    table::BaseTable,
+    where_join_operators::{WhereClause, WhereJoinOperator},
+};
+use async_trait::async_trait;
+use futures::lock::{Mutex, MutexGuard};
+use sqlx::{
+    any::AnyKind,
+    pool::PoolConnection,
+    sqlite::{SqlitePoolOptions, SqliteRow},
+    Column, Connection, Pool, Row, Sqlite, SqliteConnection, Transaction, TypeInfo, ValueRef,
+};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+// a version 4 UUID, SQLite has no function for them
+const UUID_DEFAULT: &str =
+    "(lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4' || \
+substr(lower(hex(randomblob(2))), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) || \
+substr(lower(hex(randomblob(2))), 2) || '-' || lower(hex(randomblob(6))))";
+
+// the name a table is rebuilt under before it replaces the live one
+const REBUILD_PREFIX: &str = "_dty_new_";
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// SQLite has no named locks, so the locks are shared by the managers of the
+// same pool set within this process only. They do not keep a second process
+// opening the same database file from running setup or migrations meanwhile
+static LOCKS: std::sync::Mutex<Vec<(usize, String)>> = std::sync::Mutex::new(Vec::new());
+
+struct ActiveQuery {
+    statement: String,
+    params: Vec<String>,
+    tables: Vec<String>,
+    cache_ttl: Option<Duration>,
+    on_primary: bool,
+    for_update: bool,
+}
+
+pub struct SqliteSchemaManager {
+    db_pools: Arc<PoolSet<Sqlite>>,
+    db_pool: Pool<Sqlite>,
+    active_query: Option<ActiveQuery>,
+    query_cache: Option<Arc<QueryCache>>,
+    transaction: Mutex<Option<Transaction<'static, Sqlite>>>,
+    // the tables written in the transaction, invalidated when it ends
+    written_tables: Mutex<Vec<String>>,
+    // the names of the locks this instance holds
+    locks: Mutex<Vec<String>>,
+    read_your_writes: bool,
+    has_written: AtomicBool,
+}
+
+// The transaction in progress, otherwise a connection from the pool.
+// SQLite has a single writer, statements never go around the transaction
+enum SqliteConnectionRef<'a> {
+    Transaction(MutexGuard<'a, Option<Transaction<'static, Sqlite>>>),
+    Pool(PoolConnection<Sqlite>),
+}
+
+impl Deref for SqliteConnectionRef<'_> {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        match self {
+            Self::Transaction(transaction) => transaction.as_deref().unwrap(),
+            Self::Pool(connection) => connection,
+        }
+    }
+}
+
+impl DerefMut for SqliteConnectionRef<'_> {
+    fn deref_mut(&mut self) -> &mut SqliteConnection {
+        match self {
+            Self::Transaction(transaction) => transaction.as_deref_mut().unwrap(),
+            Self::Pool(connection) => connection,
+        }
+    }
+}
+
+impl SqliteSchemaManager {
+    pub fn new(db_pools: Arc<PoolSet<Sqlite>>) -> Self {
+        Self {
+            db_pool: db_pools.primary().clone(),
+            db_pools,
+            active_query: None,
+            query_cache: None,
+            transaction: Mutex::new(None),
+            written_tables: Mutex::new(Vec::new()),
+            locks: Mutex::new(Vec::new()),
+            read_your_writes: false,
+            has_written: AtomicBool::new(false),
+        }
+    }
+
+    /// A manager of a new in-memory database. Its pool keeps a single
+    /// connection open, the database goes away with it
+    pub async fn in_memory() -> anyhow::Result<Self> {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await?;
+        Ok(Self::new(Arc::new(PoolSet::new(pool))))
+    }
+}
+
+impl Drop for SqliteSchemaManager {
+    fn drop(&mut self) {
+        let key = self.locks_key();
+        let held = self.locks.get_mut();
+        if !held.is_empty() {
+            let mut locks = LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+            locks.retain(|(pools, name)| *pools != key || !held.contains(name));
+        }
+    }
+}
+
+#[async_trait]
+impl SchemaManagerTrait for SqliteSchemaManager {
+    fn kind(&self) -> AnyKind {
+        AnyKind::Sqlite
+    }
+
+    fn set_query_cache(&mut self, cache: Arc<QueryCache>) {
+        self.query_cache = Some(cache);
+    }
+
+    fn set_read_your_writes(&mut self, enabled: bool) {
+        self.read_your_writes = enabled;
+    }
+
+    async fn fetch_table(&self, name: &str) -> anyhow::Result<Option<BaseTable>> {
+        let mut connection = self.connection(&self.db_pool).await?;
+        SqliteTableManager::new(&mut connection)
+            .fetch_table(name)
+            .await
+    }
+
+    async fn table_names(&self) -> anyhow::Result<Vec<String>> {
+        let mut connection = self.connection(&self.db_pool).await?;
+        SqliteTableManager::new(&mut connection).table_names().await
+    }
+
+    async fn fetch_table_for_update(&self, name: &str) -> anyhow::Result<BaseTable> {
+        Ok(match self.fetch_table(name).await? {
+            Some(mut table) => {
+                // unique columns already carry these
+                table.indexes.retain(|index| !index.is_single_unique());
+                table
+            }
+            None => BaseTable::new(name),
+        })
+    }
+
+    async fn has_table(&self, name: &str) -> bool {
+        let query = "SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?";
+
+        let mut connection = match self.connection(&self.db_pool).await {
+            Ok(connection) => connection,
+            Err(_) => return false,
+        };
+        let result = sqlx::query(query)
+            .bind(name)
+            .fetch_optional(&mut *connection)
+            .await;
+
+        matches!(result, Ok(Some(_)))
+    }
+
+    async fn commit(&self, table: BaseTable) -> anyhow::Result<()> {
+        self.invalidate_cache(&table.name);
+        self.do_commit(table).await
+    }
+
+    fn table_statements(&self, table: &BaseTable, live: Option<&BaseTable>) -> Vec<String> {
+        match live {
+            Some(live) => self.rebuild_statements(live, &SchemaDiff::between(table, live)),
+            None => self.create_statements(table),
+        }
+    }
+
+    async fn apply_diff(&self, diff: &SchemaDiff) -> anyhow::Result<()> {
+        self.invalidate_cache(&diff.table);
+        let live = match self.fetch_table(&diff.table).await? {
+            Some(live) => live,
+            None => anyhow::bail!("the table `{}` does not exist", diff.table),
+        };
+        self.rebuild(&self.rebuild_statements(&live, diff)).await
+    }
+
+    async fn drop_table(&self, name: &str) -> anyhow::Result<()> {
+        self.invalidate_cache(name);
+        self.execute(&format!("DROP TABLE IF EXISTS {}", quoted_name(name)), &[])
+            .await?;
+        Ok(())
+    }
+
+    fn query(&mut self, query: QueryBuilder) -> &dyn SchemaManagerTrait
+    where
+        Self: Sized,
+    {
+        self.active_query = Some(self.active_query_from(&query));
+        self
+    }
+
+    async fn try_fetch_all_as_json(&self) -> anyhow::Result<Vec<serde_json::Value>> {
+        match &self.active_query {
+            Some(active_query) => self.fetch_active_query(active_query).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn fetch(&self, query: QueryBuilder) -> anyhow::Result<Vec<serde_json::Value>> {
+        self.fetch_active_query(&self.active_query_from(&query))
+            .await
+    }
+
+    async fn insert(&self, table_name: &str, record: ColumnAndValue) -> anyhow::Result<()> {
+        let mut columns = Vec::new();
+        let mut placeholders = Vec::new();
+        let mut params = Vec::new();
+
+        for (column, value) in record {
+            columns.push(quoted_name(&column));
+            placeholders.push(self.value_placeholder(&value));
+            value.to_param(&mut params);
+        }
+
+        let statement = format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            quoted_name(table_name),
+            columns.join(","),
+            placeholders.join(",")
+        );
+
+        self.execute(&statement, &params).await?;
+        self.invalidate_written(&[table_name.to_owned()]).await;
+        Ok(())
+    }
+
+    async fn update(&self, query: QueryBuilder) -> anyhow::Result<u64> {
+        let mut params = Vec::new();
+        let mut sets = Vec::new();
+
+        if let Some(columns) = query.set_columns() {
+            for (column, value) in columns {
+                sets.push(format!(
+                    "{} = {}",
+                    quoted_name(column),
+                    self.value_placeholder(value)
+                ));
+                value.to_param(&mut params);
+            }
+        }
+
+        if sets.is_empty() {
+            return Ok(0);
+        }
+
+        let statement = format!(
+            "UPDATE {} SET {} {}",
+            query.tables().join(","),
+            sets.join(","),
+            self.build_where_clauses(&query, &mut params)
+        );
+
+        let affected = self.execute(&statement, &params).await?;
+        self.invalidate_written(query.tables()).await;
+        Ok(affected)
+    }
+
+    async fn delete(&self, query: QueryBuilder) -> anyhow::Result<u64> {
+        let mut params = Vec::new();
+        let statement = format!(
+            "DELETE FROM {} {}",
+            query.tables().join(","),
+            self.build_where_clauses(&query, &mut params)
+        );
+
+        let affected = self.execute(&statement, &params).await?;
+        self.invalidate_written(query.tables()).await;
+        Ok(affected)
+    }
+
+    async fn begin_transaction(&self) -> anyhow::Result<()> {
+        let mut transaction = self.transaction.lock().await;
+        if transaction.is_some() {
+            anyhow::bail!("a transaction is already in progress");
+        }
+
+        *transaction = Some(self.db_pool.begin().await?);
+        Ok(())
+    }
+
+    async fn commit_transaction(&self) -> anyhow::Result<()> {
+        let result = match self.transaction.lock().await.take() {
+            Some(transaction) => transaction.commit().await,
+            None => anyhow::bail!("there is no transaction to commit"),
+        };
+        self.invalidate_transaction_writes().await;
+        Ok(result?)
+    }
+
+    async fn rollback_transaction(&self) -> anyhow::Result<()> {
+        let result = match self.transaction.lock().await.take() {
+            Some(transaction) => transaction.rollback().await,
+            None => anyhow::bail!("there is no transaction to roll back"),
+        };
+        self.invalidate_transaction_writes().await;
+        Ok(result?)
+    }
+
+    async fn acquire_lock(&self, name: &str, timeout: Duration) -> anyhow::Result<()> {
+        if self.locks.lock().await.iter().any(|n| n == name) {
+            anyhow::bail!("the lock `{}` is already held", name);
+        }
+
+        let lock = (self.locks_key(), name.to_owned());
+        let started = Instant::now();
+        loop {
+            {
+                let mut locks = LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+                if !locks.contains(&lock) {
+                    locks.push(lock);
+                    break;
+                }
+            }
+
+            if started.elapsed() >= timeout {
+                anyhow::bail!(
+                    "could not acquire the lock `{}` within {} seconds",
+                    name,
+                    timeout.as_secs()
+                )
+            }
+            tokio::time::sleep(LOCK_POLL_INTERVAL).await;
+        }
+
+        // the entry in `LOCKS` makes this manager the only holder
+        self.locks.lock().await.push(name.to_owned());
+        Ok(())
+    }
+
+    async fn release_lock(&self, name: &str) -> anyhow::Result<()> {
+        let mut held = self.locks.lock().await;
+        match held.iter().position(|n| n == name) {
+            Some(position) => held.remove(position),
+            None => anyhow::bail!("the lock `{}` is not held", name),
+        };
+
+        let lock = (self.locks_key(), name.to_owned());
+        LOCKS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|l| *l != lock);
+        Ok(())
+    }
+}
+
+impl SqliteSchemaManager {
+    /// Compiles a select query into its statement and params
+    pub fn to_sql(&self, query: &QueryBuilder) -> (String, Vec<String>) {
+        let mut params = Vec::new();
+        let statement = self.build_query(query, &mut params);
+        (statement, params)
+    }
+
+    async fn connection(&self, pool: &Pool<Sqlite>) -> anyhow::Result<SqliteConnectionRef<'_>> {
+        let transaction = self.transaction.lock().await;
+        if transaction.is_some() {
+            return Ok(SqliteConnectionRef::Transaction(transaction));
+        }
+        drop(transaction);
+
+        Ok(SqliteConnectionRef::Pool(pool.acquire().await?))
+    }
+
+    async fn execute(&self, statement: &str, params: &[String]) -> anyhow::Result<u64> {
+        let mut query = sqlx::query(statement);
+        for p in params {
+            query = query.bind::<&str>(p);
+        }
+
+        self.has_written.store(true, Ordering::Relaxed);
+        let mut connection = self.connection(&self.db_pool).await?;
+        Ok(query.execute(&mut *connection).await?.rows_affected())
+    }
+
+    // Replicas serve reads unless the query asks for the primary, or
+    // this instance has written and should read its own writes
+    fn read_pool(&self, on_primary: bool) -> &Pool<Sqlite> {
+        if on_primary || (self.read_your_writes && self.has_written.load(Ordering::Relaxed)) {
+            &self.db_pool
+        } else {
+            self.db_pools.reader()
+        }
+    }
+
+    fn active_query_from(&self, query: &QueryBuilder) -> ActiveQuery {
+        let mut params = Vec::new();
+        let statement = self.build_query(query, &mut params);
+
+        ActiveQuery {
+            statement,
+            params,
+            tables: query.all_tables(),
+            cache_ttl: query.cache_ttl(),
+            on_primary: query.is_on_primary(),
+            for_update: query.is_for_update(),
+        }
+    }
+
+    async fn fetch_active_query(
+        &self,
+        active_query: &ActiveQuery,
+    ) -> anyhow::Result<Vec<serde_json::Value>> {
+        let mut results = Vec::new();
+        let in_transaction = self.transaction.lock().await.is_some();
+        let cache = match (&self.query_cache, active_query.cache_ttl) {
+            (Some(cache), Some(ttl)) if !in_transaction => Some((
+                cache,
+                ttl,
+                QueryCache::key(&active_query.statement, &active_query.params),
+                cache.generation(),
+            )),
+            _ => None,
+        };
+
+        if let Some((cache, _, key, _)) = &cache {
+            if let Some(rows) = cache.get(key) {
+                return Ok(rows);
+            }
+        }
+
+        let mut query = sqlx::query(&active_query.statement);
+        for p in &active_query.params {
+            query = query.bind::<&str>(p);
+        }
+
+        // rows from a replica that missed a recent write would stay cached
+        let on_primary = active_query.on_primary
+            || cache
+                .as_ref()
+                .is_some_and(|(cache, ..)| cache.is_recently_written(&active_query.tables));
+
+        let mut connection = self.connection(self.read_pool(on_primary)).await?;
+        if active_query.for_update {
+            // a write that changes nothing takes the write lock, other
+            // transactions wait for this one to end before they lock
+            if let Some(table) = active_query.tables.first() {
+                sqlx::query(&format!("DELETE FROM {} WHERE 0", quoted_name(table)))
+                    .execute(&mut *connection)
+                    .await?;
+            }
+        }
+        for row in query.fetch_all(&mut *connection).await? {
+            results.push(self.row_to_json(&row));
+        }
+
+        if let Some((cache, ttl, key, generation)) = cache {
+            cache.put(
+                &key,
+                active_query.tables.clone(),
+                results.clone(),
+                ttl,
+                generation,
+            );
+        }
+
+        Ok(results)
+    }
+
+    fn invalidate_cache(&self, table: &str) {
+        if let Some(cache) = &self.query_cache {
+            cache.invalidate_table(table);
+        }
+    }
+
+    // Other connections only see a transaction's writes once it commits.
+    // Invalidating earlier would let them cache the old rows again
+    async fn invalidate_written(&self, tables: &[String]) {
+        if self.transaction.lock().await.is_some() {
+            self.written_tables
+                .lock()
+                .await
+                .extend(tables.iter().cloned());
+        } else {
+            tables.iter().for_each(|t| self.invalidate_cache(t));
+        }
+    }
+
+    async fn invalidate_transaction_writes(&self) {
+        let tables = std::mem::take(&mut *self.written_tables.lock().await);
+        tables.iter().for_each(|t| self.invalidate_cache(t));
+    }
+
+    fn locks_key(&self) -> usize {
+        Arc::as_ptr(&self.db_pools) as usize
+    }
+
+    fn value_placeholder(&self, value: &Value) -> String {
+        match value {
+            Value::Null => "NULL".to_owned(),
+            _ => "?".to_owned(),
+        }
+    }
+
+    async fn do_commit(&self, table: BaseTable) -> anyhow::Result<()> {
+        let live = match self.fetch_table(&table.name).await? {
+            Some(live) if !table.is_new() => live,
+            _ => {
+                for statement in self.create_statements(&table) {
+                    self.execute(&statement, &[]).await?;
+                }
+                return Ok(());
+            }
+        };
+
+        let diff = SchemaDiff::between(&table, &live);
+        if diff.is_empty() {
+            return Ok(());
+        }
+        self.rebuild(&self.rebuild_statements(&live, &diff)).await
+    }
+
+    // Foreign keys can only be switched off outside of a transaction. As
+    // with MySQL, the schema change commits the transaction in progress,
+    // which goes on after it
+    async fn rebuild(&self, statements: &[String]) -> anyhow::Result<()> {
+        let mut transaction = self.transaction.lock().await;
+        let in_transaction = match transaction.take() {
+            Some(transaction) => {
+                transaction.commit().await?;
+                true
+            }
+            None => false,
+        };
+
+        self.has_written.store(true, Ordering::Relaxed);
+        let result = async {
+            let mut connection = self.db_pool.acquire().await?;
+            sqlx::query("PRAGMA foreign_keys = OFF")
+                .execute(&mut *connection)
+                .await?;
+            let rebuilt = rebuild_on(&mut connection, statements).await;
+            sqlx::query("PRAGMA foreign_keys = ON")
+                .execute(&mut *connection)
+                .await?;
+            rebuilt
+        }
+        .await;
+
+        if in_transaction {
+            *transaction = Some(self.db_pool.begin().await?);
+        }
+        result
+    }
+
+    fn create_statements(&self, table: &BaseTable) -> Vec<String> {
+        let mut statements = vec![self.create_table_statement(table)];
+        statements.extend(self.secondary_statements(table));
+        statements
+    }
+
+    fn create_table_statement(&self, table: &BaseTable) -> String {
+        self.table_definition(&table.name, table)
+    }
+
+    // Each entry goes on its own line, `fetch_table` reads back what the
+    // pragmas do not report from the definition SQLite keeps
+    fn table_definition(&self, name: &str, table: &BaseTable) -> String {
+        let primary_key = table.primary_key_columns();
+        let auto_increment = match primary_key.as_slice() {
+            [key]
+                if table
+                    .find_column(key)
+                    .is_some_and(|c| c.column_type == ColumnType::AutoIncrementId) =>
+            {
+                Some(key.as_str())
+            }
+            _ => None,
+        };
+
+        let mut entries: Vec<String> = table
+            .columns()
+            .iter()
+            .filter(|column| !column.has_pivot_table())
+            .map(|column| {
+                let is_unique =
+                    column.is_unique || table.indexes.iter().any(|i| i.is_unique_on(&column.name));
+                self.column_definition(column, auto_increment == Some(&column.name), is_unique)
+            })
+            .collect();
+
+        if !primary_key.is_empty() && auto_increment.is_none() {
+            entries.push(format!("PRIMARY KEY ({})", quoted_names(&primary_key)));
+        }
+
+        entries.extend(
+            table
+                .check_constraints()
+                .iter()
+                .map(|check| self.check_definition(check)),
+        );
+
+        entries.extend(
+            table
+                .foreign_key_constraints()
+                .iter()
+                .map(|key| self.foreign_key_definition(&table.name, key)),
+        );
+
+        let comment = match &table.comment {
+            Some(comment) => format!(" {}", comment_sql(comment)),
+            None => String::new(),
+        };
+
+        format!(
+            "CREATE TABLE {} ({}\n{}\n);",
+            quoted_name(name),
+            comment,
+            entries.join(",\n")
+        )
+    }
+
+    // The indexes and the triggers, which go with the table when it is dropped.
+    // Single column unique indexes are the columns' UNIQUE
+    fn secondary_statements(&self, table: &BaseTable) -> Vec<String> {
+        let mut statements: Vec<String> = table
+            .indexes
+            .iter()
+            .filter(|index| !index.is_single_unique())
+            .map(|index| self.index_statement(&table.name, index))
+            .collect();
+
+        statements.extend(
+            table
+                .columns()
+                .iter()
+                .filter(|c| c.default == Some(ColumnDefault::UpdatedAt) && c.generated.is_none())
+                .map(|column| self.updated_at_statement(&table.name, &column.name)),
+        );
+
+        statements
+    }
+
+    // SQLite has no prefix indexes and no full text index of this kind
+    fn index_statement(&self, table: &str, index: &BaseIndex) -> String {
+        let kind = match index.kind {
+            IndexKind::Unique => "UNIQUE INDEX",
+            IndexKind::Index | IndexKind::Fulltext => "INDEX",
+        };
+        let columns: Vec<String> = index
+            .columns
+            .iter()
+            .map(|column| {
+                let mut entry = quoted_name(&column.name);
+                if column.order == IndexOrder::Desc {
+                    entry.push_str(" DESC");
+                }
+                entry
+            })
+            .collect();
+
+        format!(
+            "CREATE {} {} ON {} ({});",
+            kind,
+            quoted_name(&index.name),
+            quoted_name(table),
+            columns.join(", ")
+        )
+    }
+
+    // MySQL's `ON UPDATE CURRENT_TIMESTAMP`. Only rows whose update
+    // left the column as it was are touched
+    fn updated_at_statement(&self, table: &str, column: &str) -> String {
+        let (table_name, column_name) = (quoted_name(table), quoted_name(column));
+        format!(
+            "CREATE TRIGGER {} AFTER UPDATE ON {} FOR EACH ROW WHEN NEW.{} IS OLD.{} \
+BEGIN UPDATE {} SET {} = CURRENT_TIMESTAMP WHERE rowid = NEW.rowid; END;",
+            quoted_name(&updated_at_trigger(table, column)),
+            table_name,
+            column_name,
+            column_name,
+            table_name,
+            column_name
+        )
+    }
+
+    /// The statements that bring the live table in line with the diff.
+    /// SQLite can hardly alter a table, so it is created again under a
+    /// temporary name, the rows are copied over and it replaces the live one
+    fn rebuild_statements(&self, live: &BaseTable, diff: &SchemaDiff) -> Vec<String> {
+        let (table, copied) = altered_table(live, diff);
+        let temporary = format!("{}{}", REBUILD_PREFIX, table.name);
+
+        let mut statements = vec![self.table_definition(&temporary, &table)];
+        if !copied.is_empty() {
+            let (sources, targets): (Vec<String>, Vec<String>) = copied.into_iter().unzip();
+            statements.push(format!(
+                "INSERT INTO {} ({}) SELECT {} FROM {};",
+                quoted_name(&temporary),
+                quoted_names(&targets),
+                quoted_names(&sources),
+                quoted_name(&live.name)
+            ));
+        }
+        statements.push(format!("DROP TABLE {};", quoted_name(&live.name)));
+        statements.push(format!(
+            "ALTER TABLE {} RENAME TO {};",
+            quoted_name(&temporary),
+            quoted_name(&table.name)
+        ));
+        statements.extend(self.secondary_statements(&table));
+
+        statements
+    }
+
+    fn foreign_key_definition(&self, table: &str, key: &BaseForeignKey) -> String {
+        let mut clause = format!(
+            "CONSTRAINT {} FOREIGN KEY ({}) REFERENCES {} ({})",
+            quoted_name(&key.name_in(table)),
+            quoted_names(&key.columns),
+            quoted_name(&key.table),
+            quoted_names(&key.references)
+        );
+        if key.on_delete != ForeignKeyAction::NoAction {
+            clause.push_str(&format!(
+                " ON DELETE {}",
+                foreign_key_action_sql(key.on_delete)
+            ));
+        }
+        if key.on_update != ForeignKeyAction::NoAction {
+            clause.push_str(&format!(
+                " ON UPDATE {}",
+                foreign_key_action_sql(key.on_update)
+            ));
+        }
+        clause
+    }
+
+    fn check_definition(&self, check: &BaseCheck) -> String {
+        format!(
+            "CONSTRAINT {} CHECK ({})",
+            quoted_name(&check.name),
+            check.expression
+        )
+    }
+
+    // SQLite only knows its own collations, those of MySQL are left out
+    fn column_definition(
+        &self,
+        column: &BaseColumn,
+        auto_increment: bool,
+        is_unique: bool,
+    ) -> String {
+        let mut entry = format!("{} ", quoted_name(&column.name));
+
+        // column type
+        if column.is_unsigned
+            && column.column_type.is_numeric()
+            && column.column_type != ColumnType::AutoIncrementId
+        {
+            entry.push_str("unsigned ");
+        }
+        entry.push_str(&column_type_sql(&column.column_type));
+        if let Some(generated) = &column.generated {
+            entry.push_str(&format!(
+                " GENERATED ALWAYS AS ({}) {}",
+                generated.expression,
+                match generated.storage {
+                    GeneratedStorage::Virtual => "VIRTUAL",
+                    GeneratedStorage::Stored => "STORED",
+                }
+            ));
+        } else if auto_increment {
+            entry.push_str(" PRIMARY KEY AUTOINCREMENT");
+        }
+
+        // column is nullable
+        if let Some(nullable) = column.is_nullable {
+            if nullable {
+                entry.push_str(" NULL");
+            } else {
+                entry.push_str(" NOT NULL");
+            }
+        }
+
+        // column default. Generated columns have none
+        let default = match column
+            .default
+            .as_ref()
+            .filter(|_| column.generated.is_none())
+        {
+            Some(ColumnDefault::CreatedAt) | Some(ColumnDefault::UpdatedAt) => {
+                Some("CURRENT_TIMESTAMP".to_owned())
+            }
+            Some(ColumnDefault::Custom(d)) => Some(quoted_value(d)),
+            Some(ColumnDefault::EmptyArray) => Some("'[]'".to_owned()),
+            Some(ColumnDefault::EmptyObject) => Some("'{}'".to_owned()),
+            Some(ColumnDefault::EmptyString) => Some("''".to_owned()),
+            Some(ColumnDefault::Uuid) => Some(UUID_DEFAULT.to_owned()),
+            Some(ColumnDefault::Ulid) | None => None,
+            Some(ColumnDefault::Zero) => Some("0".to_owned()),
+        };
+        if let Some(default) = default {
+            entry.push_str(&format!(" DEFAULT {}", default));
+        }
+
+        if is_unique {
+            entry.push_str(" UNIQUE");
+        }
+
+        // enumerations are text checked against their values
+        if let ColumnType::Enum(values) = &column.column_type {
+            entry.push_str(&format!(
+                " CHECK ({} IN ({}))",
+                quoted_name(&column.name),
+                values
+                    .iter()
+                    .map(|v| quoted_value(v))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ));
+        }
+
+        if let Some(comment) = &column.comment {
+            entry.push_str(&format!(" {}", comment_sql(comment)));
+        }
+
+        entry
+    }
+
+    fn build_query(&self, query: &QueryBuilder, params: &mut Vec<String>) -> String {
+        let mut sql = "SELECT".to_owned();
+
+        // fields
+        match query.select_columns() {
+            Some(fields) => sql = format!("{} {}", sql, fields.join(",")),
+            None => {
+                for entry in query.tables().iter().enumerate() {
+                    if entry.0 == 0 {
+                        sql = format!("{} {}.*", sql, entry.1)
+                    } else {
+                        sql = format!("{}, {}.*", sql, entry.1)
+                    }
+                }
+            }
+        };
+
+        // join fields
+        if let Some(joins) = query.joins() {
+            for a_join in joins {
+                match a_join.select_columns() {
+                    Some(columns) => {
+                        sql = format!("{}, {}", sql, columns.join(","));
+                    }
+                    None => sql = format!("{}, {}.*", sql, a_join.table()),
+                }
+            }
+        }
+
+        // from
+        sql = format!("{} FROM {}", sql, query.tables().join(","));
+
+        // joins
+        if let Some(joins) = query.joins() {
+            for a_join in joins {
+                sql = format!(
+                    "{} {} join {} on {}",
+                    sql,
+                    a_join.join_type(),
+                    a_join.table(),
+                    a_join.join_clause()
+                );
+            }
+        }
+
+        // wheres
+        sql = format!("{} {}", sql, self.build_where_clauses(query, params));
+
+        sql
+    }
+
+    fn build_where_clauses(&self, query: &QueryBuilder, params: &mut Vec<String>) -> String {
+        let wheres = self.build_clauses(query.where_clauses(), params);
+
+        if wheres.is_empty() {
+            wheres
+        } else {
+            format!("WHERE {}", wheres)
+        }
+    }
+
+    fn build_clauses(&self, clauses: &[WhereJoinOperator], params: &mut Vec<String>) -> String {
+        let mut wheres = "".to_owned();
+        for where_join in clauses {
+            let condition = match where_join.clause() {
+                WhereClause::Condition(condition) => self.transform_condition(condition, params),
+                WhereClause::Group(group) => format!("({})", self.build_clauses(group, params)),
+            };
+            wheres = where_join.as_clause(&wheres, &condition);
+        }
+
+        wheres
+    }
+
+    fn transform_condition(&self, condition: &Condition, params: &mut Vec<String>) -> String {
+        let is_list =
+            *condition.operator() == Operator::In || *condition.operator() == Operator::NotIn;
+
+        let placeholder = match condition.value() {
+            Value::SubQuery(q) if is_list => self.build_query(q, params),
+            Value::SubQuery(q) => format!("({})", self.build_query(q, params)),
+            value => {
+                value.to_param(params);
+                if is_list {
+                    let length = match value {
+                        Value::I64s(v) => v.len(),
+                        Value::U64s(v) => v.len(),
+                        Value::F64s(v) => v.len(),
+                        Value::Strings(v) => v.len(),
+                        _ => 1,
+                    };
+
+                    let mut placeholder = Vec::new();
+                    placeholder.resize(length, "?");
+                    placeholder.join(",")
+                } else {
+                    "?".to_owned()
+                }
+            }
+        };
+
+        condition
+            .operator()
+            .as_clause(condition.column(), &placeholder)
+    }
+
+    // Values are mapped by their storage class. Booleans are stored as
+    // integers and told apart by their declared type
+    fn row_to_json(&self, row: &SqliteRow) -> serde_json::Value {
+        let mut this_row = serde_json::Map::new();
+
+        for col in row.columns() {
+            let name = col.name().to_owned();
+            let storage = match row.try_get_raw(col.ordinal()) {
+                Ok(value) if !value.is_null() => value.type_info().name().to_owned(),
+                _ => {
+                    this_row.insert(name, serde_json::Value::Null);
+                    continue;
+                }
+            };
+
+            match storage.as_str() {
+                "INTEGER" if col.type_info().name() == "BOOLEAN" => {
+                    let v = row.try_get::<bool, _>(col.ordinal()).unwrap_or_default();
+                    this_row.insert(name, serde_json::Value::Bool(v));
+                }
+                "INTEGER" => {
+                    let v = row.try_get::<i64, _>(col.ordinal()).unwrap_or_default();
+                    this_row.insert(name, serde_json::Value::Number(serde_json::Number::from(v)));
+                }
+                "REAL" => {
+                    let v = row.try_get::<f64, _>(col.ordinal()).unwrap_or_default();
+                    this_row.insert(
+                        name,
+                        serde_json::Number::from_f64(v)
+                            .map(serde_json::Value::Number)
+                            .unwrap_or(serde_json::Value::Null),
+                    );
+                }
+                "TEXT" => {
+                    if let Ok(v) = row.try_get::<String, _>(col.ordinal()) {
+                        this_row.insert(name, serde_json::Value::String(v));
+                    } else {
+                        this_row.insert(name, serde_json::Value::Null);
+                    }
+                }
+                "BLOB" => {
+                    let v = row.try_get::<Vec<u8>, _>(col.ordinal());
+                    this_row.insert(name, v.map(binary_to_json).unwrap_or_default());
+                }
+                _ => {
+                    log::warn!("column type not mapped: {:?}", col.type_info());
+                }
+            }
+        }
+
+        serde_json::Value::Object(this_row)
+    }
+}
+
+// Runs the rebuild in its own transaction. It is undone when
+// the copied rows break a foreign key
+async fn rebuild_on(
+    connection: &mut SqliteConnection,
+    statements: &[String],
+) -> anyhow::Result<()> {
+    let mut transaction = connection.begin().await?;
+    for statement in statements {
+        sqlx::query(statement).execute(&mut transaction).await?;
+    }
+
+    let violations = sqlx::query("PRAGMA foreign_key_check")
+        .fetch_all(&mut transaction)
+        .await?;
+    if let Some(violation) = violations.first() {
+        anyhow::bail!(
+            "the rows of `{}` break a foreign key",
+            violation.try_get::<String, _>(0).unwrap_or_default()
+        );
+    }
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// The live table with the changes of the diff, and the (live, new) names
+/// of the columns whose values are copied over when it is rebuilt
+fn altered_table(live: &BaseTable, diff: &SchemaDiff) -> (BaseTable, Vec<(String, String)>) {
+    let mut table = live.clone();
+    let mut copied: Vec<(String, String)> = live
+        .columns()
+        .iter()
+        .filter(|c| c.generated.is_none())
+        .map(|c| (c.name.clone(), c.name.clone()))
+        .collect();
+
+    for change in &diff.changes {
+        match &change.operation {
+            SchemaOperation::DropForeignKey(name) => {
+                let table_name = table.name.clone();
+                for column in table.columns.iter_mut() {
+                    let is_dropped = column
+                        .relationship
+                        .as_ref()
+                        .is_some_and(|k| k.constraint(&column.name).name_in(&table_name) == *name);
+                    if is_dropped {
+                        column.relationship = None;
+                    }
+                }
+                table
+                    .foreign_keys
+                    .retain(|k| k.name_in(&table_name) != *name);
+            }
+            SchemaOperation::DropIndex(name) => {
+                let unique_column = table
+                    .indexes
+                    .iter()
+                    .find(|i| i.name == *name && i.is_single_unique())
+                    .map(|i| i.columns[0].name.clone());
+                if let Some(column) = unique_column {
+                    set_unique(&mut table, &column, false);
+                }
+                table.indexes.retain(|i| i.name != *name);
+            }
+            SchemaOperation::DropCheck(name) => {
+                let table_name = table.name.clone();
+                table.checks.retain(|c| c.name != *name);
+                for column in table.columns.iter_mut() {
+                    if BaseCheck::generated_name(&table_name, &column.name) == *name {
+                        column.check = None;
+                    }
+                }
+            }
+            // foreign keys and checks are changes of their own
+            SchemaOperation::AddColumn(column) => {
+                let mut column = column.clone();
+                let after = renamed_after(column.after.take(), &copied);
+                column.new_name = None;
+                column.relationship = None;
+                column.check = None;
+                insert_column(&mut table, column, after);
+            }
+            // so are the column's uniqueness and foreign key
+            SchemaOperation::AlterColumn(column) => {
+                let mut altered = column.clone();
+                let after = renamed_after(altered.after.take(), &copied);
+                if let Some(new_name) = altered.new_name.take() {
+                    rename_column(&mut table, &mut copied, &column.name, &new_name);
+                    altered.name = new_name;
+                }
+
+                let position = table.columns.iter().position(|c| c.name == column.name);
+                if let Some(existing) = position.map(|p| table.columns.remove(p)) {
+                    altered.is_unique = existing.is_unique;
+                    altered.relationship = existing.relationship;
+                    altered.check = existing.check;
+                    if after.is_none() {
+                        table.columns.insert(position.unwrap(), altered.clone());
+                    }
+                }
+                if after.is_some() || position.is_none() {
+                    insert_column(&mut table, altered.clone(), after);
+                }
+
+                if altered.generated.is_some() {
+                    copied.retain(|(_, name)| *name != altered.name);
+                }
+            }
+            SchemaOperation::DropPrimaryKey => table.primary_key = Some(Vec::new()),
+            SchemaOperation::AddPrimaryKey(columns) => table.primary_key = Some(columns.clone()),
+            SchemaOperation::AddUnique(column) => set_unique(&mut table, column, true),
+            SchemaOperation::AddIndex(index) => {
+                table.indexes.retain(|i| i.name != index.name);
+                table.indexes.push(index.clone());
+            }
+            SchemaOperation::AddCheck(check) => {
+                table.checks.retain(|c| c.name != check.name);
+                table.checks.push(check.clone());
+            }
+            SchemaOperation::DropColumn(name) => {
+                table.columns.retain(|c| c.name != *name);
+                table
+                    .indexes
+                    .retain(|i| !i.columns.iter().any(|c| c.name == *name));
+                copied.retain(|(source, _)| source != name);
+            }
+            SchemaOperation::AddForeignKey(key) => table.foreign_keys.push(key.clone()),
+            // SQLite has no table options
+            SchemaOperation::SetEngine(_)
+            | SchemaOperation::SetCharset(_)
+            | SchemaOperation::SetCollation(_) => (),
+            SchemaOperation::SetComment(comment) => table.comment = Some(comment.clone()),
+            SchemaOperation::RenameTable(new_name) => table.name = new_name.clone(),
+        }
+    }
+
+    (table, copied)
+}
+
+// `after` names the column as it is in the desired table,
+// which may have been renamed by then
+fn renamed_after(after: Option<String>, copied: &[(String, String)]) -> Option<String> {
+    after.map(|after| {
+        copied
+            .iter()
+            .find(|(source, _)| *source == after)
+            .map_or(after, |(_, name)| name.clone())
+    })
+}
+
+fn insert_column(table: &mut BaseTable, column: BaseColumn, after: Option<String>) {
+    match after.and_then(|after| table.columns.iter().position(|c| c.name == after)) {
+        Some(position) => table.columns.insert(position + 1, column),
+        None => table.columns.push(column),
+    }
+}
+
+fn set_unique(table: &mut BaseTable, column: &str, unique: bool) {
+    if let Some(column) = table.columns.iter_mut().find(|c| c.name == column) {
+        column.is_unique = unique;
+    }
+}
+
+// The keys and indexes refer to the column by its new name
+fn rename_column(table: &mut BaseTable, copied: &mut [(String, String)], from: &str, to: &str) {
+    let rename = |name: &mut String| {
+        if name == from {
+            *name = to.to_owned();
+        }
+    };
+
+    if let Some(primary_key) = table.primary_key.as_mut() {
+        primary_key.iter_mut().for_each(rename);
+    }
+    for index in table.indexes.iter_mut() {
+        index.columns.iter_mut().for_each(|c| rename(&mut c.name));
+    }
+    for key in table.foreign_keys.iter_mut() {
+        key.columns.iter_mut().for_each(rename);
+    }
+    copied.iter_mut().for_each(|(_, name)| rename(name));
+}
+
+// The declared type of a column. MySQL's type names give SQLite the
+// right affinity and map back onto the same column type
+fn column_type_sql(column_type: &ColumnType) -> String {
+    match column_type.storage_type() {
+        // the only type AUTOINCREMENT takes
+        ColumnType::AutoIncrementId => "integer".to_owned(),
+        ColumnType::Binary(length) => format!("varbinary({})", length),
+        ColumnType::Blob => "blob".to_owned(),
+        ColumnType::Boolean => "boolean".to_owned(),
+        ColumnType::Char(length) => format!("char({})", length),
+        ColumnType::Date => "date".to_owned(),
+        ColumnType::Datetime => "datetime".to_owned(),
+        ColumnType::Decimal { precision, scale } => format!("decimal({},{})", precision, scale),
+        ColumnType::Enum(_) => "text".to_owned(),
+        ColumnType::Float => "float".to_owned(),
+        ColumnType::Integer => "bigint".to_owned(),
+        ColumnType::Json => "json".to_owned(),
+        ColumnType::MediumInteger => "mediumint".to_owned(),
+        ColumnType::Number => "double".to_owned(),
+        ColumnType::SmallInteger => "smallint".to_owned(),
+        ColumnType::String(length) => format!("varchar({})", length),
+        ColumnType::Text => "text".to_owned(),
+        ColumnType::Time => "time".to_owned(),
+        ColumnType::Timestamp => "timestamp".to_owned(),
+        ColumnType::TinyInteger => "tinyint".to_owned(),
+        ColumnType::Relation { .. } => {
+            unreachable!("multiple relations are stored in a pivot table")
+        }
+        // storage_type maps these onto the types above
+        ColumnType::File(_) | ColumnType::Select(_) | ColumnType::Uuid => {
+            unreachable!("not a storage type")
+        }
+    }
+}
+
+fn foreign_key_action_sql(action: ForeignKeyAction) -> &'static str {
+    match action {
+        ForeignKeyAction::Cascade => "CASCADE",
+        ForeignKeyAction::SetNull => "SET NULL",
+        ForeignKeyAction::Restrict => "RESTRICT",
+        ForeignKeyAction::NoAction => "NO ACTION",
+    }
+}
+
+// SQLite keeps comments in the definition, which is how they are read back
+fn comment_sql(comment: &str) -> String {
+    format!("/* {} */", comment.replace("*/", "* /").replace('\n', " "))
+}
+
+fn quoted_value(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn quoted_name(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn quoted_names(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| quoted_name(name))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::column::RelationType;
+
+    // A manager whose pool never connects. Good enough for SQL generation
+    fn offline_manager() -> SqliteSchemaManager {
+        let pool = SqlitePoolOptions::new()
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_lazy("sqlite::memory:")
+            .unwrap();
+        SqliteSchemaManager::new(Arc::new(PoolSet::new(pool)))
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn posts_table() -> BaseTable {
+        let mut table = BaseTable::new("posts");
+        table.id(None);
+        table.set_comment("Blog posts");
+        table.string("title").set_is_unique(true);
+        table
+            .enumeration("status", &["draft", "it's"])
+            .set_default("draft")
+            .set_comment("Status");
+        table.json("meta").default_is_empty_object();
+        table
+            .string("sku")
+            .set_is_nullable(true)
+            .set_virtual_as("json_extract(meta, '$.sku')");
+        table.decimal("price", 8, 2).set_check("price >= 0");
+        table.boolean("published").default_is_zero();
+        table.index("posts_status_title", &["status", "title"]);
+        table.timestamps();
+        table
+    }
+
+    #[test]
+    fn create_table_renders_sqlite_definitions() {
+        let statements = offline_manager().create_statements(&posts_table());
+        assert_eq!(
+            statements[0],
+            "CREATE TABLE \"posts\" ( /* Blog posts */
+\"id\" integer PRIMARY KEY AUTOINCREMENT NOT NULL,
+\"title\" varchar(255) NOT NULL UNIQUE,
+\"status\" text NOT NULL DEFAULT 'draft' CHECK (\"status\" IN ('draft','it''s')) /* Status */,
+\"meta\" json NOT NULL DEFAULT '{}',
+\"sku\" varchar(255) GENERATED ALWAYS AS (json_extract(meta, '$.sku')) VIRTUAL NULL,
+\"price\" decimal(8,2) NOT NULL,
+\"published\" boolean NOT NULL DEFAULT 0,
+\"created_at\" datetime NOT NULL DEFAULT CURRENT_TIMESTAMP,
+\"updated_at\" datetime NOT NULL DEFAULT CURRENT_TIMESTAMP,
+CONSTRAINT \"posts_price_check\" CHECK (price >= 0)
+);"
+        );
+        assert_eq!(
+            statements[1],
+            "CREATE INDEX \"posts_status_title\" ON \"posts\" (\"status\", \"title\");"
+        );
+        assert!(statements[2].starts_with(
+            "CREATE TRIGGER \"posts_updated_at_on_update\" AFTER UPDATE ON \"posts\""
+        ));
+    }
+
+    #[test]
+    fn column_types_render_sqlite_types() {
+        let mut table = BaseTable::new("things");
+        table.float("a");
+        table.decimal("b", 8, 2).set_is_unsigned(true);
+        table.file("c", RelationType::Single);
+        table.select("d", RelationType::Multiple(0));
+        table.uuid("e").default_is_uuid();
+        table.text("f");
+        table.small_integer("g").set_is_unsigned(true);
+        table.binary("h", 16);
+        table.ulid("i").default_is_ulid();
+        table.integer("j").default_is_zero();
+        table.number("k");
+        table.tiny_integer("l");
+        table.medium_integer("m");
+        table.date("n");
+        table.time("o");
+        table.blob("p");
+
+        let manager = offline_manager();
+        let definitions: Vec<String> = table
+            .columns()
+            .iter()
+            .map(|column| manager.column_definition(column, false, false))
+            .collect();
+
+        assert_eq!(
+            definitions,
+            vec![
+                "\"a\" float NOT NULL".to_owned(),
+                "\"b\" unsigned decimal(8,2) NOT NULL".to_owned(),
+                "\"c\" char(26) NOT NULL".to_owned(),
+                "\"d\" json NOT NULL".to_owned(),
+                format!("\"e\" char(36) NOT NULL DEFAULT {}", UUID_DEFAULT),
+                "\"f\" text NOT NULL".to_owned(),
+                "\"g\" unsigned smallint NOT NULL".to_owned(),
+                "\"h\" varbinary(16) NOT NULL".to_owned(),
+                "\"i\" char(26) NOT NULL".to_owned(),
+                "\"j\" bigint NOT NULL DEFAULT 0".to_owned(),
+                "\"k\" double NOT NULL".to_owned(),
+                "\"l\" tinyint NOT NULL".to_owned(),
+                "\"m\" mediumint NOT NULL".to_owned(),
+                "\"n\" date NOT NULL".to_owned(),
+                "\"o\" time NOT NULL".to_owned(),
+                "\"p\" blob NOT NULL".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn altering_rebuilds_the_table() {
+        let mut live = posts_table();
+        live.set_is_new(false);
+        let mut table = live.clone();
+        table.drop_column("published");
+        table.string("title").set_is_unique(true).rename("headline");
+        table.integer("views").default_is_zero().set_after("title");
+
+        let statements = offline_manager().table_statements(&table, Some(&live));
+        assert!(statements[0].starts_with("CREATE TABLE \"_dty_new_posts\" ("));
+        assert!(statements[0].contains("\"headline\" varchar(255) NOT NULL UNIQUE,\n\"views\""));
+        assert_eq!(
+            &statements[1..4],
+            &[
+                "INSERT INTO \"_dty_new_posts\" (\"id\", \"headline\", \"status\", \"meta\", \"price\", \"created_at\", \"updated_at\") \
+SELECT \"id\", \"title\", \"status\", \"meta\", \"price\", \"created_at\", \"updated_at\" FROM \"posts\";",
+                "DROP TABLE \"posts\";",
+                "ALTER TABLE \"_dty_new_posts\" RENAME TO \"posts\";",
+            ]
+        );
+        assert_eq!(
+            statements[4],
+            "CREATE INDEX \"posts_status_title\" ON \"posts\" (\"status\", \"headline\");"
+        );
+    }
+
+    #[test]
+    fn tables_round_trip_through_the_database() {
+        block_on(async {
+            let manager = SqliteSchemaManager::in_memory().await.unwrap();
+            let table = posts_table();
+            manager.commit(table.clone()).await.unwrap();
+
+            assert!(manager.has_table("posts").await);
+            assert_eq!(manager.table_names().await.unwrap(), vec!["posts"]);
+
+            let live = manager.fetch_table("posts").await.unwrap().unwrap();
+            let diff = SchemaDiff::between(&table, &live);
+            assert!(diff.is_empty(), "{:#?}", diff.changes);
+        });
+    }
+
+    #[test]
+    fn rebuilding_keeps_the_rows_and_their_children() {
+        block_on(async {
+            let manager = SqliteSchemaManager::in_memory().await.unwrap();
+            let mut users = BaseTable::new("users");
+            users.id(None);
+            users.string("name");
+            manager.commit(users.clone()).await.unwrap();
+
+            let mut posts = BaseTable::new("posts");
+            posts.id(None);
+            posts
+                .integer("user_id")
+                .references_with_cascade_delete("users", "id");
+            manager.commit(posts).await.unwrap();
+
+            let mut user = ColumnAndValue::new();
+            user.insert("name".to_owned(), Value::from("Ada"));
+            manager.insert("users", user).await.unwrap();
+            let mut post = ColumnAndValue::new();
+            post.insert("user_id".to_owned(), Value::from(1));
+            manager.insert("posts", post).await.unwrap();
+
+            let mut users = manager.fetch_table_for_update("users").await.unwrap();
+            users.string("email").set_is_nullable(true);
+            manager.commit(users).await.unwrap();
+
+            let live = manager.fetch_table("users").await.unwrap().unwrap();
+            assert!(live.find_column("email").is_some());
+
+            let mut manager = manager;
+            manager.query(QueryBuilder::new(vec!["users".to_owned()]));
+            assert_eq!(
+                manager.fetch_all_as_json().await,
+                vec![serde_json::json!({"id": 1, "name": "Ada", "email": null})]
+            );
+            manager.query(QueryBuilder::new(vec!["posts".to_owned()]));
+            assert_eq!(manager.fetch_all_as_json().await.len(), 1);
+        });
+    }
+
+    #[test]
+    fn rows_are_written_and_read_as_json() {
+        block_on(async {
+            let mut manager = SqliteSchemaManager::in_memory().await.unwrap();
+            manager.commit(posts_table()).await.unwrap();
+
+            let mut record = ColumnAndValue::new();
+            record.insert("title".to_owned(), Value::from("Hello"));
+            record.insert("meta".to_owned(), Value::from("{\"sku\":\"A1\"}"));
+            record.insert("price".to_owned(), Value::from(2.5_f32));
+            record.insert("published".to_owned(), Value::from(true));
+            manager.insert("posts", record).await.unwrap();
+
+            manager.begin_transaction().await.unwrap();
+            let mut query = QueryBuilder::new(vec!["posts".to_owned()]);
+            query.eq("title", "Hello");
+            assert_eq!(manager.delete(query).await.unwrap(), 1);
+            manager.rollback_transaction().await.unwrap();
+
+            let mut query = QueryBuilder::new(vec!["posts".to_owned()]);
+            query.select_multiple(&["title", "status", "sku", "price", "published"]);
+            manager.query(query);
+            assert_eq!(
+                manager.fetch_all_as_json().await,
+                vec![serde_json::json!({
+                    "title": "Hello",
+                    "status": "draft",
+                    "sku": "A1",
+                    "price": 2.5,
+                    "published": true,
+                })]
+            );
+        });
+    }
+
+    #[test]
+    fn blobs_are_read_as_base64() {
+        block_on(async {
+            let mut manager = SqliteSchemaManager::in_memory().await.unwrap();
+            let mut files = BaseTable::new("files");
+            files.blob("data").set_is_nullable(true);
+            files.binary("digest", 16).set_is_nullable(true);
+            manager.commit(files).await.unwrap();
+
+            manager
+                .execute(
+                    "INSERT INTO \"files\" (\"data\", \"digest\") VALUES (X'00FF10', NULL)",
+                    &[],
+                )
+                .await
+                .unwrap();
+
+            manager.query(QueryBuilder::new(vec!["files".to_owned()]));
+            assert_eq!(
+                manager.try_fetch_all_as_json().await.unwrap(),
+                vec![serde_json::json!({"data": "AP8Q", "digest": null})]
+            );
+        });
+    }
+
+    #[test]
+    fn failed_queries_are_errors() {
+        block_on(async {
+            let mut manager = SqliteSchemaManager::in_memory().await.unwrap();
+            manager.query(QueryBuilder::new(vec!["missing".to_owned()]));
+            assert!(manager.try_fetch_all_as_json().await.is_err());
+            assert!(manager.fetch_table_for_update("missing").await.is_ok());
+        });
+    }
+
+    #[test]
+    fn collections_do_not_take_over_other_tables() {
+        use crate::base::{
+            collection::{setup_collection_tables, Collection},
+            column::{BaseColumn, ColumnType},
+            manager::{Manager, SCHEMA_LOCK},
+        };
+
+        block_on(async {
+            let schema = SqliteSchemaManager::in_memory().await.unwrap();
+            schema.commit(posts_table()).await.unwrap();
+            let pools = schema.db_pools.clone();
+            let manager = Manager::new(Box::new(schema));
+            setup_collection_tables(&manager).await.unwrap();
+
+            let mut posts = Collection::new("posts");
+            posts.field(BaseColumn::new("title", ColumnType::String(255)));
+            assert!(manager.save_collection(&posts, true).await.is_err());
+            assert!(manager.has_table("posts").await);
+
+            let mut articles = Collection::new("articles");
+            articles.field(BaseColumn::new("title", ColumnType::String(255)));
+            manager.save_collection(&articles, false).await.unwrap();
+            manager.save_collection(&articles, false).await.unwrap();
+
+            // Another instance is changing the schema
+            let other = Manager::new(Box::new(SqliteSchemaManager::new(pools)))
+                .with_lock_timeout(Duration::from_millis(100));
+            manager
+                .lock(SCHEMA_LOCK, Duration::from_secs(1))
+                .await
+                .unwrap();
+            assert!(other.save_collection(&articles, false).await.is_err());
+            manager.unlock(SCHEMA_LOCK).await.unwrap();
+            other.save_collection(&articles, false).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn records_are_validated_against_the_registered_definition() {
+        use crate::base::{
+            collection::{setup_collection_tables, Collection, SchemaRegistry},
+            column::{BaseColumn, ColumnType},
+            manager::Manager,
+            validation::{ValidationErrors, ValidationRule},
+        };
+        use std::sync::RwLock;
+
+        block_on(async {
+            let registry = Arc::new(RwLock::new(SchemaRegistry::new()));
+            let schema = SqliteSchemaManager::in_memory().await.unwrap();
+            let manager = Manager::new(Box::new(schema)).with_registry(registry.clone());
+            setup_collection_tables(&manager).await.unwrap();
+
+            let mut articles = Collection::new("articles");
+            articles
+                .field(BaseColumn::new("title", ColumnType::String(255)))
+                .add_rule(ValidationRule::MaxLength(5));
+            manager.save_collection(&articles, false).await.unwrap();
+            registry.write().unwrap().register(articles);
+
+            let error = manager
+                .insert("articles")
+                .set("title", "too long".to_owned())
+                .save()
+                .await
+                .unwrap_err();
+            assert!(error.downcast_ref::<ValidationErrors>().is_some());
+
+            manager
+                .insert("articles")
+                .set("title", "short".to_owned())
+                .save()
+                .await
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn live_definitions_are_cached_until_the_schema_changes() {
+        use crate::base::{collection::SchemaRegistry, manager::Manager};
+        use std::sync::RwLock;
+
+        block_on(async {
+            let registry = Arc::new(RwLock::new(SchemaRegistry::new()));
+            let schema = SqliteSchemaManager::in_memory().await.unwrap();
+            let manager = Manager::new(Box::new(schema)).with_registry(registry.clone());
+            let mut posts = BaseTable::new("posts");
+            posts.id_set();
+            manager.converge_table(posts, false).await.unwrap();
+
+            manager.insert("posts").save().await.unwrap();
+            assert!(registry.read().unwrap().live_table("posts").is_some());
+
+            let mut posts = BaseTable::new("posts");
+            posts.id_set();
+            posts.timestamps();
+            manager.converge_table(posts, false).await.unwrap();
+            assert!(registry.read().unwrap().live_table("posts").is_none());
+
+            manager.insert("posts").save().await.unwrap();
+            let live = registry.read().unwrap().live_table("posts").cloned();
+            assert!(live
+                .unwrap()
+                .columns()
+                .iter()
+                .any(|c| c.name == "created_at"));
+            let rows = manager
+                .fetch(QueryBuilder::new(vec!["posts".to_owned()]))
+                .await
+                .unwrap();
+            assert!(rows.iter().any(|row| row["created_at"].is_string()));
+        });
+    }
+
+    #[test]
+    fn relations_are_attached_and_synced_once() {
+        use crate::base::{column::RelationType, manager::Manager};
+
+        block_on(async {
+            let manager = Manager::new(Box::new(SqliteSchemaManager::in_memory().await.unwrap()));
+            let mut roles = BaseTable::new("roles");
+            roles.id_set();
+            manager.converge_table(roles, false).await.unwrap();
+            let mut users = BaseTable::new("users");
+            users.id_set();
+            users.relation("roles", RelationType::Multiple(2), "roles");
+            let pivot = users.pivot_tables().remove(0);
+            manager.converge_table(users, false).await.unwrap();
+
+            for table in ["users", "roles", "roles"] {
+                manager.insert(table).save().await.unwrap();
+            }
+            let ids = |rows: Vec<serde_json::Value>| -> Vec<String> {
+                rows.iter()
+                    .map(|row| row["id"].as_str().unwrap().to_owned())
+                    .collect()
+            };
+            let user = ids(manager
+                .fetch(QueryBuilder::new(vec!["users".to_owned()]))
+                .await
+                .unwrap());
+            let roles = ids(manager
+                .fetch(QueryBuilder::new(vec!["roles".to_owned()]))
+                .await
+                .unwrap());
+            let (user, a, b) = (user[0].as_str(), roles[0].as_str(), roles[1].as_str());
+
+            manager.attach(&pivot, user, &[a, a]).await.unwrap();
+            manager.attach(&pivot, user, &[a, b]).await.unwrap();
+            assert!(manager.attach(&pivot, user, &["other"]).await.is_err());
+            manager.sync(&pivot, user, &[b]).await.unwrap();
+
+            let mut linked = QueryBuilder::new(vec![pivot.name().to_owned()]);
+            linked.select("related_id");
+            assert_eq!(
+                manager.fetch(linked).await.unwrap(),
+                vec![serde_json::json!({"related_id": b})]
+            );
+        });
+    }
+
+    #[test]
+    fn concurrent_attaches_stay_within_the_limit() {
+        use crate::base::{column::RelationType, manager::Manager};
+
+        block_on(async {
+            let path = std::env::temp_dir().join(format!("dty_attach_{}.db", std::process::id()));
+            let url = format!("sqlite://{}?mode=rwc", path.display());
+            let manager = || async {
+                let pool = SqlitePoolOptions::new()
+                    .max_connections(1)
+                    .connect(&url)
+                    .await
+                    .unwrap();
+                Manager::new(Box::new(SqliteSchemaManager::new(Arc::new(PoolSet::new(
+                    pool,
+                )))))
+            };
+            let (first, second) = (manager().await, manager().await);
+
+            let mut roles = BaseTable::new("roles");
+            roles.id_set();
+            first.converge_table(roles, false).await.unwrap();
+            let mut users = BaseTable::new("users");
+            users.id_set();
+            users.relation("roles", RelationType::Multiple(1), "roles");
+            let pivot = users.pivot_tables().remove(0);
+            first.converge_table(users, false).await.unwrap();
+            for table in ["users", "roles", "roles"] {
+                first.insert(table).save().await.unwrap();
+            }
+            let id = |row: &serde_json::Value| row["id"].as_str().unwrap().to_owned();
+            let user = id(&first
+                .fetch(QueryBuilder::new(vec!["users".to_owned()]))
+                .await
+                .unwrap()[0]);
+            let roles: Vec<String> = first
+                .fetch(QueryBuilder::new(vec!["roles".to_owned()]))
+                .await
+                .unwrap()
+                .iter()
+                .map(id)
+                .collect();
+
+            let (role_a, role_b) = ([roles[0].as_str()], [roles[1].as_str()]);
+            let (a, b) = futures::join!(
+                first.attach(&pivot, &user, &role_a),
+                second.attach(&pivot, &user, &role_b)
+            );
+            let linked = first
+                .fetch(QueryBuilder::new(vec![pivot.name().to_owned()]))
+                .await
+                .unwrap();
+            drop((first, second));
+            std::fs::remove_file(&path).unwrap();
+
+            let error = a.err().or(b.err()).expect("one attach is over the limit");
+            assert!(error.to_string().contains("limit"), "{}", error);
+            assert_eq!(linked.len(), 1);
+        });
+    }
+
+    #[test]
+    fn transaction_writes_invalidate_the_cache_when_it_ends() {
+        block_on(async {
+            let cache = Arc::new(QueryCache::new(10));
+            let mut manager = SqliteSchemaManager::in_memory().await.unwrap();
+            let mut tags = BaseTable::new("tags");
+            tags.string("name");
+            manager.commit(tags).await.unwrap();
+            manager.set_query_cache(cache.clone());
+
+            let tag = |name: &str| {
+                let mut record = ColumnAndValue::new();
+                record.insert("name".to_owned(), Value::from(name));
+                record
+            };
+            let ttl = Duration::from_secs(60);
+
+            manager.begin_transaction().await.unwrap();
+            manager.insert("tags", tag("a")).await.unwrap();
+            // Cached by another connection before the commit
+            cache.put(
+                "tags",
+                vec!["tags".to_owned()],
+                Vec::new(),
+                ttl,
+                cache.generation(),
+            );
+            assert!(cache.get("tags").is_some());
+            manager.commit_transaction().await.unwrap();
+            assert!(cache.get("tags").is_none());
+
+            manager.begin_transaction().await.unwrap();
+            manager.insert("tags", tag("b")).await.unwrap();
+            cache.put(
+                "tags",
+                vec!["tags".to_owned()],
+                Vec::new(),
+                ttl,
+                cache.generation(),
+            );
+            manager.rollback_transaction().await.unwrap();
+            assert!(cache.get("tags").is_none());
+        });
+    }
+
+    #[test]
+    fn locks_are_shared_by_the_pool_set() {
+        block_on(async {
+            let manager = SqliteSchemaManager::in_memory().await.unwrap();
+            let other = SqliteSchemaManager::new(manager.db_pools.clone());
+
+            manager
+                .acquire_lock("schema", Duration::from_secs(1))
+                .await
+                .unwrap();
+            assert!(other
+                .acquire_lock("schema", Duration::from_millis(100))
+                .await
+                .is_err());
+
+            drop(manager);
+            other
+                .acquire_lock("schema", Duration::from_millis(100))
+                .await
+                .unwrap();
+            other.release_lock("schema").await.unwrap();
+            assert!(other.release_lock("schema").await.is_err());
+        });
+    }
+
+    #[test]
+    fn waiting_for_a_lock_does_not_block_releasing_others() {
+        block_on(async {
+            let manager = SqliteSchemaManager::in_memory().await.unwrap();
+            let other = SqliteSchemaManager::new(manager.db_pools.clone());
+            let timeout = Duration::from_secs(2);
+            manager.acquire_lock("a", timeout).await.unwrap();
+            other.acquire_lock("b", timeout).await.unwrap();
+
+            let handover = async {
+                manager.release_lock("a").await.unwrap();
+                other.acquire_lock("a", timeout).await.unwrap();
+                other.release_lock("b").await.unwrap();
+            };
+            let (waited, _) = futures::join!(manager.acquire_lock("b", timeout), handover);
+            waited.unwrap();
+        });
+    }
+
+    #[test]
+    fn migrations_that_failed_part_way_block_the_next_run() {
+        use crate::base::{
+            manager::Manager,
+            migration::{
+                setup_migration_table, Migration, MigrationRegistry, MigrationRunner,
+                MIGRATION_TABLE,
+            },
+        };
+
+        struct CreateTags;
+
+        #[async_trait::async_trait(?Send)]
+        impl Migration for CreateTags {
+            fn name(&self) -> &str {
+                "create_tags"
+            }
+
+            async fn up(&self, manager: &Manager) -> anyhow::Result<()> {
+                manager.create("tags", |table| table.id_set()).await
+            }
+
+            async fn down(&self, manager: &Manager) -> anyhow::Result<()> {
+                manager.drop_table("tags").await
+            }
+        }
+
+        block_on(async {
+            let mut manager =
+                Manager::new(Box::new(SqliteSchemaManager::in_memory().await.unwrap()));
+            setup_migration_table(&manager).await.unwrap();
+            let mut registry = MigrationRegistry::new();
+            registry.register(Box::new(CreateTags)).unwrap();
+
+            let applied = MigrationRunner::new(&mut manager, &registry)
+                .migrate()
+                .await
+                .unwrap();
+            assert_eq!(applied, vec!["create_tags"]);
+            let status = MigrationRunner::new(&mut manager, &registry)
+                .status()
+                .await
+                .unwrap();
+            assert!(!status[0].is_incomplete);
+
+            manager
+                .insert(MIGRATION_TABLE)
+                .set("name", "add_posts".to_owned())
+                .set("batch", "2".to_owned())
+                .set("is_pending", "1".to_owned())
+                .save()
+                .await
+                .unwrap();
+            let error = MigrationRunner::new(&mut manager, &registry)
+                .migrate()
+                .await
+                .unwrap_err();
+            assert!(error.to_string().contains("`add_posts` failed part way"));
+        });
+    }
+}